@@ -0,0 +1,69 @@
+//! The Fixed ACPI Description Table, which describes fixed hardware features of the machine
+
+use core::mem;
+use super::{AcpiTables, SdtHeader};
+
+pub const SIGNATURE: &[u8; 4] = b"FACP";
+
+/// Set in the IA-PC boot architecture flags if the machine has an 8042 (PS/2) controller
+const BOOT_ARCH_8042: u16 = 1 << 1;
+
+/// The FADT, up to the IA-PC boot architecture flags. The rest of the table isn't used.
+#[allow(dead_code)] // Dead fields for completeness
+#[repr(C, packed)]
+pub struct Fadt {
+    header: SdtHeader,
+    firmware_control: u32,
+    dsdt: u32,
+    reserved: u8,
+    preferred_pm_profile: u8,
+    sci_interrupt: u16,
+    smi_command_port: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4bios_request: u8,
+    pstate_control: u8,
+    pm1a_event_block: u32,
+    pm1b_event_block: u32,
+    pm1a_control_block: u32,
+    pm1b_control_block: u32,
+    pm2_control_block: u32,
+    pm_timer_block: u32,
+    gpe0_block: u32,
+    gpe1_block: u32,
+    pm1_event_length: u8,
+    pm1_control_length: u8,
+    pm2_control_length: u8,
+    pm_timer_length: u8,
+    gpe0_length: u8,
+    gpe1_length: u8,
+    gpe1_base: u8,
+    cstate_control: u8,
+    worst_c2_latency: u16,
+    worst_c3_latency: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alarm: u8,
+    month_alarm: u8,
+    century: u8,
+    boot_architecture_flags: u16,
+}
+
+impl Fadt {
+    /// Finds the FADT in the given tables
+    pub fn find(tables: &AcpiTables) -> Option<&'static Fadt> {
+        tables.find(SIGNATURE).map(|header| unsafe { &*(header as *const SdtHeader as *const Fadt) })
+    }
+
+    /// Returns `true` if the machine has an 8042 (PS/2) controller. The flag only exists from
+    /// ACPI 2.0 onwards, so with an older FADT the controller is assumed to be there.
+    pub fn has_8042(&self) -> bool {
+        if self.header.revision < 2 || (self.header.length as usize) < mem::size_of::<Fadt>() {
+            return true;
+        }
+
+        self.boot_architecture_flags & BOOT_ARCH_8042 != 0
+    }
+}
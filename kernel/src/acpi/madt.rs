@@ -0,0 +1,183 @@
+//! The Multiple APIC Description Table, which describes the interrupt controllers of the machine
+
+use core::{mem, ptr};
+use super::{AcpiTables, SdtHeader};
+
+pub const SIGNATURE: &[u8; 4] = b"APIC";
+
+/// The MADT, followed by a variable amount of entries
+#[repr(C, packed)]
+pub struct Madt {
+    header: SdtHeader,
+    local_apic_address: u32,
+    flags: u32,
+}
+
+impl Madt {
+    /// Finds the MADT in the given tables
+    pub fn find(tables: &AcpiTables) -> Option<&'static Madt> {
+        tables.find(SIGNATURE).map(|header| unsafe { &*(header as *const SdtHeader as *const Madt) })
+    }
+
+    /// Gets the physical address of the local APICs, taking address overrides into account
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .filter_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .next()
+            .unwrap_or(self.local_apic_address as u64)
+    }
+
+    /// Returns `true` if the machine also has dual 8259 PICs, which must be disabled when using
+    /// the APICs
+    #[allow(dead_code)] // Part of API
+    pub fn has_legacy_pics(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    /// Gets an iterator over the entries of this table
+    pub fn entries(&self) -> MadtEntries {
+        let start = self as *const Madt as usize + mem::size_of::<Madt>();
+        let end = self as *const Madt as usize + self.header.length as usize;
+
+        MadtEntries { address: start, end }
+    }
+
+    /// Gets the interrupt source override for the given ISA IRQ, if any
+    pub fn isa_override(&self, irq: u8) -> Option<InterruptSourceOverride> {
+        self.entries()
+            .filter_map(|entry| match entry {
+                MadtEntry::InterruptSourceOverride(source_override) => Some(source_override),
+                _ => None,
+            })
+            .find(|source_override| source_override.bus == 0 && source_override.source == irq)
+    }
+}
+
+/// An entry in the [Madt]
+#[allow(dead_code)] // Dead variants for completeness
+#[derive(Copy, Clone, Debug)]
+pub enum MadtEntry {
+    /// A processor and its local APIC
+    LocalApic { processor_id: u8, apic_id: u8, flags: u32 },
+    /// An I/O APIC, handling the global system interrupts from `gsi_base` onwards
+    IoApic { id: u8, address: u32, gsi_base: u32 },
+    /// Describes how an ISA IRQ is connected to a global system interrupt, if not identity mapped
+    InterruptSourceOverride(InterruptSourceOverride),
+    /// Describes which local APIC interrupt input the NMI is connected to
+    LocalApicNmi { processor_id: u8, flags: u16, lint: u8 },
+    /// Overrides the 32 bit local APIC address in the MADT header
+    LocalApicAddressOverride { address: u64 },
+    /// An entry of a type not handled by flower
+    Unknown(u8),
+}
+
+/// Describes how an ISA IRQ is connected to a global system interrupt
+#[derive(Copy, Clone, Debug)]
+pub struct InterruptSourceOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+impl InterruptSourceOverride {
+    /// The polarity of the interrupt signal
+    pub fn polarity(&self) -> Polarity {
+        match self.flags & 0b11 {
+            0b01 => Polarity::ActiveHigh,
+            0b11 => Polarity::ActiveLow,
+            _ => Polarity::Conforming,
+        }
+    }
+
+    /// The trigger mode of the interrupt signal
+    pub fn trigger_mode(&self) -> TriggerMode {
+        match (self.flags >> 2) & 0b11 {
+            0b01 => TriggerMode::Edge,
+            0b11 => TriggerMode::Level,
+            _ => TriggerMode::Conforming,
+        }
+    }
+}
+
+/// The polarity of an interrupt signal. `Conforming` means the bus' default is used
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Polarity {
+    Conforming,
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// The trigger mode of an interrupt signal. `Conforming` means the bus' default is used
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TriggerMode {
+    Conforming,
+    Edge,
+    Level,
+}
+
+/// Iterates over the entries of a [Madt]
+pub struct MadtEntries {
+    address: usize,
+    end: usize,
+}
+
+impl Iterator for MadtEntries {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<MadtEntry> {
+        // Each entry begins with its type and length
+        if self.address + 2 > self.end {
+            return None;
+        }
+
+        let entry_type = self.read::<u8>(0);
+        let length = self.read::<u8>(1) as usize;
+
+        // A zero length would never advance, so the table must be corrupt
+        if length < 2 || self.address + length > self.end {
+            return None;
+        }
+
+        let entry = match entry_type {
+            0 => MadtEntry::LocalApic {
+                processor_id: self.read(2),
+                apic_id: self.read(3),
+                flags: self.read(4),
+            },
+            1 => MadtEntry::IoApic {
+                id: self.read(2),
+                address: self.read(4),
+                gsi_base: self.read(8),
+            },
+            2 => MadtEntry::InterruptSourceOverride(InterruptSourceOverride {
+                bus: self.read(2),
+                source: self.read(3),
+                gsi: self.read(4),
+                flags: self.read(8),
+            }),
+            4 => MadtEntry::LocalApicNmi {
+                processor_id: self.read(2),
+                flags: self.read(3),
+                lint: self.read(5),
+            },
+            5 => MadtEntry::LocalApicAddressOverride { address: self.read(4) },
+            unknown => MadtEntry::Unknown(unknown),
+        };
+
+        self.address += length;
+
+        Some(entry)
+    }
+}
+
+impl MadtEntries {
+    /// Reads a value at the given offset into the current entry. Entries are not necessarily
+    /// aligned.
+    fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { ptr::read_unaligned((self.address + offset) as *const T) }
+    }
+}
@@ -0,0 +1,181 @@
+//! # ACPI
+//!
+//! Discovers the ACPI tables through the RSDP and gives access to them. The RSDP is taken from the
+//! multiboot information if the bootloader provided it, and otherwise found by searching the BIOS
+//! memory areas. Only the tables which flower uses are parsed:
+//!  - [madt::Madt] - describes the interrupt controllers of the machine
+//!  - [fadt::Fadt] - describes fixed hardware features, such as whether there is a PS/2 controller
+//!
+//! The tables must be found through `init` before they can be accessed through `tables`.
+
+pub mod madt;
+pub mod fadt;
+
+use core::{mem, ptr, slice};
+use memory::IDENTITY_MAPPED_END;
+use spin::Once;
+
+/// The address at which the real mode segment of the EBDA is stored
+const EBDA_SEGMENT_POINTER: usize = 0x40E;
+/// The main BIOS area which may contain the RSDP
+const BIOS_AREA: (usize, usize) = (0xE0000, 0x100000);
+
+static TABLES: Once<AcpiTables> = Once::new();
+
+/// An error which occurred while finding or parsing the ACPI tables
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AcpiError {
+    /// The RSDP could not be found, so the machine probably doesn't support ACPI
+    NoRsdp,
+    /// The table with the given signature has an invalid checksum
+    InvalidChecksum([u8; 4]),
+    /// The table with the given signature claims to be shorter than its own header
+    InvalidLength([u8; 4]),
+    /// The table at the given physical address is not mapped
+    Unmapped(u64),
+}
+
+/// The root system description pointer, through which all other tables are found
+#[allow(dead_code)] // Dead fields for completeness
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+}
+
+/// The extended RSDP, present from ACPI 2.0 onwards
+#[allow(dead_code)] // Dead fields for completeness
+#[repr(C, packed)]
+struct Rsdp2 {
+    rsdp: Rsdp,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// The header common to all system description tables
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// The ACPI tables of the machine, found through the RSDT or XSDT
+pub struct AcpiTables {
+    root: &'static SdtHeader,
+    /// The size of the pointers in the root table. 4 for the RSDT, 8 for the XSDT
+    entry_size: usize,
+}
+
+impl AcpiTables {
+    /// Finds the table with the given signature, if present and valid
+    pub fn find(&self, signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+        let root_address = self.root as *const SdtHeader as usize;
+        // The root table was validated by `table_at`, so it is at least as long as its header
+        let count = (self.root.length as usize - mem::size_of::<SdtHeader>()) / self.entry_size;
+
+        (0..count)
+            .map(|index| {
+                let entry = root_address + mem::size_of::<SdtHeader>() + index * self.entry_size;
+
+                // The entries are not necessarily aligned
+                unsafe {
+                    if self.entry_size == 8 {
+                        ptr::read_unaligned(entry as *const u64)
+                    } else {
+                        ptr::read_unaligned(entry as *const u32) as u64
+                    }
+                }
+            })
+            .filter_map(|address| unsafe { table_at(address).ok() })
+            .find(|table| &table.signature == signature)
+    }
+}
+
+/// Finds the ACPI tables through the given copy of the RSDP, such as the one in the multiboot
+/// information, or by searching for the RSDP in the BIOS memory areas if there is none
+///
+/// # Safety
+///
+/// The given address must point to an RSDP
+pub unsafe fn init(rsdp_address: Option<usize>) -> Result<&'static AcpiTables, AcpiError> {
+    let rsdp_address = match rsdp_address {
+        Some(address) if checksum(address, mem::size_of::<Rsdp>()) => address,
+        Some(_) => return Err(AcpiError::InvalidChecksum(*b"RSD ")),
+        None => find_rsdp().ok_or(AcpiError::NoRsdp)?,
+    };
+
+    let rsdp = &*(rsdp_address as *const Rsdp);
+
+    let tables = if rsdp.revision >= 2 {
+        let rsdp = &*(rsdp_address as *const Rsdp2);
+        AcpiTables { root: table_at(rsdp.xsdt_address)?, entry_size: 8 }
+    } else {
+        AcpiTables { root: table_at(rsdp.rsdt_address as u64)?, entry_size: 4 }
+    };
+
+    debug!("acpi: found tables, revision {}", rsdp.revision);
+
+    Ok(TABLES.call_once(|| tables))
+}
+
+/// Gets the ACPI tables, if they have been found
+pub fn tables() -> Option<&'static AcpiTables> {
+    TABLES.try()
+}
+
+/// Gets and validates the table at the given physical address
+unsafe fn table_at(address: u64) -> Result<&'static SdtHeader, AcpiError> {
+    if address + mem::size_of::<SdtHeader>() as u64 > IDENTITY_MAPPED_END {
+        return Err(AcpiError::Unmapped(address));
+    }
+
+    let table = &*(address as usize as *const SdtHeader);
+
+    if (table.length as usize) < mem::size_of::<SdtHeader>() {
+        return Err(AcpiError::InvalidLength(table.signature));
+    }
+
+    if address + table.length as u64 > IDENTITY_MAPPED_END {
+        return Err(AcpiError::Unmapped(address));
+    }
+
+    if !checksum(address as usize, table.length as usize) {
+        return Err(AcpiError::InvalidChecksum(table.signature));
+    }
+
+    Ok(table)
+}
+
+/// Searches the first KiB of the EBDA and then the main BIOS area for the RSDP
+fn find_rsdp() -> Option<usize> {
+    let ebda = unsafe { ptr::read(EBDA_SEGMENT_POINTER as *const u16) as usize } << 4;
+
+    let in_ebda = if ebda != 0 { search_rsdp(ebda, ebda + 1024) } else { None };
+    in_ebda.or_else(|| search_rsdp(BIOS_AREA.0, BIOS_AREA.1))
+}
+
+/// Searches the given area for the RSDP, which is always on a 16 byte boundary
+fn search_rsdp(start: usize, end: usize) -> Option<usize> {
+    (0..(end - start) / 16).map(|index| start + index * 16).find(|&address| {
+        let rsdp = unsafe { &*(address as *const Rsdp) };
+        &rsdp.signature == b"RSD PTR " && checksum(address, mem::size_of::<Rsdp>())
+    })
+}
+
+/// Returns `true` if the bytes in the given area sum to zero, as every ACPI structure's should
+fn checksum(address: usize, length: usize) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(address as *const u8, length) };
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
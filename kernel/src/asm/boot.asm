@@ -7,6 +7,7 @@
 
 extern kmain
 global start
+global stack_guard
 
 section .text
 bits 32
@@ -15,6 +16,10 @@ start:
     ; Disable interrupts
     cli
 
+    ; Save the multiboot information pointer in the first argument register for kmain, as ebx is
+    ; used by the checks below
+    mov edi, ebx
+
     ; Checks
     call check_multiboot ; Check if booted correctly
     call check_cpuid  ; Check if cpuid supported
@@ -92,6 +97,7 @@ error_print:
 
 ; Set up paging
 ; Thanks to https://intermezzos.github.io/book/paging.html
+; Identity maps the first 4 GiB, so that memory mapped devices such as the APICs are reachable
 setup_paging:
 
     ; Point entry #1 of page 4 to entry #1 of page 3
@@ -99,22 +105,38 @@ setup_paging:
     or eax, 0b11
     mov [p4_table + 0], eax ; set 1st entry of p4 table to 1st entry of p3 table
     
-    ; Point entry #1 of page 3 to entry #1 of page 2
-    mov eax, p2_table + 0 ; set eax to 1st entry of p3 table
-    or eax, 0b11
-    mov [p3_table + 0], eax ; set 1st entry of p4 table to 1st entry of p3 table
+    ; Point entries #1-4 of page 3 to the four p2 tables
+    mov ecx, 0
+    .map_p3_table_loop:
+
+        mov eax, 4096 ; size of a p2 table
+        mul ecx ; multiply by counter
+        add eax, p2_table ; get address of the counter'th p2 table
+        or eax, 0b11
+
+        mov [p3_table + ecx * 8], eax
+
+        inc ecx
+        cmp ecx, 4
+        jne .map_p3_table_loop
     
     mov ecx, 0
     .map_p2_table_loop:
         
-        mov eax, 0x200000 ; 2mib (page size) TODO 4kib page size?
+        mov eax, 0x200000 ; 2mib (page size), split into 4kib pages by memory::paging where needed
         mul ecx ; multiply by counter
         or eax, 0b10000011 ; first 1 is huge page bit
+
+        ; The last GiB is mostly memory mapped devices, so disable caching for it
+        cmp ecx, 512 * 3
+        jb .cached
+        or eax, 0b10000 ; cache disable bit
+    .cached:
         
         mov [p2_table + ecx * 8], eax
         
         inc ecx
-        cmp ecx, 512
+        cmp ecx, 512 * 4
         jne .map_p2_table_loop
     
     ; Set page table address to cr3
@@ -224,6 +246,11 @@ p4_table:
 p3_table:
     resb 4096
 p2_table:
+    resb 4096 * 4 ; one table per GiB mapped
+
+; Unmapped by memory::stack once paging is set up, so that overflowing the stack faults instead of
+; running into the page tables
+stack_guard:
     resb 4096
 
 ; Stack grows the other way
@@ -258,6 +285,13 @@ long_mode_start:
     
     ; Setup stack
     mov esp, stack_top
+
+    ; Clear the upper half of rdi (undefined after the switch to long mode), leaving the multiboot
+    ; information pointer
+    mov edi, edi
+
+    ; Terminate the frame pointer chain, so that backtraces know where the stack ends
+    xor ebp, ebp
     
     call kmain
 
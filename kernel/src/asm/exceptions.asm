@@ -0,0 +1,91 @@
+; Entry stubs for CPU exceptions
+; Each stub pushes a dummy error code if the CPU doesn't push one, and then the vector number, so
+; that every exception leaves the same layout on the stack. The common handler then saves the
+; general purpose registers and calls exception_dispatch with a pointer to all of it (see
+; interrupts::exceptions::ExceptionContext in Rust)
+
+extern exception_dispatch
+
+section .text
+bits 64
+
+; Args: vector number
+%macro exception_stub 1
+global exception_stub_%1
+exception_stub_%1:
+    push 0 ; dummy error code
+    push %1 ; vector number
+    jmp exception_common
+%endmacro
+
+; Args: vector number
+%macro exception_stub_with_error 1
+global exception_stub_%1
+exception_stub_%1:
+    push %1 ; vector number
+    jmp exception_common
+%endmacro
+
+exception_stub 0 ; divide by zero
+exception_stub 1 ; debug
+exception_stub 3 ; breakpoint
+exception_stub 4 ; overflow
+exception_stub 5 ; bound range exceeded
+exception_stub 6 ; invalid opcode
+exception_stub 7 ; device not available
+exception_stub_with_error 8 ; double fault
+exception_stub_with_error 10 ; invalid tss
+exception_stub_with_error 11 ; segment not present
+exception_stub_with_error 12 ; stack segment fault
+exception_stub_with_error 13 ; general protection fault
+exception_stub_with_error 14 ; page fault
+exception_stub 16 ; x87 floating point
+exception_stub_with_error 17 ; alignment check
+exception_stub 18 ; machine check
+exception_stub 19 ; simd floating point
+exception_stub 20 ; virtualization
+exception_stub_with_error 30 ; security exception
+
+exception_common:
+    ; Save the general purpose registers, in the reverse order of the Registers struct
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+
+    ; The CPU aligns the stack to 16 bytes before pushing its frame, and 22 quadwords have been
+    ; pushed since, so the stack is still aligned for the call
+    mov rdi, rsp ; pointer to the context
+    cld
+    call exception_dispatch
+
+    ; Restore the (possibly modified) registers
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+
+    add rsp, 16 ; pop the vector number and error code
+    iretq
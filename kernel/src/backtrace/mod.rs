@@ -0,0 +1,177 @@
+//! # Backtraces
+//!
+//! Walks the chain of frame pointers, which the target spec keeps (`eliminate-frame-pointer` is
+//! off), to find the return address of every frame on the stack. Each function pushes the caller's
+//! RBP on entry and points RBP at it, so `[rbp]` is the previous frame pointer and `[rbp + 8]` the
+//! return address.
+//!
+//! Return addresses are symbolized against the kernel's symbol table, which the bootloader loads
+//! and describes in the multiboot ELF sections tag.
+//!
+//! The stack may be corrupt when a backtrace is wanted most, so every frame pointer is checked to
+//! be mapped and to move up the stack before it is followed. The walk stops at the first one which
+//! isn't.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! println!("{}", Backtrace::current());
+//! ```
+
+mod symbols;
+
+use core::fmt;
+use memory::{paging, IDENTITY_MAPPED_END, PAGE_SIZE};
+
+/// The maximum amount of frames walked by default
+pub const MAX_FRAMES: usize = 16;
+
+/// A backtrace, walked every time it is formatted
+#[derive(Copy, Clone, Debug)]
+pub struct Backtrace {
+    /// The address of the code the backtrace was taken in, if known. The frame pointers only give
+    /// the return addresses of its callers.
+    rip: Option<usize>,
+    rbp: usize,
+    max_frames: usize,
+}
+
+impl Backtrace {
+    /// The backtrace of the calling function
+    #[inline(always)]
+    pub fn current() -> Self {
+        let rbp: usize;
+        unsafe { asm!("mov %rbp, $0" : "=r"(rbp)); }
+
+        Backtrace { rip: None, rbp, max_frames: MAX_FRAMES }
+    }
+
+    /// The backtrace of interrupted code, such as code which caused an exception
+    pub fn from_registers(rip: usize, rbp: usize) -> Self {
+        Backtrace { rip: Some(rip), rbp, max_frames: MAX_FRAMES }
+    }
+
+    /// Limits the amount of frames walked, for instance to fit the backtrace on the screen
+    pub fn max_frames(self, max_frames: usize) -> Self {
+        Backtrace { max_frames, ..self }
+    }
+
+    /// Gets an iterator over the return addresses of every frame, starting with the innermost
+    pub fn frames(&self) -> Frames {
+        Frames { rbp: self.rbp, walked: 0, max_frames: self.max_frames, error: None }
+    }
+}
+
+/// Why a walk stopped before reaching the outermost frame
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum WalkError {
+    /// The frame pointer isn't aligned, isn't mapped or points down the stack
+    InvalidFramePointer(usize),
+    /// The maximum amount of frames was walked
+    TooDeep,
+}
+
+/// Iterates over the return addresses on a stack
+pub struct Frames {
+    rbp: usize,
+    walked: usize,
+    max_frames: usize,
+    /// Set once the walk stopped early
+    error: Option<WalkError>,
+}
+
+impl Frames {
+    /// Why the walk stopped early, once it has
+    pub fn error(&self) -> Option<WalkError> {
+        self.error
+    }
+}
+
+impl Iterator for Frames {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        // The outermost frame, in `boot.asm`, has no frame pointer
+        if self.rbp == 0 || self.error.is_some() {
+            return None;
+        }
+
+        if self.walked == self.max_frames {
+            self.error = Some(WalkError::TooDeep);
+            return None;
+        }
+
+        if self.rbp % 8 != 0 || !is_readable(self.rbp) || !is_readable(self.rbp + 8) {
+            self.error = Some(WalkError::InvalidFramePointer(self.rbp));
+            return None;
+        }
+
+        let (previous, return_address) = unsafe {
+            (*(self.rbp as *const usize), *((self.rbp + 8) as *const usize))
+        };
+
+        // The stack grows down, so callers' frames must be higher up. This also stops loops.
+        if previous != 0 && previous <= self.rbp {
+            self.error = Some(WalkError::InvalidFramePointer(previous));
+        }
+
+        self.rbp = previous;
+        self.walked += 1;
+
+        if return_address == 0 {
+            None
+        } else {
+            Some(return_address)
+        }
+    }
+}
+
+impl fmt::Display for Backtrace {
+    /// Prints one frame per line, such as `  #1 0x10a2b4 flower_kernel::kmain+0x1c4`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "backtrace:")?;
+
+        let mut index = 0;
+        if let Some(rip) = self.rip {
+            write!(f, "\n{}", Frame(index, rip))?;
+            index += 1;
+        }
+
+        let mut frames = self.frames();
+        for address in &mut frames {
+            // The return address is just after the call, which may be the start of another symbol
+            write!(f, "\n{}", Frame(index, address - 1))?;
+            index += 1;
+        }
+
+        match frames.error() {
+            Some(WalkError::InvalidFramePointer(rbp)) => write!(f, "\n  <invalid frame pointer {:#x}>", rbp),
+            Some(WalkError::TooDeep) => write!(f, "\n  <more frames omitted>"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A single line of a backtrace
+struct Frame(usize, usize);
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "  #{} {:#x}", self.0, self.1)?;
+
+        match symbols::lookup(self.1) {
+            Some(symbol) => write!(f, " {}+{:#x}", symbols::Demangle(symbol.name), symbol.offset),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns `true` if the given address can be read without faulting. This may be called in a
+/// panic, so must not wait on the page table lock.
+fn is_readable(address: usize) -> bool {
+    match paging::ACTIVE_TABLE.try_lock() {
+        Some(table) => table.translate(address).is_some(),
+        // Without the page table, only the identity map is known to be mapped
+        None => (address as u64) >= PAGE_SIZE && (address as u64) < IDENTITY_MAPPED_END,
+    }
+}
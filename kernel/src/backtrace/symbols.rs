@@ -0,0 +1,228 @@
+//! Symbolization of addresses against the kernel's ELF symbol table, and demangling of the Rust
+//! symbol names found in it
+
+use core::{fmt, mem, slice, str};
+use multiboot::{self, ElfSection};
+use multiboot::elf_sections::ElfSectionType;
+
+/// Symbol types, from the low nibble of a symbol's info
+const SYMBOL_TYPE_NONE: u8 = 0;
+const SYMBOL_TYPE_FUNCTION: u8 = 2;
+
+/// The length of the hash which rustc appends to mangled names, including the leading `h`
+const HASH_LENGTH: usize = 17;
+
+/// Escapes used in mangled names for characters which aren't valid in symbols
+const ESCAPES: &[(&str, &str)] = &[
+    ("$SP$", "@"),
+    ("$BP$", "*"),
+    ("$RF$", "&"),
+    ("$LT$", "<"),
+    ("$GT$", ">"),
+    ("$LP$", "("),
+    ("$RP$", ")"),
+    ("$C$", ","),
+    ("$u20$", " "),
+    ("$u22$", "\""),
+    ("$u27$", "'"),
+    ("$u2b$", "+"),
+    ("$u3b$", ";"),
+    ("$u5b$", "["),
+    ("$u5d$", "]"),
+    ("$u7b$", "{"),
+    ("$u7d$", "}"),
+    ("$u7e$", "~"),
+    ("..", "::"),
+];
+
+/// A symbol table entry (ELF64)
+#[allow(dead_code)] // Dead fields for completeness
+#[repr(C)]
+struct ElfSymbol {
+    name_index: u32,
+    info: u8,
+    other: u8,
+    section_index: u16,
+    value: u64,
+    size: u64,
+}
+
+impl ElfSymbol {
+    /// Returns `true` if this symbol is code which the given address may be in. Symbols without
+    /// a type or size come from assembly labels, which are assumed to extend up to the next symbol.
+    fn may_contain(&self, address: u64) -> bool {
+        let typ = self.info & 0xF;
+        if self.value == 0 || self.value > address {
+            return false;
+        }
+
+        match typ {
+            SYMBOL_TYPE_FUNCTION if self.size != 0 => address - self.value < self.size,
+            SYMBOL_TYPE_FUNCTION | SYMBOL_TYPE_NONE => self.size == 0,
+            _ => false,
+        }
+    }
+}
+
+/// The symbol an address is in
+pub struct Symbol {
+    /// The (mangled) name of the symbol
+    pub name: &'static str,
+    /// The offset of the address from the start of the symbol
+    pub offset: usize,
+}
+
+/// Finds the symbol the given address is in, if the bootloader loaded the symbol table
+pub fn lookup(address: usize) -> Option<Symbol> {
+    let elf_sections = multiboot::info()?.elf_sections()?;
+    let symbol_table = elf_sections.sections()
+        .find(|section| section.section_type() == ElfSectionType::SymbolTable)?;
+    let strings = elf_sections.section(symbol_table.link() as usize)?.data()?;
+
+    // A function containing the address wins over the closest label before it
+    let address = address as u64;
+    let symbol = symbols(symbol_table)?.iter()
+        .filter(|symbol| symbol.may_contain(address))
+        .max_by_key(|symbol| (symbol.size != 0, symbol.value))?;
+
+    Some(Symbol {
+        name: string_at(strings, symbol.name_index as usize)?,
+        offset: (address - symbol.value) as usize,
+    })
+}
+
+fn symbols(symbol_table: &ElfSection) -> Option<&'static [ElfSymbol]> {
+    let data = symbol_table.data()?;
+
+    if symbol_table.entry_size() != mem::size_of::<ElfSymbol>() as u64 {
+        return None;
+    }
+
+    let count = data.len() / mem::size_of::<ElfSymbol>();
+    Some(unsafe { slice::from_raw_parts(data.as_ptr() as *const ElfSymbol, count) })
+}
+
+/// Reads the null terminated string at the given index of a string table
+fn string_at(strings: &'static [u8], index: usize) -> Option<&'static str> {
+    let string = strings.get(index..)?;
+    let length = string.iter().position(|&byte| byte == 0)?;
+
+    str::from_utf8(&string[..length]).ok()
+}
+
+/// Formats a mangled Rust symbol name as a path, such as `flower_kernel::kmain`. Names which
+/// aren't mangled are written as they are.
+pub struct Demangle<'a>(pub &'a str);
+
+impl<'a> fmt::Display for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = match mangled_path(self.0) {
+            Some(path) => path,
+            None => return write!(f, "{}", self.0),
+        };
+
+        let mut rest = path;
+        let mut first = true;
+
+        while let Some((segment, next)) = next_segment(rest) {
+            rest = next;
+
+            // The hash disambiguates between crate versions, which is just noise here
+            let is_hash = rest.is_empty()
+                && segment.len() == HASH_LENGTH
+                && segment.starts_with('h')
+                && segment[1..].bytes().all(|byte| (byte as char).is_digit(16));
+
+            if is_hash {
+                break;
+            }
+
+            if !first {
+                write!(f, "::")?;
+            }
+
+            write_segment(f, segment)?;
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// Strips the `_ZN` prefix and `E` suffix of a mangled name, checking that every segment in
+/// between is well formed
+fn mangled_path(name: &str) -> Option<&str> {
+    if !name.starts_with("_ZN") || !name.ends_with('E') || name.len() < 4 {
+        return None;
+    }
+
+    let path = &name[3..name.len() - 1];
+
+    let mut rest = path;
+    while !rest.is_empty() {
+        rest = next_segment(rest)?.1;
+    }
+
+    Some(path)
+}
+
+/// Splits the length prefixed segment at the start of a mangled path off from the rest
+fn next_segment(path: &str) -> Option<(&str, &str)> {
+    let digits = path.bytes().take_while(|byte| byte.is_ascii_digit()).count();
+    let length: usize = path[..digits].parse().ok()?;
+    let end = digits.checked_add(length)?;
+
+    if end > path.len() || !path.is_char_boundary(end) {
+        return None;
+    }
+
+    Some((&path[digits..end], &path[end..]))
+}
+
+fn write_segment(f: &mut fmt::Formatter, segment: &str) -> fmt::Result {
+    // Segments which would start with an escape are prefixed with an underscore
+    let mut rest = if segment.starts_with("_$") { &segment[1..] } else { segment };
+
+    while let Some(character) = rest.chars().next() {
+        let escape = ESCAPES.iter().find(|&&(escaped, _)| rest.starts_with(escaped));
+
+        match escape {
+            Some(&(escaped, unescaped)) => {
+                write!(f, "{}", unescaped)?;
+                rest = &rest[escaped.len()..];
+            }
+            None => {
+                write!(f, "{}", character)?;
+                rest = &rest[character.len_utf8()..];
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use super::Demangle;
+
+    kernel_test! {
+        fn demangles_path_without_hash() {
+            let name = "_ZN13flower_kernel4kmain17h0123456789abcdefE";
+            assert_eq!(Demangle(name).to_string(), "flower_kernel::kmain");
+        }
+
+        fn demangles_escapes() {
+            let name = "_ZN4core3ptr13drop_in_place17h0123456789abcdefE";
+            assert_eq!(Demangle(name).to_string(), "core::ptr::drop_in_place");
+
+            let name = "_ZN49_$LT$flower_kernel..log..Level$u20$as$u20$Ord$GT$3cmp17h0123456789abcdefE";
+            assert_eq!(Demangle(name).to_string(), "<flower_kernel::log::Level as Ord>::cmp");
+        }
+
+        fn leaves_unmangled_names() {
+            assert_eq!(Demangle("kmain").to_string(), "kmain");
+            assert_eq!(Demangle("_ZN3fooX").to_string(), "_ZN3fooX");
+        }
+    }
+}
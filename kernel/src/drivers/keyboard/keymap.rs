@@ -171,6 +171,46 @@ pub fn get_us_qwerty_char(keycode: u8) -> Option<(char, char)> {
         codes::PERIOD => Some(('.', '>')),
         codes::FORWARD_SLASH => Some(('/', '?')),
         codes::SPACE => Some((' ', ' ')),
+        codes::NUM_PAD_FORWARD_SLASH => Some(('/', '/')),
+        codes::NUM_PAD_ASTERISK => Some(('*', '*')),
+        codes::NUM_PAD_MINUS => Some(('-', '-')),
+        codes::NUM_PAD_PLUS => Some(('+', '+')),
+        codes::NUM_PAD_ENTER => Some(('\n', '\n')),
+        _ => None,
+    }
+}
+
+/// Gets the character typed by the given numeric keypad key while Num Lock is on
+pub fn get_num_pad_char(keycode: u8) -> Option<char> {
+    match keycode {
+        codes::NUM_PAD_0 => Some('0'),
+        codes::NUM_PAD_1 => Some('1'),
+        codes::NUM_PAD_2 => Some('2'),
+        codes::NUM_PAD_3 => Some('3'),
+        codes::NUM_PAD_4 => Some('4'),
+        codes::NUM_PAD_5 => Some('5'),
+        codes::NUM_PAD_6 => Some('6'),
+        codes::NUM_PAD_7 => Some('7'),
+        codes::NUM_PAD_8 => Some('8'),
+        codes::NUM_PAD_9 => Some('9'),
+        codes::NUM_PAD_DELETE => Some('.'),
+        _ => None,
+    }
+}
+
+/// Gets the Flower keycode of the navigation key the given numeric keypad key acts as while Num Lock is off
+pub fn get_num_pad_navigation_code(keycode: u8) -> Option<u8> {
+    match keycode {
+        codes::NUM_PAD_0 => Some(codes::INSERT),
+        codes::NUM_PAD_1 => Some(codes::END),
+        codes::NUM_PAD_2 => Some(codes::DOWN_ARROW),
+        codes::NUM_PAD_3 => Some(codes::PAGE_DOWN),
+        codes::NUM_PAD_4 => Some(codes::LEFT_ARROW),
+        codes::NUM_PAD_6 => Some(codes::RIGHT_ARROW),
+        codes::NUM_PAD_7 => Some(codes::HOME),
+        codes::NUM_PAD_8 => Some(codes::UP_ARROW),
+        codes::NUM_PAD_9 => Some(codes::PAGE_UP),
+        codes::NUM_PAD_DELETE => Some(codes::DELETE),
         _ => None,
     }
 }
@@ -275,11 +315,49 @@ pub fn get_extended_code_ps2_set_2(extended_code: u8) -> Option<u8> {
         0x4A => Some(codes::NUM_PAD_FORWARD_SLASH),
         0x5A => Some(codes::NUM_PAD_ENTER),
         0x69 => Some(codes::END),
+        0x6B => Some(codes::LEFT_ARROW),
         0x6C => Some(codes::HOME),
         0x70 => Some(codes::INSERT),
         0x71 => Some(codes::DELETE),
+        0x72 => Some(codes::DOWN_ARROW),
+        0x74 => Some(codes::RIGHT_ARROW),
+        0x75 => Some(codes::UP_ARROW),
         0x7A => Some(codes::PAGE_DOWN),
         0x7D => Some(codes::PAGE_UP),
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    kernel_test! {
+        fn scancodes_map_to_distinct_keycodes() {
+            let mut seen = [false; 0x100];
+
+            let normal = (0..=0xFFu8).filter_map(get_code_ps2_set_2);
+            let extended = (0..=0xFFu8).filter_map(get_extended_code_ps2_set_2);
+
+            for keycode in normal.chain(extended) {
+                assert!(!seen[keycode as usize], "keycode {:#x} mapped twice", keycode);
+                seen[keycode as usize] = true;
+            }
+        }
+
+        fn maps_characters() {
+            assert_eq!(get_code_ps2_set_2(0x1C), Some(codes::A));
+            assert_eq!(get_us_qwerty_char(codes::A), Some(('a', 'A')));
+            assert_eq!(get_us_qwerty_char(codes::KEY_1), Some(('1', '!')));
+            assert_eq!(get_us_qwerty_char(codes::LEFT_SHIFT), None);
+        }
+
+        fn maps_num_pad() {
+            assert_eq!(get_num_pad_char(codes::NUM_PAD_7), Some('7'));
+            assert_eq!(get_num_pad_navigation_code(codes::NUM_PAD_7), Some(codes::HOME));
+            assert_eq!(get_num_pad_navigation_code(codes::NUM_PAD_5), None);
+            assert_eq!(get_num_pad_char(codes::NUM_PAD_PLUS), None);
+            assert_eq!(get_extended_code_ps2_set_2(0x75), Some(codes::UP_ARROW));
+        }
+    }
+}
@@ -3,18 +3,24 @@
 //! The keyboard driver handles all keyboard related functionality, intended to support both PS/2 and USB.
 //! Currently, only PS/2 support has been implemented through the use of the PS/2 driver.
 //!
-//! The driver is event based, and events are received through the `read_event` method, which blocks until an event is received.
+//! The driver is event based. Scancodes are decoded into events by the keyboard's interrupt handler and queued.
+//! Events are received through the `wait_event` method, which halts until an event is received, or polled with `read_event`.
 //! The event contains the keycode pressed, which can be compared to `keymap::codes`, an optional `char`, the type of press, and various modifier flags.
+//! The modifier flags include the state of Caps, Num and Scroll Lock, which the keyboard's LEDs are kept in sync with.
+//!
+//! A keyboard which fails its self-test stops sending events until it is plugged back in, after which it is set up again
+//! the next time events are read. Consumers are told through `ps2::hotplug`. Errors the keyboard reports while in use,
+//! such as an overrun of its buffer, only drop the keys being decoded.
 //!
 //! # Examples
 //!
 //! ```rust,no_run
-//! let device = drivers::ps2::CONTROLLER.device(drivers::ps2::DevicePort::Keyboard);
+//! let device = drivers::ps2::CONTROLLER.keyboard().unwrap();
 //! let mut keyboard = Ps2Keyboard::new(device);
 //!
 //! keyboard.enable()?;
 //! loop {
-//!     let event = keyboard.read_event()?;
+//!     let event = keyboard.wait_event()?;
 //!     handle_event(event);
 //! }
 //! ```
@@ -23,9 +29,12 @@ pub mod keymap;
 
 use core::convert::From;
 
-use drivers::ps2::{self, Device, DeviceState};
+use drivers::ps2::{self, hotplug, Device, DeviceState};
 use drivers::ps2::io::Ps2Error;
 use drivers::ps2::io::commands::{DeviceCommand, DeviceDataCommand};
+use interrupts::{self, irq::{self, IrqError}};
+use ring_buffer::RingBuffer;
+use spin::Mutex;
 
 bitflags! {
     pub struct ModifierFlags: u8 {
@@ -35,6 +44,12 @@ bitflags! {
         const ALT = 1 << 1;
         /// If a SHIFT modifier is active
         const SHIFT = 1 << 2;
+        /// If Caps Lock is on
+        const CAPS_LOCK = 1 << 3;
+        /// If Num Lock is on
+        const NUM_LOCK = 1 << 4;
+        /// If Scroll Lock is on
+        const SCROLL_LOCK = 1 << 5;
     }
 }
 
@@ -54,6 +69,38 @@ impl ModifierFlags {
         flags.set(ModifierFlags::SHIFT, shift);
         flags
     }
+
+    /// Gets the lock toggled by the given keycode, if any
+    fn from_lock_key(keycode: u8) -> Option<Self> {
+        match keycode {
+            keymap::codes::CAPS_LOCK => Some(ModifierFlags::CAPS_LOCK),
+            keymap::codes::NUM_LOCK => Some(ModifierFlags::NUM_LOCK),
+            keymap::codes::SCROLL_LOCK => Some(ModifierFlags::SCROLL_LOCK),
+            _ => None,
+        }
+    }
+
+    /// Gets the byte sent with the PS/2 set LEDs command to light the LEDs of the contained locks
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let locks = ModifierFlags::CAPS_LOCK | ModifierFlags::SCROLL_LOCK;
+    /// assert_eq!(locks.ps2_leds(), 0b101);
+    /// ```
+    fn ps2_leds(&self) -> u8 {
+        let mut leds = 0;
+        if self.contains(ModifierFlags::SCROLL_LOCK) {
+            leds |= 1 << 0;
+        }
+        if self.contains(ModifierFlags::NUM_LOCK) {
+            leds |= 1 << 1;
+        }
+        if self.contains(ModifierFlags::CAPS_LOCK) {
+            leds |= 1 << 2;
+        }
+        leds
+    }
 }
 
 /// Contains data relating to a key press event
@@ -90,6 +137,10 @@ pub enum Ps2KeyboardError {
     ScancodeSetFailed,
     /// If enabling scanning fails
     ScanningEnableFailed,
+    /// If setting the LEDs fails
+    LedSetFailed,
+    /// If the keyboard's IRQ line could not be claimed
+    IrqUnavailable(IrqError),
 }
 
 /// Interface to a generic keyboard.
@@ -105,7 +156,7 @@ pub trait Keyboard {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// let device = drivers::ps2::CONTROLLER.device(drivers::ps2::DevicePort::Keyboard);
+    /// let device = drivers::ps2::CONTROLLER.keyboard().unwrap();
     /// let mut keyboard = Ps2Keyboard::new(device);
     ///
     /// match keyboard.enable() {
@@ -124,7 +175,7 @@ pub trait Keyboard {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// let device = drivers::ps2::CONTROLLER.device(drivers::ps2::DevicePort::Keyboard);
+    /// let device = drivers::ps2::CONTROLLER.keyboard().unwrap();
     /// let mut keyboard = Ps2Keyboard::new(device);
     ///
     /// match keyboard.disable() {
@@ -134,25 +185,56 @@ pub trait Keyboard {
     /// ```
     fn disable(&mut self) -> Result<(), Self::Error>;
 
-    /// Polls the device for a new key state event, or returns `None` if none have occurred since the last poll.
+    /// Takes the next queued key state event, or returns `None` if none have occurred since the last read.
+    /// This never blocks.
     ///
     /// # Examples
     ///
     /// ```rust,no_run
-    /// let device = drivers::ps2::CONTROLLER.device(drivers::ps2::DevicePort::Keyboard);
+    /// let device = drivers::ps2::CONTROLLER.keyboard().unwrap();
     /// let mut keyboard = Ps2Keyboard::new(device);
     ///
     /// if let Some(event) = keyboard.read_event()? {
     ///     println!("Event occurred for char: {}", event.char.unwrap_or(' '));
     /// }
     /// ```
-    // TODO: This should eventually use interrupts and hold a queue
     fn read_event(&mut self) -> Result<Option<KeyEvent>, Self::Error>;
 
+    /// Takes the next queued key state event, halting the CPU until one is received.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let device = drivers::ps2::CONTROLLER.keyboard().unwrap();
+    /// let mut keyboard = Ps2Keyboard::new(device);
+    ///
+    /// let event = keyboard.wait_event()?;
+    /// println!("Event occurred for char: {}", event.char.unwrap_or(' '));
+    /// ```
+    fn wait_event(&mut self) -> Result<KeyEvent, Self::Error> {
+        loop {
+            // Check the queue with interrupts disabled so that an event can't arrive between the
+            // check and the halt, which would leave it waiting until the next interrupt
+            interrupts::disable();
+
+            match self.read_event() {
+                Ok(Some(event)) => {
+                    interrupts::enable();
+                    return Ok(event);
+                }
+                Ok(None) => interrupts::enable_and_halt(),
+                Err(error) => {
+                    interrupts::enable();
+                    return Err(error);
+                }
+            }
+        }
+    }
+
     /// Returns `true` if the given keycode is currently being pressed
     ///
     /// ```rust,no_run
-    /// let device = drivers::ps2::CONTROLLER.device(drivers::ps2::DevicePort::Keyboard);
+    /// let device = drivers::ps2::CONTROLLER.keyboard().unwrap();
     /// let mut keyboard = Ps2Keyboard::new(device);
     ///
     /// if keyboard.pressed(keymap::codes::LEFT_SHIFT) {
@@ -164,10 +246,39 @@ pub trait Keyboard {
     fn pressed(&self, keycode: u8) -> bool;
 }
 
+/// Events decoded by the PS/2 keyboard interrupt handler, waiting to be read
+static EVENT_QUEUE: RingBuffer<KeyEvent, [Option<KeyEvent>; 64]> = RingBuffer::new([None; 64]);
+
+/// The PS/2 scancode decoder. This is locked by the interrupt handler, so must only be locked
+/// elsewhere with interrupts disabled
+static DECODER: Mutex<Ps2Decoder> = Mutex::new(Ps2Decoder::new());
+
+/// Handles an interrupt from the PS/2 keyboard, decoding the received byte and queueing the
+/// resulting event, if any
+fn handle_ps2_interrupt() {
+    // The status tells which port the data came from, so it has to be read first
+    let port = ps2::io::output_port_unlocked();
+    let data = ps2::io::read_data_unlocked();
+    let mut decoder = DECODER.lock();
+
+    if hotplug::is_device_error(data) {
+        // The keyboard is still there, but bytes around this one were lost
+        decoder.resync();
+    } else if decoder.is_idle() && hotplug::report(port, data) {
+        // The keyboard was plugged in or out. Either way, the keys held down before are no longer,
+        // and the keyboard's LEDs are off.
+        *decoder = Ps2Decoder::new();
+    } else if let Some(event) = decoder.decode(data) {
+        // If nobody is reading events, the queue may fill up, in which case the event is dropped
+        let _ = EVENT_QUEUE.push(event);
+    }
+}
+
 /// Handles interface to a PS/2 keyboard, if available
 pub struct Ps2Keyboard<'a> {
     device: &'a mut Device,
-    key_states: [bool; 0xFF],
+    /// The locks whose LEDs are lit
+    leds: ModifierFlags,
 }
 
 impl<'a> Ps2Keyboard<'a> {
@@ -176,61 +287,181 @@ impl<'a> Ps2Keyboard<'a> {
     /// # Examples
     ///
     /// ```rust,no_run
-    /// let device = drivers::ps2::CONTROLLER.device(drivers::ps2::DevicePort::Keyboard);
+    /// let device = drivers::ps2::CONTROLLER.keyboard().unwrap();
     /// let mut keyboard = Ps2Keyboard::new(device);
     /// ```
     pub fn new(device: &'a mut Device) -> Self {
-        Ps2Keyboard {
-            device,
-            key_states: [false; 0xFF],
+        Ps2Keyboard { device, leds: ModifierFlags::empty() }
+    }
+
+    /// Lights the LEDs of the locks in the given flags, turning off the others
+    fn set_leds(&mut self, locks: ModifierFlags) -> Result<(), Ps2KeyboardError> {
+        // The keyboard's replies would otherwise be taken by the interrupt handler, or data from the
+        // mouse taken as its replies
+        let leds = locks.ps2_leds();
+        let result = self.device.exclusively(|device| {
+            device.command_data(DeviceDataCommand::SetLeds, leds)
+        })?;
+
+        if result? != ps2::ACK {
+            return Err(Ps2KeyboardError::LedSetFailed);
         }
+
+        Ok(())
     }
+}
 
-    /// Reads a single scancode from this PS/2 keyboard
-    ///
-    /// # Examples
-    ///
-    /// ```rust,no_run
-    /// let device = drivers::ps2::CONTROLLER.device(drivers::ps2::DevicePort::Keyboard);
-    /// let mut keyboard = Ps2Keyboard::new(device);
-    ///
-    /// if let Some(scancode) = keyboard.read_scancode()? {
-    ///     print!(scancode);
-    /// }
-    /// ```
-    fn read_scancode(&self) -> Result<Option<Ps2Scancode>, Ps2KeyboardError> {
-        use ps2::io;
-
-        if self.device.state == DeviceState::Enabled {
-            if io::can_read()? && io::can_read_keyboard()? {
-                let mut make = true;
-                let mut extended = false;
-
-                // Get all scancode modifiers, and return when the actual scancode is received
-                let scancode = (io::DATA_PORT.with_lock(|mut data_port| {
-                    loop {
-                        let data = io::read(&mut data_port)?;
-                        match data {
-                            0xE0 ... 0xE1 => extended = true,
-                            0xF0 => make = false,
-                            _ => {
-                                break Ok(data);
-                            }
-                        }
+impl<'a> Keyboard for Ps2Keyboard<'a> {
+    type Error = Ps2KeyboardError;
+
+    fn enable(&mut self) -> Result<(), Ps2KeyboardError> {
+        self.device.enable()?;
+
+        if self.device.state != DeviceState::Enabled {
+            return Err(Ps2KeyboardError::KeyboardEnableFailed);
+        }
+
+        // A keyboard which stopped answering is reset and set up once more
+        match set_up(self.device) {
+            Err(Ps2KeyboardError::ReadError(error)) if error.is_wedged() => {
+                self.device.recover()?;
+                set_up(self.device)?;
+            }
+            result => result?,
+        }
+
+        // From here on, data from the keyboard is received by the interrupt handler
+        irq::register(self.device.irq(), handle_ps2_interrupt)
+            .map_err(Ps2KeyboardError::IrqUnavailable)?;
+        interrupts::without_interrupts(|| self.device.set_interrupts(true))?;
+
+        Ok(())
+    }
+
+    fn disable(&mut self) -> Result<(), Ps2KeyboardError> {
+        interrupts::without_interrupts(|| self.device.set_interrupts(false))?;
+        irq::unregister(self.device.irq()).map_err(Ps2KeyboardError::IrqUnavailable)?;
+
+        self.device.disable()?;
+
+        Ok(())
+    }
+
+    fn read_event(&mut self) -> Result<Option<KeyEvent>, Self::Error> {
+        self.device.handle_hotplug(set_up)?;
+
+        match self.device.state {
+            DeviceState::Enabled => {
+                let locks = interrupts::without_interrupts(|| DECODER.lock().locks);
+
+                if locks != self.leds {
+                    // The LEDs only show the lock state, so the keyboard is still usable without them.
+                    // Setting them is tried again the next time events are read.
+                    match self.set_leds(locks) {
+                        Ok(_) => self.leds = locks,
+                        Err(error) => warn!("kbd: failed to set LEDs: {:?}", error),
                     }
-                }): Result<u8, io::Ps2Error>)?;
+                }
+
+                Ok(EVENT_QUEUE.pop())
+            }
+            // Nothing can be received until the keyboard is plugged back in
+            DeviceState::Detached => Ok(None),
+            _ => Err(Ps2KeyboardError::KeyboardDisabled),
+        }
+    }
+
+    fn pressed(&self, keycode: u8) -> bool {
+        interrupts::without_interrupts(|| DECODER.lock().pressed(keycode))
+    }
+}
+
+/// Sets the scancode set of the given keyboard and enables scanning
+fn set_up(device: &mut Device) -> Result<(), Ps2KeyboardError> {
+    if device.command_data(DeviceDataCommand::SetScancode, 2)? != ps2::ACK {
+        return Err(Ps2KeyboardError::ScancodeSetFailed);
+    }
+
+    if device.command(DeviceCommand::EnableScanning)? != ps2::ACK {
+        return Err(Ps2KeyboardError::ScanningEnableFailed);
+    }
+
+    Ok(())
+}
+
+/// Decodes the PS/2 scancode set 2 byte stream into [KeyEvent]s, keeping track of which keys are
+/// currently pressed and which locks are on
+struct Ps2Decoder {
+    make: bool,
+    extended: bool,
+    key_states: [bool; 0xFF],
+    locks: ModifierFlags,
+}
+
+impl Ps2Decoder {
+    const fn new() -> Self {
+        Ps2Decoder {
+            make: true,
+            extended: false,
+            key_states: [false; 0xFF],
+            locks: ModifierFlags { bits: 0 },
+        }
+    }
+
+    /// Feeds a single byte received from the keyboard into this decoder, returning the resulting
+    /// event once a full scancode has been received
+    fn decode(&mut self, data: u8) -> Option<KeyEvent> {
+        let scancode = self.feed(data)?;
+        // Keys are tracked by their position, as a numeric keypad key may be reported as a
+        // navigation key
+        let keycode = scancode.keycode()?;
+
+        // Locks are toggled when their key is pressed, but not while it's held down
+        if scancode.make && !self.pressed(keycode) {
+            if let Some(lock) = ModifierFlags::from_lock_key(keycode) {
+                self.locks.toggle(lock);
+            }
+        }
+
+        let event = self.create_event(&scancode)?;
+        self.key_states[keycode as usize] = scancode.make;
+
+        Some(event)
+    }
+
+    /// Returns `true` if no scancode is partially received
+    fn is_idle(&self) -> bool {
+        self.make && !self.extended
+    }
+
+    /// Starts over after bytes from the keyboard were lost, dropping any partial scancode. Keys
+    /// may have been released meanwhile, so none are held down anymore, but the locks are kept, as
+    /// the keyboard's LEDs still show them.
+    fn resync(&mut self) {
+        self.make = true;
+        self.extended = false;
+        self.key_states = [false; 0xFF];
+    }
+
+    /// Feeds a single byte into this decoder, returning a scancode with its modifiers once the
+    /// actual scancode is received
+    fn feed(&mut self, data: u8) -> Option<Ps2Scancode> {
+        match data {
+            0xE0 ... 0xE1 => self.extended = true,
+            0xF0 => self.make = false,
+            _ => {
+                let scancode = Ps2Scancode::new(data, self.extended, self.make);
+                self.make = true;
+                self.extended = false;
 
                 // If scancode is present, return it with modifiers
-                return Ok(if scancode != 0 {
-                    Some(Ps2Scancode::new(scancode, extended, make))
-                } else {
-                    None
-                });
+                if data != 0 {
+                    return Some(scancode);
+                }
             }
-            Ok(None)
-        } else {
-            Err(Ps2KeyboardError::KeyboardDisabled)
         }
+
+        None
     }
 
     /// Creates a [KeyEvent] from the given scancode and key state
@@ -239,7 +470,7 @@ impl<'a> Ps2Keyboard<'a> {
     ///
     /// ```rust,no_run
     /// let scancode = Ps2Scancode::new(0x15, false, true);
-    /// let event = keyboard.create_event(&scancode).unwrap();
+    /// let event = decoder.create_event(&scancode).unwrap();
     /// assert_eq!(event.keycode, keymap::codes::Q);
     /// assert_eq!(event.char, Some('q'));
     /// assert_eq!(event.event_type, KeyEventType::Make);
@@ -248,16 +479,9 @@ impl<'a> Ps2Keyboard<'a> {
         let ctrl = self.pressed(keymap::codes::LEFT_CONTROL) || self.pressed(keymap::codes::RIGHT_CONTROL);
         let alt = self.pressed(keymap::codes::LEFT_ALT) || self.pressed(keymap::codes::RIGHT_ALT);
         let shift = self.pressed(keymap::codes::LEFT_SHIFT) || self.pressed(keymap::codes::RIGHT_SHIFT);
-        let modifiers = ModifierFlags::from_modifiers(ctrl, alt, shift);
+        let modifiers = ModifierFlags::from_modifiers(ctrl, alt, shift) | self.locks;
 
         if let Some(keycode) = scancode.keycode() {
-            let char = keymap::get_us_qwerty_char(keycode)
-                .map(|chars| if shift {
-                    chars.1
-                } else {
-                    chars.0
-                });
-
             // If the key was already pressed and make was sent, this is a repeat event
             let event_type = match scancode.make {
                 true if self.pressed(keycode) => KeyEventType::Repeat,
@@ -265,51 +489,35 @@ impl<'a> Ps2Keyboard<'a> {
                 false => KeyEventType::Break,
             };
 
+            // The numeric keypad types digits while Num Lock is on, and acts as navigation keys
+            // otherwise
+            let (keycode, char) = match keymap::get_num_pad_char(keycode) {
+                Some(char) if self.locks.contains(ModifierFlags::NUM_LOCK) => (keycode, Some(char)),
+                Some(_) => (keymap::get_num_pad_navigation_code(keycode).unwrap_or(keycode), None),
+                None => (keycode, self.get_char(keycode, shift)),
+            };
+
             return Some(KeyEvent { keycode, char, event_type, modifiers });
         }
 
         None
     }
-}
 
-impl<'a> Keyboard for Ps2Keyboard<'a> {
-    type Error = Ps2KeyboardError;
-
-    fn enable(&mut self) -> Result<(), Ps2KeyboardError> {
-        self.device.enable()?;
+    /// Gets the character typed by the given key. Caps Lock only affects letters, and is reversed
+    /// by shift.
+    fn get_char(&self, keycode: u8, shift: bool) -> Option<char> {
+        keymap::get_us_qwerty_char(keycode).map(|chars| {
+            let caps = self.locks.contains(ModifierFlags::CAPS_LOCK) && chars.0.is_ascii_alphabetic();
 
-        if self.device.state != DeviceState::Enabled {
-            return Err(Ps2KeyboardError::KeyboardEnableFailed);
-        }
-
-        if self.device.command_data(DeviceDataCommand::SetScancode, 2)? != ps2::ACK {
-            return Err(Ps2KeyboardError::ScancodeSetFailed);
-        }
-
-        if self.device.command(DeviceCommand::EnableScanning)? != ps2::ACK {
-            return Err(Ps2KeyboardError::ScanningEnableFailed);
-        }
-
-        Ok(())
-    }
-
-    fn disable(&mut self) -> Result<(), Ps2KeyboardError> {
-        self.device.disable()?;
-
-        Ok(())
-    }
-
-    fn read_event(&mut self) -> Result<Option<KeyEvent>, Self::Error> {
-        let event = self.read_scancode()?.and_then(|scancode| {
-            let event = self.create_event(&scancode);
-            if event.is_some() {
-                self.key_states[event.unwrap().keycode as usize] = scancode.make;
+            if shift != caps {
+                chars.1
+            } else {
+                chars.0
             }
-            event
-        });
-        Ok(event)
+        })
     }
 
+    /// Returns `true` if the given keycode is currently being pressed
     fn pressed(&self, keycode: u8) -> bool {
         *self.key_states.get(keycode as usize).unwrap_or(&false)
     }
@@ -334,7 +542,7 @@ impl Ps2Scancode {
     ///
     /// ```rust
     /// let scancode = Ps2Scancode::new(0x01, false, true);
-    /// assert_eq!(scancode.keycode(), Some(keymap::codes::KEY_F9));
+    /// assert_eq!(scancode.keycode(), Some(keymap::codes::F9));
     /// ```
     fn keycode(&self) -> Option<u8> {
         if self.extended {
@@ -350,3 +558,171 @@ impl From<Ps2Error> for Ps2KeyboardError {
         Ps2KeyboardError::ReadError(error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use drivers::ps2::fake::FakePs2;
+    use drivers::ps2::io;
+    use io::mock;
+    use super::*;
+    use super::keymap::codes;
+
+    /// Decodes every byte the given script has the controller send, as the interrupt handler would
+    fn decode(script: &[u8]) -> Vec<KeyEvent> {
+        let mock = FakePs2::new().sends(script).build();
+
+        let (_, events) = mock::run(mock, || {
+            let mut decoder = Ps2Decoder::new();
+            let mut events = Vec::new();
+
+            while io::can_read().unwrap() {
+                let data = io::read(&mut io::DATA_PORT.lock()).unwrap();
+                events.extend(decoder.decode(data));
+            }
+
+            events
+        });
+
+        events
+    }
+
+    kernel_test! {
+        fn decodes_make_and_break() {
+            let events = decode(&[0x15, 0xF0, 0x15]);
+
+            assert_eq!(events.len(), 2);
+            assert_eq!(events[0].keycode, codes::Q);
+            assert_eq!(events[0].char, Some('q'));
+            assert_eq!(events[0].event_type, KeyEventType::Make);
+            assert_eq!(events[1].keycode, codes::Q);
+            assert_eq!(events[1].event_type, KeyEventType::Break);
+        }
+
+        fn repeated_make_is_repeat() {
+            let events = decode(&[0x1C, 0x1C]);
+
+            assert_eq!(events[0].event_type, KeyEventType::Make);
+            assert_eq!(events[1].event_type, KeyEventType::Repeat);
+        }
+
+        fn shift_selects_upper_character() {
+            let events = decode(&[0x12, 0x1C, 0xF0, 0x12, 0x1C]);
+
+            assert_eq!(events[1].char, Some('A'));
+            assert_eq!(events[1].modifiers, ModifierFlags::SHIFT);
+            assert_eq!(events[3].char, Some('a'));
+            assert_eq!(events[3].modifiers, ModifierFlags::empty());
+        }
+
+        fn decodes_extended_codes() {
+            let events = decode(&[0xE0, 0x14, 0x15, 0xE0, 0xF0, 0x14]);
+
+            assert_eq!(events[0].keycode, codes::RIGHT_CONTROL);
+            assert_eq!(events[1].modifiers, ModifierFlags::CTRL);
+            assert_eq!(events[2].keycode, codes::RIGHT_CONTROL);
+            assert_eq!(events[2].event_type, KeyEventType::Break);
+        }
+
+        fn decoder_is_busy_within_scancode() {
+            let mut decoder = Ps2Decoder::new();
+            assert!(decoder.is_idle());
+
+            // A break code in scancode set 2 can't be mistaken for a self-test result
+            decoder.decode(0xF0);
+            assert!(!decoder.is_idle());
+            decoder.decode(0x15);
+            assert!(decoder.is_idle());
+        }
+
+        fn resync_drops_partial_scancode() {
+            let mut decoder = Ps2Decoder::new();
+            decoder.decode(0x1C);
+            decoder.decode(0xF0);
+            decoder.resync();
+
+            // The key is no longer held down, and the next byte starts a new scancode
+            let event = decoder.decode(0x1C).unwrap();
+            assert_eq!(event.keycode, codes::A);
+            assert_eq!(event.event_type, KeyEventType::Make);
+        }
+
+        fn ignores_unknown_scancodes() {
+            assert!(decode(&[0x00, 0x02]).is_empty());
+        }
+
+        fn caps_lock_toggles_on_press() {
+            // Caps Lock is held down long enough to repeat, then pressed again
+            let events = decode(&[0x58, 0x58, 0xF0, 0x58, 0x1C, 0x58, 0xF0, 0x58, 0x1C]);
+
+            assert_eq!(events[1].event_type, KeyEventType::Repeat);
+            assert_eq!(events[3].char, Some('A'));
+            assert_eq!(events[3].modifiers, ModifierFlags::CAPS_LOCK);
+            assert_eq!(events[6].char, Some('a'));
+            assert_eq!(events[6].modifiers, ModifierFlags::empty());
+        }
+
+        fn caps_lock_only_affects_letters() {
+            let events = decode(&[0x58, 0xF0, 0x58, 0x16, 0x12, 0x1C]);
+
+            assert_eq!(events[2].char, Some('1'));
+            // Shift reverses Caps Lock
+            assert_eq!(events[4].char, Some('a'));
+            assert_eq!(events[4].modifiers, ModifierFlags::SHIFT | ModifierFlags::CAPS_LOCK);
+        }
+
+        fn num_lock_selects_num_pad_function() {
+            let events = decode(&[0x75, 0xF0, 0x75, 0x77, 0xF0, 0x77, 0x75, 0x73]);
+
+            assert_eq!(events[0].keycode, codes::UP_ARROW);
+            assert_eq!(events[0].char, None);
+            assert_eq!(events[4].keycode, codes::NUM_PAD_8);
+            assert_eq!(events[4].char, Some('8'));
+            assert_eq!(events[5].char, Some('5'));
+        }
+
+        fn num_pad_key_released_across_num_lock() {
+            // Num Lock is turned on while 8 is held down as the up arrow
+            let events = decode(&[0x75, 0x77, 0x75, 0xF0, 0x75]);
+
+            assert_eq!(events[2].event_type, KeyEventType::Repeat);
+            assert_eq!(events[3].keycode, codes::NUM_PAD_8);
+            assert_eq!(events[3].event_type, KeyEventType::Break);
+        }
+
+        fn sets_leds() {
+            let mut device = Device::new(ps2::DevicePort::Port1);
+            device.state = DeviceState::Enabled;
+            let mut keyboard = Ps2Keyboard::new(&mut device);
+
+            let mock = FakePs2::new()
+                .controller_replies(0x20, &[0x01])
+                .acks(DeviceDataCommand::SetLeds as u8)
+                .acks(0b110)
+                .build();
+            let (mock, result) = mock::run(mock, || {
+                keyboard.set_leds(ModifierFlags::CAPS_LOCK | ModifierFlags::NUM_LOCK | ModifierFlags::SHIFT)
+            });
+
+            assert_eq!(result, Ok(()));
+            // The mouse's port and both ports' interrupts are disabled in the config while the
+            // keyboard answers
+            assert_eq!(mock.writes_to(0x60), [0x20, 0xED, 0b110, 0x01]);
+        }
+
+        fn failed_leds_are_set_again() {
+            let mut device = Device::new(ps2::DevicePort::Port1);
+            device.state = DeviceState::Enabled;
+            let mut keyboard = Ps2Keyboard::new(&mut device);
+            interrupts::without_interrupts(|| DECODER.lock().locks = ModifierFlags::CAPS_LOCK);
+
+            // The keyboard doesn't answer the command
+            let mock = FakePs2::new().controller_replies(0x20, &[0x01]).build();
+            let (_, result) = mock::run(mock, || keyboard.read_event());
+            interrupts::without_interrupts(|| DECODER.lock().locks = ModifierFlags::empty());
+
+            assert!(result.is_ok());
+            assert_eq!(keyboard.leds, ModifierFlags::empty());
+        }
+    }
+}
@@ -1,3 +1,5 @@
 pub mod vga;
+pub mod serial;
 pub mod ps2;
 pub mod keyboard;
+pub mod mouse;
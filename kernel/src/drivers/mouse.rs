@@ -0,0 +1,709 @@
+//! # Mouse Driver
+//!
+//! The mouse driver handles all mouse related functionality, intended to support both PS/2 and USB.
+//! Currently, only PS/2 mice are supported, including the IntelliMouse extensions for a scroll wheel
+//! and the 4th and 5th buttons.
+//!
+//! Like the keyboard driver, the driver is event based. Packets sent by the mouse are decoded into
+//! events by its interrupt handler and queued. Events are received through `wait_event`, which
+//! halts until an event is received, or polled with `read_event`.
+//!
+//! A mouse which is plugged back in reports its self-test result and ID, which it sends in place
+//! of a packet. It is then set up again the next time events are read, as the extensions it had
+//! enabled are lost.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! let device = drivers::ps2::CONTROLLER.mouse().unwrap();
+//! let mut mouse = Ps2Mouse::new(device);
+//!
+//! mouse.enable()?;
+//! loop {
+//!     match mouse.wait_event()? {
+//!         MouseEvent::Motion { dx, dy } => move_cursor(dx, dy),
+//!         event => handle_event(event),
+//!     }
+//! }
+//! ```
+
+use drivers::ps2::{self, hotplug, Device, DeviceState, DeviceType};
+use drivers::ps2::io::{self, Ps2Error};
+use drivers::ps2::io::commands::{DeviceCommand, DeviceDataCommand};
+use interrupts::{self, irq::{self, IrqError}};
+use ring_buffer::RingBuffer;
+use spin::Mutex;
+
+/// The sample rates a PS/2 mouse supports, in samples per second
+pub const SAMPLE_RATES: [u8; 7] = [10, 20, 40, 60, 80, 100, 200];
+
+/// The sample rate set when the mouse is enabled
+pub const DEFAULT_SAMPLE_RATE: u8 = 100;
+
+bitflags! {
+    pub struct MouseButtons: u8 {
+        const LEFT = 1 << 0;
+        const RIGHT = 1 << 1;
+        const MIDDLE = 1 << 2;
+        /// The 4th (usually back) button of a 5-button mouse
+        const BUTTON_4 = 1 << 3;
+        /// The 5th (usually forward) button of a 5-button mouse
+        const BUTTON_5 = 1 << 4;
+    }
+}
+
+/// Contains data relating to a mouse event
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MouseEvent {
+    /// The mouse moved by the given amount of counts. Positive `dy` is away from the user.
+    Motion { dx: i16, dy: i16 },
+    /// The given button was pressed
+    Press(MouseButtons),
+    /// The given button was released
+    Release(MouseButtons),
+    /// The wheel was scrolled by the given amount of notches. Positive is toward the user.
+    Scroll(i8),
+}
+
+/// The packet format a PS/2 mouse reports in, depending on the extensions it has enabled
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Ps2MouseProtocol {
+    /// 3-byte packets with left, right and middle buttons
+    Standard,
+    /// 4-byte packets, adding a scroll wheel
+    IntelliMouse,
+    /// 4-byte packets, adding a scroll wheel and the 4th and 5th buttons
+    IntelliMouseExplorer,
+}
+
+impl Ps2MouseProtocol {
+    /// The length of this protocol's packets
+    fn packet_len(&self) -> usize {
+        match *self {
+            Ps2MouseProtocol::Standard => 3,
+            _ => 4,
+        }
+    }
+}
+
+/// The resolution a PS/2 mouse reports motion in
+#[allow(dead_code)] // Dead variants for completeness
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum Ps2MouseResolution {
+    CountsPerMm1 = 0,
+    CountsPerMm2 = 1,
+    CountsPerMm4 = 2,
+    CountsPerMm8 = 3,
+}
+
+/// An error for a PS/2 mouse
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Ps2MouseError {
+    /// If an error occurred while reading from PS/2
+    ReadError(Ps2Error),
+    /// If the mouse is disabled and cannot be used
+    MouseDisabled,
+    /// If enabling the mouse fails
+    MouseEnableFailed,
+    /// If the given sample rate isn't one of [SAMPLE_RATES]
+    InvalidSampleRate(u8),
+    /// If the mouse doesn't accept a sample rate or resolution
+    ConfigurationFailed,
+    /// If enabling scanning fails
+    ScanningEnableFailed,
+    /// If the mouse's IRQ line could not be claimed
+    IrqUnavailable(IrqError),
+}
+
+/// Interface to a generic mouse.
+pub trait Mouse {
+    type Error;
+
+    /// Enables this mouse, setting it up before use.
+    ///
+    /// # Note
+    ///
+    /// The mouse should not be accessed while not enabled!
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let device = drivers::ps2::CONTROLLER.mouse().unwrap();
+    /// let mut mouse = Ps2Mouse::new(device);
+    ///
+    /// match mouse.enable() {
+    ///     Ok(_) => println!("Mouse successfully enabled"),
+    ///     Err(err) => println!("Mouse enable failed with error: {:?}", err),
+    /// }
+    /// ```
+    fn enable(&mut self) -> Result<(), Self::Error>;
+
+    /// Disables this mouse, making use unavailable.
+    ///
+    /// # Note
+    ///
+    /// Until `enable` is called again, this mouse should not be used
+    fn disable(&mut self) -> Result<(), Self::Error>;
+
+    /// Takes the next queued mouse event, or returns `None` if none have occurred since the last
+    /// read. This never blocks.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// if let Some(MouseEvent::Scroll(notches)) = mouse.read_event()? {
+    ///     terminal.scroll(notches);
+    /// }
+    /// ```
+    fn read_event(&mut self) -> Result<Option<MouseEvent>, Self::Error>;
+
+    /// Takes the next queued mouse event, halting the CPU until one is received.
+    fn wait_event(&mut self) -> Result<MouseEvent, Self::Error> {
+        loop {
+            // Check the queue with interrupts disabled so that an event can't arrive between the
+            // check and the halt, which would leave it waiting until the next interrupt
+            interrupts::disable();
+
+            match self.read_event() {
+                Ok(Some(event)) => {
+                    interrupts::enable();
+                    return Ok(event);
+                }
+                Ok(None) => interrupts::enable_and_halt(),
+                Err(error) => {
+                    interrupts::enable();
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if all of the given buttons are currently being pressed
+    ///
+    /// ```rust,no_run
+    /// if mouse.pressed(MouseButtons::LEFT) {
+    ///     println!("Dragging");
+    /// }
+    /// ```
+    fn pressed(&self, buttons: MouseButtons) -> bool;
+}
+
+/// Events decoded by the PS/2 mouse interrupt handler, waiting to be read
+static EVENT_QUEUE: RingBuffer<MouseEvent, [Option<MouseEvent>; 128]> =
+    RingBuffer::new([None; 128]);
+
+/// The PS/2 packet decoder. This is locked by the interrupt handler, so must only be locked
+/// elsewhere with interrupts disabled
+static DECODER: Mutex<Ps2MouseDecoder> = Mutex::new(Ps2MouseDecoder::new());
+
+/// Handles an interrupt from the PS/2 mouse, decoding the received byte and queueing the
+/// resulting events, if any
+fn handle_ps2_interrupt() {
+    // The status tells which port the data came from, so it has to be read first
+    let port = ps2::io::output_port_unlocked();
+    let data = ps2::io::read_data_unlocked();
+    let mut decoder = DECODER.lock();
+
+    // Only a mouse being plugged in can be told apart from its packets, whose bytes can take any
+    // value, so a mouse never reports being unplugged
+    if decoder.plugged_in(data) {
+        hotplug::report(port, ps2::SELF_TEST_PASSED);
+    } else {
+        decoder.decode(data, |event| {
+            // If nobody is reading events, the queue may fill up, in which case the event is
+            // dropped
+            let _ = EVENT_QUEUE.push(event);
+        });
+    }
+}
+
+/// Handles interface to a PS/2 mouse, if available
+pub struct Ps2Mouse<'a> {
+    device: &'a mut Device,
+    protocol: Ps2MouseProtocol,
+}
+
+impl<'a> Ps2Mouse<'a> {
+    /// Creates a new Ps2Mouse from the given PS/2 device
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let device = drivers::ps2::CONTROLLER.mouse().unwrap();
+    /// let mut mouse = Ps2Mouse::new(device);
+    /// ```
+    pub fn new(device: &'a mut Device) -> Self {
+        Ps2Mouse { device, protocol: Ps2MouseProtocol::Standard }
+    }
+
+    /// The packet format this mouse reports in. This is known once the mouse is enabled.
+    pub fn protocol(&self) -> Ps2MouseProtocol {
+        self.protocol
+    }
+
+    /// Sets how many times per second this mouse samples its motion. Must be one of [SAMPLE_RATES].
+    #[allow(dead_code)] // Part of API
+    pub fn set_sample_rate(&mut self, rate: u8) -> Result<(), Ps2MouseError> {
+        if !SAMPLE_RATES.contains(&rate) {
+            return Err(Ps2MouseError::InvalidSampleRate(rate));
+        }
+
+        self.reconfigure(|device| configure(device, DeviceDataCommand::SetSampleRate, rate))
+    }
+
+    /// Sets the resolution this mouse reports its motion in
+    #[allow(dead_code)] // Part of API
+    pub fn set_resolution(&mut self, resolution: Ps2MouseResolution) -> Result<(), Ps2MouseError> {
+        self.reconfigure(|device| {
+            configure(device, DeviceDataCommand::SetResolution, resolution as u8)
+        })
+    }
+
+    /// Runs the given configuration. If the mouse is streaming packets, it is paused meanwhile, so
+    /// that its packets can't be mistaken for the replies to the configuration's commands.
+    fn reconfigure<F>(&mut self, f: F) -> Result<(), Ps2MouseError>
+        where F: FnOnce(&mut Device) -> Result<(), Ps2MouseError>
+    {
+        let streaming = self.device.state == DeviceState::Enabled;
+
+        if streaming {
+            interrupts::without_interrupts(|| self.device.set_interrupts(false))?;
+            self.device.command(DeviceCommand::DisableScanning)?;
+            io::flush_output()?;
+        }
+
+        f(self.device)?;
+
+        if streaming {
+            // Anything received before the pause may have been part of a packet
+            interrupts::without_interrupts(|| DECODER.lock().resync());
+
+            if self.device.command(DeviceCommand::EnableScanning)? != ps2::ACK {
+                return Err(Ps2MouseError::ScanningEnableFailed);
+            }
+
+            interrupts::without_interrupts(|| self.device.set_interrupts(true))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Mouse for Ps2Mouse<'a> {
+    type Error = Ps2MouseError;
+
+    fn enable(&mut self) -> Result<(), Ps2MouseError> {
+        self.device.enable()?;
+
+        if self.device.state != DeviceState::Enabled {
+            return Err(Ps2MouseError::MouseEnableFailed);
+        }
+
+        // A mouse which stopped answering is reset and set up once more
+        self.protocol = match set_up(self.device) {
+            Err(Ps2MouseError::ReadError(error)) if error.is_wedged() => {
+                self.device.recover()?;
+                set_up(self.device)?
+            }
+            result => result?,
+        };
+
+        // From here on, data from the mouse is received by the interrupt handler
+        irq::register(self.device.irq(), handle_ps2_interrupt)
+            .map_err(Ps2MouseError::IrqUnavailable)?;
+        interrupts::without_interrupts(|| self.device.set_interrupts(true))?;
+
+        Ok(())
+    }
+
+    fn disable(&mut self) -> Result<(), Ps2MouseError> {
+        interrupts::without_interrupts(|| self.device.set_interrupts(false))?;
+        irq::unregister(self.device.irq()).map_err(Ps2MouseError::IrqUnavailable)?;
+
+        self.device.disable()?;
+
+        Ok(())
+    }
+
+    fn read_event(&mut self) -> Result<Option<MouseEvent>, Self::Error> {
+        let protocol = &mut self.protocol;
+        self.device.handle_hotplug(|device| -> Result<(), Ps2MouseError> {
+            *protocol = set_up(device)?;
+            Ok(())
+        })?;
+
+        match self.device.state {
+            DeviceState::Enabled => Ok(EVENT_QUEUE.pop()),
+            // Nothing can be received until the mouse is plugged back in
+            DeviceState::Detached => Ok(None),
+            _ => Err(Ps2MouseError::MouseDisabled),
+        }
+    }
+
+    fn pressed(&self, buttons: MouseButtons) -> bool {
+        interrupts::without_interrupts(|| DECODER.lock().buttons.contains(buttons))
+    }
+}
+
+/// Enables the extensions the given mouse supports, sets the default sample rate and enables
+/// scanning, returning the protocol the mouse reports in
+fn set_up(device: &mut Device) -> Result<Ps2MouseProtocol, Ps2MouseError> {
+    let protocol = detect_protocol(device)?;
+    configure(device, DeviceDataCommand::SetSampleRate, DEFAULT_SAMPLE_RATE)?;
+
+    interrupts::without_interrupts(|| DECODER.lock().set_protocol(protocol));
+
+    if device.command(DeviceCommand::EnableScanning)? != ps2::ACK {
+        return Err(Ps2MouseError::ScanningEnableFailed);
+    }
+
+    Ok(protocol)
+}
+
+/// Enables the IntelliMouse extensions if the given mouse supports them, returning the protocol it
+/// reports in afterwards. Each extension is unlocked by setting a magic sequence of sample rates,
+/// after which the mouse identifies as supporting it.
+fn detect_protocol(device: &mut Device) -> Result<Ps2MouseProtocol, Ps2MouseError> {
+    let mut protocol = Ps2MouseProtocol::Standard;
+
+    for &(rates, device_type, extension) in [
+        ([200, 100, 80], DeviceType::WheelMouse, Ps2MouseProtocol::IntelliMouse),
+        ([200, 200, 80], DeviceType::FiveButtonMouse, Ps2MouseProtocol::IntelliMouseExplorer),
+    ].iter() {
+        for &rate in rates.iter() {
+            configure(device, DeviceDataCommand::SetSampleRate, rate)?;
+        }
+
+        if device.identify()? != device_type {
+            break;
+        }
+
+        device.device_type = device_type;
+        protocol = extension;
+    }
+
+    Ok(protocol)
+}
+
+/// Sends a command with data to the given mouse, expecting it to be acknowledged
+fn configure(device: &mut Device, cmd: DeviceDataCommand, data: u8) -> Result<(), Ps2MouseError> {
+    if device.command_data(cmd, data)? != ps2::ACK {
+        return Err(Ps2MouseError::ConfigurationFailed);
+    }
+
+    Ok(())
+}
+
+/// Decodes the PS/2 mouse byte stream into [MouseEvent]s, keeping track of which buttons are
+/// currently pressed
+struct Ps2MouseDecoder {
+    protocol: Ps2MouseProtocol,
+    packet: [u8; 4],
+    received: usize,
+    buttons: MouseButtons,
+}
+
+impl Ps2MouseDecoder {
+    const fn new() -> Self {
+        Ps2MouseDecoder {
+            protocol: Ps2MouseProtocol::Standard,
+            packet: [0; 4],
+            received: 0,
+            buttons: MouseButtons { bits: 0 },
+        }
+    }
+
+    fn set_protocol(&mut self, protocol: Ps2MouseProtocol) {
+        self.protocol = protocol;
+        self.resync();
+    }
+
+    /// Discards any partially received packet
+    fn resync(&mut self) {
+        self.received = 0;
+    }
+
+    /// Returns `true` if the given byte completes the self-test result and ID a mouse sends once
+    /// plugged in, dropping both. As 0xAA could also start a packet, it is only known once the ID
+    /// of 0x00 follows, which a packet's X movement could only match by chance.
+    fn plugged_in(&mut self, data: u8) -> bool {
+        if self.received == 1 && self.packet[0] == ps2::SELF_TEST_PASSED && data == 0x00 {
+            // The mouse is back to reporting standard packets
+            self.set_protocol(Ps2MouseProtocol::Standard);
+            self.buttons = MouseButtons::empty();
+            return true;
+        }
+
+        false
+    }
+
+    /// Feeds a single byte received from the mouse into this decoder, passing the resulting events
+    /// to `emit` once a full packet has been received
+    fn decode<F: FnMut(MouseEvent)>(&mut self, data: u8, emit: F) {
+        if let Some(packet) = self.feed(data) {
+            self.emit_events(&packet, emit);
+        }
+    }
+
+    /// Feeds a single byte into this decoder, returning the packet once all of its bytes are
+    /// received
+    fn feed(&mut self, data: u8) -> Option<Ps2MousePacket> {
+        // Bit 3 of the first byte is always set, which is how a lost byte is noticed. The stream
+        // is resynchronized by dropping bytes until one which could be a first byte arrives.
+        if self.received == 0 && data & (1 << 3) == 0 {
+            return None;
+        }
+
+        self.packet[self.received] = data;
+        self.received += 1;
+
+        if self.received < self.protocol.packet_len() {
+            return None;
+        }
+
+        self.received = 0;
+        Some(Ps2MousePacket::parse(&self.packet, self.protocol))
+    }
+
+    /// Emits the events describing the changes the given packet reports
+    fn emit_events<F: FnMut(MouseEvent)>(&mut self, packet: &Ps2MousePacket, mut emit: F) {
+        let pressed = packet.buttons - self.buttons;
+        let released = self.buttons - packet.buttons;
+        self.buttons = packet.buttons;
+
+        for &button in [
+            MouseButtons::LEFT,
+            MouseButtons::RIGHT,
+            MouseButtons::MIDDLE,
+            MouseButtons::BUTTON_4,
+            MouseButtons::BUTTON_5,
+        ].iter() {
+            if pressed.contains(button) {
+                emit(MouseEvent::Press(button));
+            } else if released.contains(button) {
+                emit(MouseEvent::Release(button));
+            }
+        }
+
+        if packet.dx != 0 || packet.dy != 0 {
+            emit(MouseEvent::Motion { dx: packet.dx, dy: packet.dy });
+        }
+
+        if packet.scroll != 0 {
+            emit(MouseEvent::Scroll(packet.scroll));
+        }
+    }
+}
+
+/// Represents a full packet received from a PS/2 mouse
+struct Ps2MousePacket {
+    buttons: MouseButtons,
+    dx: i16,
+    dy: i16,
+    scroll: i8,
+}
+
+impl Ps2MousePacket {
+    /// Parses a packet of the given protocol. Motion is reported as 9-bit two's complement, with
+    /// the sign bits in the first byte.
+    fn parse(packet: &[u8; 4], protocol: Ps2MouseProtocol) -> Self {
+        let flags = packet[0];
+        let mut buttons = MouseButtons::from_bits_truncate(flags & 0b111);
+
+        let dx = Self::motion(packet[1], flags & (1 << 4) != 0, flags & (1 << 6) != 0);
+        let dy = Self::motion(packet[2], flags & (1 << 5) != 0, flags & (1 << 7) != 0);
+
+        let scroll = match protocol {
+            Ps2MouseProtocol::Standard => 0,
+            Ps2MouseProtocol::IntelliMouse => packet[3] as i8,
+            Ps2MouseProtocol::IntelliMouseExplorer => {
+                buttons.set(MouseButtons::BUTTON_4, packet[3] & (1 << 4) != 0);
+                buttons.set(MouseButtons::BUTTON_5, packet[3] & (1 << 5) != 0);
+
+                // Only the low 4 bits hold the scroll amount, so they are sign extended
+                ((packet[3] << 4) as i8) >> 4
+            }
+        };
+
+        Ps2MousePacket { buttons, dx, dy, scroll }
+    }
+
+    /// Combines a motion byte with its sign bit. Motion which overflowed can't be trusted, so it is
+    /// dropped.
+    fn motion(value: u8, negative: bool, overflow: bool) -> i16 {
+        match (negative, overflow) {
+            (_, true) => 0,
+            (true, _) => value as i16 - 0x100,
+            (false, _) => value as i16,
+        }
+    }
+}
+
+impl From<Ps2Error> for Ps2MouseError {
+    fn from(error: Ps2Error) -> Self {
+        Ps2MouseError::ReadError(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use drivers::ps2::fake::FakePs2;
+    use io::mock;
+    use super::*;
+
+    /// Decodes every byte of the given stream, as the interrupt handler would
+    fn decode(protocol: Ps2MouseProtocol, stream: &[u8]) -> Vec<MouseEvent> {
+        let mut decoder = Ps2MouseDecoder::new();
+        decoder.set_protocol(protocol);
+
+        let mut events = Vec::new();
+        for &data in stream {
+            decoder.decode(data, |event| events.push(event));
+        }
+
+        events
+    }
+
+    /// A mouse on the second port, which acknowledges every command and identifies as `id` once
+    /// the IntelliMouse sequence of sample rates is set
+    fn fake_mouse(id: u8) -> FakePs2 {
+        FakePs2::new()
+            .acks(DeviceDataCommand::SetSampleRate as u8)
+            .acks(200)
+            .acks(100)
+            .acks(80)
+            .device_replies(DeviceCommand::Identify as u8, &[id])
+    }
+
+    kernel_test! {
+        fn decodes_standard_packet() {
+            let events = decode(Ps2MouseProtocol::Standard, &[0b0000_1001, 5, 3]);
+
+            assert_eq!(events, [
+                MouseEvent::Press(MouseButtons::LEFT),
+                MouseEvent::Motion { dx: 5, dy: 3 },
+            ]);
+        }
+
+        fn decodes_negative_motion() {
+            let events = decode(Ps2MouseProtocol::Standard, &[0b0011_1000, 0xFB, 0xFF]);
+
+            assert_eq!(events, [MouseEvent::Motion { dx: -5, dy: -1 }]);
+        }
+
+        fn drops_overflowed_motion() {
+            let events = decode(Ps2MouseProtocol::Standard, &[0b0100_1000, 0xFF, 2]);
+
+            assert_eq!(events, [MouseEvent::Motion { dx: 0, dy: 2 }]);
+        }
+
+        fn reports_button_changes_once() {
+            let events = decode(Ps2MouseProtocol::Standard, &[
+                0b0000_1010, 0, 0,
+                0b0000_1010, 0, 0,
+                0b0000_1000, 0, 0,
+            ]);
+
+            assert_eq!(events, [
+                MouseEvent::Press(MouseButtons::RIGHT),
+                MouseEvent::Release(MouseButtons::RIGHT),
+            ]);
+        }
+
+        fn decodes_wheel_packet() {
+            let events = decode(Ps2MouseProtocol::IntelliMouse, &[0b0000_1000, 0, 0, 0xFF]);
+
+            assert_eq!(events, [MouseEvent::Scroll(-1)]);
+        }
+
+        fn decodes_five_button_packet() {
+            let events = decode(Ps2MouseProtocol::IntelliMouseExplorer, &[
+                0b0000_1100, 0, 0, 0b0001_0001,
+                0b0000_1000, 0, 0, 0b0010_1111,
+            ]);
+
+            assert_eq!(events, [
+                MouseEvent::Press(MouseButtons::MIDDLE),
+                MouseEvent::Press(MouseButtons::BUTTON_4),
+                MouseEvent::Scroll(1),
+                MouseEvent::Release(MouseButtons::MIDDLE),
+                MouseEvent::Release(MouseButtons::BUTTON_4),
+                MouseEvent::Press(MouseButtons::BUTTON_5),
+                MouseEvent::Scroll(-1),
+            ]);
+        }
+
+        fn resynchronizes_after_lost_byte() {
+            // The first byte of the first packet was lost
+            let events = decode(Ps2MouseProtocol::Standard, &[5, 3, 0b0000_1001, 0, 0]);
+
+            assert_eq!(events, [MouseEvent::Press(MouseButtons::LEFT)]);
+        }
+
+        fn notices_mouse_plugged_in() {
+            let mut decoder = Ps2MouseDecoder::new();
+            decoder.set_protocol(Ps2MouseProtocol::IntelliMouse);
+            decoder.buttons = MouseButtons::LEFT;
+
+            assert!(!decoder.plugged_in(0xAA));
+            decoder.decode(0xAA, |_| ());
+            assert!(decoder.plugged_in(0x00));
+
+            assert_eq!(decoder.protocol, Ps2MouseProtocol::Standard);
+            assert_eq!(decoder.received, 0);
+            assert!(decoder.buttons.is_empty());
+        }
+
+        fn packet_starting_like_self_test_is_decoded() {
+            let mut decoder = Ps2MouseDecoder::new();
+            let mut events = Vec::new();
+
+            for &data in [0xAA, 5, 0].iter() {
+                if !decoder.plugged_in(data) {
+                    decoder.decode(data, |event| events.push(event));
+                }
+            }
+
+            assert_eq!(events, [
+                MouseEvent::Press(MouseButtons::RIGHT),
+                MouseEvent::Motion { dx: 5, dy: 0 },
+            ]);
+        }
+
+        fn detects_wheel() {
+            let mut device = Device::new(ps2::DevicePort::Port2);
+            device.state = DeviceState::Available;
+
+            let (mock, protocol) = mock::run(fake_mouse(3).build(), || detect_protocol(&mut device));
+
+            assert_eq!(protocol, Ok(Ps2MouseProtocol::IntelliMouse));
+            // Every byte for the mouse is preceded by the command addressing the second port
+            assert_eq!(mock.writes_to(0x64), [0xD4; 14]);
+            assert_eq!(mock.writes_to(0x60), [
+                0xF3, 200, 0xF3, 100, 0xF3, 80, 0xF2,
+                0xF3, 200, 0xF3, 200, 0xF3, 80, 0xF2,
+            ]);
+        }
+
+        fn detects_five_buttons() {
+            let mut device = Device::new(ps2::DevicePort::Port2);
+            device.state = DeviceState::Available;
+
+            // Identifies as a wheel mouse first, and as a 5-button mouse once that is unlocked
+            let mock = fake_mouse(3).device_replies(DeviceCommand::Identify as u8, &[4]).build();
+            let (_, protocol) = mock::run(mock, || detect_protocol(&mut device));
+
+            assert_eq!(protocol, Ok(Ps2MouseProtocol::IntelliMouseExplorer));
+            assert_eq!(device.device_type, DeviceType::FiveButtonMouse);
+        }
+
+        fn rejects_unsupported_sample_rate() {
+            let mut device = Device::new(ps2::DevicePort::Port2);
+            let mut mouse = Ps2Mouse::new(&mut device);
+
+            assert_eq!(mouse.set_sample_rate(50), Err(Ps2MouseError::InvalidSampleRate(50)));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! # Fake PS/2 Controller
+//!
+//! Builds the [MockIo] the PS/2 driver tests run against: a controller whose status port reports
+//! when output is waiting on the data port, with the controller and its devices answering the
+//! commands they are scripted to.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! let mock = FakePs2::new()
+//!     .controller_replies(ControllerReturnCommand::TestController as u8, &[0x55])
+//!     .device_replies(DeviceCommand::Identify as u8, &[0xAB, 0x83])
+//!     .build();
+//! ```
+
+use alloc::vec::Vec;
+use drivers::ps2::ACK;
+use drivers::ps2::io;
+use io::mock::MockIo;
+
+/// The data port of the controller
+pub const DATA: u16 = 0x60;
+/// The command and status port of the controller
+pub const COMMAND: u16 = 0x64;
+
+/// A builder for a fake PS/2 controller, see the [module docs](self)
+pub struct FakePs2 {
+    mock: MockIo,
+}
+
+impl FakePs2 {
+    /// Creates a controller which answers nothing, with an empty data port
+    pub fn new() -> Self {
+        let mock = MockIo::new()
+            .port(DATA, 0)
+            .ready_flag(COMMAND, DATA, io::StatusFlags::OUTPUT_FULL.bits() as u32);
+
+        FakePs2 { mock }
+    }
+
+    /// Makes the controller answer the given command with `reply`
+    pub fn controller_replies(self, command: u8, reply: &[u8]) -> Self {
+        let mock = self.mock.respond(COMMAND, command as u32, DATA, &widen(reply));
+        FakePs2 { mock }
+    }
+
+    /// Makes the devices acknowledge the given byte, which is either a command or its data
+    pub fn acks(self, value: u8) -> Self {
+        self.device_replies(value, &[])
+    }
+
+    /// Makes the devices acknowledge the given byte, followed by `reply`. If the same byte is
+    /// scripted several times, the replies are used in turn, with the last one used from then on.
+    pub fn device_replies(self, value: u8, reply: &[u8]) -> Self {
+        let mut response = Vec::new();
+        response.push(ACK as u32);
+        response.extend(widen(reply));
+
+        let mock = self.mock.respond(DATA, value as u32, DATA, &response);
+        FakePs2 { mock }
+    }
+
+    /// Queues bytes which the devices send on their own, such as scancodes or replies to a single
+    /// command
+    pub fn sends(self, bytes: &[u8]) -> Self {
+        let mock = self.mock.queue(DATA, &widen(bytes));
+        FakePs2 { mock }
+    }
+
+    /// Gets the mock to run
+    pub fn build(self) -> MockIo {
+        self.mock
+    }
+}
+
+fn widen(bytes: &[u8]) -> Vec<u32> {
+    bytes.iter().map(|&byte| byte as u32).collect()
+}
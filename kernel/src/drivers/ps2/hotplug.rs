@@ -0,0 +1,164 @@
+//! # PS/2 Hotplug
+//!
+//! PS/2 has no way of announcing that a device was plugged in or out, but devices send the result
+//! of their self-test once powered up. The interrupt handlers of drivers pass on any byte which is
+//! received outside of a scancode or packet to [report], which records the change for the
+//! device's port. A device failing its self-test is taken to be unplugged, as it can't be used.
+//!
+//! Unplugging a device is otherwise not noticed. The error bytes a keyboard sends on a key
+//! detection error or an overrun don't change its attachment, as it is still there: its driver
+//! only drops the bytes it was decoding, see [is_device_error]. A mouse only reports being
+//! plugged in, as the bytes of its packets can take any value.
+//!
+//! Changes can't be handled by the interrupt handler itself, as setting a device up again takes
+//! commands whose replies would be received by that same handler. Instead, the driver owning the
+//! device handles them through [Device::handle_hotplug] the next time it is used, after which an
+//! event is queued for consumers to [read](read_event).
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! while let Some(event) = hotplug::read_event() {
+//!     match event {
+//!         HotplugEvent::Attached(port) => println!("Device plugged into {:?}", port),
+//!         HotplugEvent::Detached(port) => println!("Device unplugged from {:?}", port),
+//!     }
+//! }
+//! ```
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use drivers::ps2::{DevicePort, SELF_TEST_PASSED};
+use ring_buffer::RingBuffer;
+
+/// Sent by a device if its self-test failed
+const SELF_TEST_FAILED: [u8; 2] = [0xFC, 0xFD];
+/// Sent by a keyboard on a key detection error or an overrun of its buffer
+const DEVICE_ERROR: [u8; 2] = [0x00, 0xFF];
+
+/// No change is waiting to be handled
+const NO_CHANGE: usize = 0;
+
+/// The change waiting to be handled for each port, encoded with [HotplugChange::encode]
+static PENDING: [AtomicUsize; 2] = [AtomicUsize::new(NO_CHANGE), AtomicUsize::new(NO_CHANGE)];
+
+/// Handled changes, waiting to be read by consumers
+static EVENTS: RingBuffer<HotplugEvent, [Option<HotplugEvent>; 8]> = RingBuffer::new([None; 8]);
+
+/// A change noticed in a device, which its driver has to handle
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum HotplugChange {
+    /// The device passed its self-test, so was plugged in or reset itself. It has to be set up
+    /// again.
+    Attached,
+    /// The device failed its self-test, so can't be used until it is plugged in again
+    Detached,
+}
+
+impl HotplugChange {
+    /// Gets the change announced by a byte received outside of a scancode or packet, if any
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// assert_eq!(HotplugChange::from_byte(0xAA), Some(HotplugChange::Attached));
+    /// assert_eq!(HotplugChange::from_byte(0x1C), None);
+    /// ```
+    pub fn from_byte(data: u8) -> Option<Self> {
+        if data == SELF_TEST_PASSED {
+            Some(HotplugChange::Attached)
+        } else if SELF_TEST_FAILED.contains(&data) {
+            Some(HotplugChange::Detached)
+        } else {
+            None
+        }
+    }
+
+    fn encode(&self) -> usize {
+        match *self {
+            HotplugChange::Attached => 1,
+            HotplugChange::Detached => 2,
+        }
+    }
+
+    fn decode(value: usize) -> Option<HotplugChange> {
+        match value {
+            1 => Some(HotplugChange::Attached),
+            2 => Some(HotplugChange::Detached),
+            _ => None,
+        }
+    }
+}
+
+/// A handled change in a device, as seen by consumers
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum HotplugEvent {
+    /// A device was plugged into the given port and has been set up again
+    Attached(DevicePort),
+    /// The device in the given port was unplugged
+    Detached(DevicePort),
+}
+
+/// Records the change the given byte announces for the device in the given port, if any, returning
+/// `true` if it did. Called by interrupt handlers for bytes received outside of a scancode or
+/// packet. A later change replaces one which hasn't been handled yet.
+pub fn report(port: DevicePort, data: u8) -> bool {
+    match HotplugChange::from_byte(data) {
+        Some(change) => {
+            PENDING[index(port)].store(change.encode(), Ordering::SeqCst);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns `true` if the given byte is an error sent by a keyboard which is still plugged in, after
+/// which the bytes around it were lost
+pub fn is_device_error(data: u8) -> bool {
+    DEVICE_ERROR.contains(&data)
+}
+
+/// Takes the change waiting to be handled for the device in the given port, if any
+pub fn take(port: DevicePort) -> Option<HotplugChange> {
+    HotplugChange::decode(PENDING[index(port)].swap(NO_CHANGE, Ordering::SeqCst))
+}
+
+/// Queues an event for consumers. If nobody is reading events, the queue may fill up, in which
+/// case the event is dropped.
+pub fn publish(event: HotplugEvent) {
+    let _ = EVENTS.push(event);
+}
+
+/// Takes the next handled change, or returns `None` if none have occurred since the last read
+#[allow(dead_code)] // Part of API
+pub fn read_event() -> Option<HotplugEvent> {
+    EVENTS.pop()
+}
+
+fn index(port: DevicePort) -> usize {
+    match port {
+        DevicePort::Port1 => 0,
+        DevicePort::Port2 => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    kernel_test! {
+        fn classifies_bytes() {
+            assert_eq!(HotplugChange::from_byte(0xAA), Some(HotplugChange::Attached));
+            assert_eq!(HotplugChange::from_byte(0xFC), Some(HotplugChange::Detached));
+            assert_eq!(HotplugChange::from_byte(0x00), None);
+            assert_eq!(HotplugChange::from_byte(0xFF), None);
+            assert_eq!(HotplugChange::from_byte(0xF0), None);
+            assert_eq!(HotplugChange::from_byte(0x15), None);
+        }
+
+        fn errors_are_transient() {
+            assert!(is_device_error(0x00));
+            assert!(is_device_error(0xFF));
+            assert!(!is_device_error(0xAA));
+        }
+    }
+}
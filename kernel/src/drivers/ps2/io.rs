@@ -22,7 +22,6 @@ pub mod commands {
         TestController = 0xAA,
         TestPort1 = 0xAB,
         TestPort2 = 0xA9,
-        IdentifyDevice = 0xF2,
     }
 
     /// Represents a PS2 controller command with a data value
@@ -38,6 +37,7 @@ pub mod commands {
     #[derive(Copy, Clone, Debug)]
     #[repr(u8)]
     pub enum DeviceCommand {
+        Identify = 0xF2,
         EnableScanning = 0xF4,
         DisableScanning = 0xF5,
         SetDefaults = 0xF6,
@@ -49,7 +49,10 @@ pub mod commands {
     #[derive(Copy, Clone, Debug)]
     #[repr(u8)]
     pub enum DeviceDataCommand {
+        SetResolution = 0xE8,
+        SetLeds = 0xED,
         SetScancode = 0xF0,
+        SetSampleRate = 0xF3,
     }
 
     /// Sends a controller command without a return
@@ -78,14 +81,18 @@ pub mod commands {
     }
 }
 
+use drivers::ps2::DevicePort;
 use io::{Port, SynchronizedPort};
+use time::Deadline;
 
 pub static DATA_PORT: SynchronizedPort<u8> = unsafe { SynchronizedPort::new(0x60) };
 pub static STATUS_PORT: SynchronizedPort<u8> = unsafe { SynchronizedPort::new(0x64) };
 pub static COMMAND_PORT: SynchronizedPort<u8> = unsafe { SynchronizedPort::new(0x64) };
 
-/// The number of iterations before assuming no data to be read. Should be changed to a timeout as of #26
-pub const WAIT_TIMEOUT: u32 = 1000000;
+/// How long to wait for the controller to accept or send a byte, in milliseconds
+pub const TIMEOUT_MS: u64 = 100;
+/// How long a device may take to finish its self-test after being reset, in milliseconds
+pub const RESET_TIMEOUT_MS: u64 = 1000;
 
 bitflags! {
     pub struct StatusFlags: u8 {
@@ -95,53 +102,122 @@ bitflags! {
         const INPUT_FULL = 1 << 1;
         /// If the current output from the controller is from the second port
         const OUTPUT_PORT_2 = 1 << 5;
+        /// If a device didn't answer or send a full byte in time
+        const TIMEOUT_ERROR = 1 << 6;
+        /// If the current output was received with a parity error
+        const PARITY_ERROR = 1 << 7;
     }
 }
 
 /// Represents an error returned by PS/2
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Ps2Error {
-    NoData,
+    /// The controller didn't accept or send a byte in time
+    Timeout,
+    /// A byte was received with a parity error
+    ParityError,
+    /// The device hasn't been detected, so can't be used
     DeviceUnavailable,
+    /// The controller failed its self-test, answering with the given byte
+    ControllerTestFailed(u8),
+    /// A device answered with the given byte where something else was expected
+    UnexpectedResponse(u8),
+    /// A device kept asking for a command to be resent
+    TooManyResends,
+    /// The machine has no PS/2 controller
+    NoController,
 }
 
-/// Writes to the given port, or waits until available
+impl Ps2Error {
+    /// Returns `true` if this error means a device stopped answering properly, in which case it
+    /// may be recovered by resetting it
+    pub fn is_wedged(&self) -> bool {
+        match *self {
+            Ps2Error::Timeout | Ps2Error::ParityError | Ps2Error::UnexpectedResponse(_)
+                | Ps2Error::TooManyResends => true,
+            _ => false,
+        }
+    }
+}
+
+/// Writes to the given port once the controller is ready, or returns `Timeout` if it never is
 pub fn write(port: &mut Port<u8>, value: u8) -> Result<(), Ps2Error> {
-    loop {
-        // Check if the input status bit is empty
-        if can_write()? {
-            port.write(value);
-            break
+    let mut deadline = Deadline::after(TIMEOUT_MS);
+
+    // Wait until the input status bit is empty
+    while !can_write()? {
+        if deadline.passed() {
+            return Err(Ps2Error::Timeout);
         }
     }
 
+    port.write(value);
+
     Ok(())
 }
 
-/// Reads from the given port, returning an optional value. `NoData` returned if nothing could be read
+/// Reads from the given port, or returns `Timeout` if nothing could be read
 pub fn read(port: &mut Port<u8>) -> Result<u8, Ps2Error> {
-    for _ in 0..WAIT_TIMEOUT {
+    read_within(port, TIMEOUT_MS)
+}
+
+/// Reads from the given port, waiting up to the given amount of milliseconds for data
+pub fn read_within(port: &mut Port<u8>, timeout_ms: u64) -> Result<u8, Ps2Error> {
+    let mut deadline = Deadline::after(timeout_ms);
+
+    loop {
+        let status = read_status()?;
+
         // Check if the output status bit is full
-        if can_read()? {
-            return Ok(port.read());
+        if status.contains(StatusFlags::OUTPUT_FULL) {
+            // A bad byte still has to be read, to make room for the next one
+            let data = port.read();
+
+            return if status.contains(StatusFlags::PARITY_ERROR) {
+                Err(Ps2Error::ParityError)
+            } else if status.contains(StatusFlags::TIMEOUT_ERROR) {
+                Err(Ps2Error::Timeout)
+            } else {
+                Ok(data)
+            };
         }
-    }
 
-    Err(Ps2Error::NoData)
+        if deadline.passed() {
+            return Err(Ps2Error::Timeout);
+        }
+    }
 }
 
 /// Flushes the controller's output buffer
 pub fn flush_output() -> Result<(), Ps2Error> {
-    // Read until the output status bit is empty
+    let mut deadline = Deadline::after(TIMEOUT_MS);
+
+    // Read until the output status bit is empty. If there is no controller, it never is.
     DATA_PORT.with_lock(|mut data_port| {
         while can_read()? {
+            if deadline.passed() {
+                return Err(Ps2Error::Timeout);
+            }
+
             data_port.read();
         }
 
         Ok(())
-    })?;
+    })
+}
 
-    Ok(())
+/// Reads the data port without taking the lock of [DATA_PORT]. Meant for interrupt handlers, as code
+/// waiting for a device holds that lock with interrupts enabled, so waiting for it in an interrupt
+/// handler would deadlock.
+pub fn read_data_unlocked() -> u8 {
+    unsafe { Port::<u8>::new(0x60) }.read()
+}
+
+/// Gets the port of the device the next data will be read from, reading the status without taking
+/// the lock of [STATUS_PORT]. Meant for interrupt handlers, like [read_data_unlocked].
+pub fn output_port_unlocked() -> DevicePort {
+    let status = StatusFlags::from_bits_truncate(unsafe { Port::<u8>::new(0x64) }.read());
+    port_of(status)
 }
 
 /// Reads from the status port and returns the flags
@@ -159,6 +235,20 @@ pub fn can_read() -> Result<bool, Ps2Error> {
     read_status().map(|status| status.contains(StatusFlags::OUTPUT_FULL))
 }
 
+/// Gets the port of the device the next data will be read from
+#[allow(dead_code)] // Part of API
+pub fn output_port() -> Result<DevicePort, Ps2Error> {
+    read_status().map(port_of)
+}
+
+fn port_of(status: StatusFlags) -> DevicePort {
+    if status.contains(StatusFlags::OUTPUT_PORT_2) {
+        DevicePort::Port2
+    } else {
+        DevicePort::Port1
+    }
+}
+
 /// Returns true if output port bit is 0, meaning the next data will be read from the keyboard
 #[allow(dead_code)] // To be used by drivers interfacing with PS/2
 pub fn can_read_keyboard() -> Result<bool, Ps2Error> {
@@ -7,16 +7,35 @@
 //! For it to be initialized, `initialize` must be called on it. This sets up all attached devices.
 //!
 //! The [Device] handles interface to a single PS/2 device. Its state can be checked and toggled through `enable` and `disable`.
-//! Devices can be obtained from the controller through `device(DevicePort)` or `devices`.
+//! Devices are identified when the controller is initialized, so that drivers can bind to them by [DeviceType] wherever
+//! they are plugged in. They can be obtained from the controller through `keyboard`, `mouse`, `device(DevicePort)` or `devices`.
+//!
+//! Devices plugged in or out while in use are noticed by their drivers' interrupt handlers, and handled through the
+//! [hotplug] module.
 
+#[cfg(test)]
+pub mod fake;
+pub mod hotplug;
 pub mod io;
 
+use core::mem;
+use drivers::ps2::hotplug::{HotplugChange, HotplugEvent};
 use drivers::ps2::io::Ps2Error;
 use drivers::ps2::io::commands::{self, ControllerCommand, ControllerReturnCommand, ControllerDataCommand, DeviceCommand, DeviceDataCommand};
+use acpi::{self, fadt::Fadt};
+use interrupts::{self, irq};
+use io::Port;
 use spin::Mutex;
 
 pub const RESEND: u8 = 0xFE;
 pub const ACK: u8 = 0xFA;
+/// Sent by a device once it passed its self-test after a reset
+pub const SELF_TEST_PASSED: u8 = 0xAA;
+/// Returned by the controller if it passed its self-test
+pub const CONTROLLER_TEST_PASSED: u8 = 0x55;
+
+/// How many times a command is resent before the device is given up on
+const MAX_RESENDS: usize = 3;
 
 lazy_static! {
     pub static ref CONTROLLER: Mutex<Controller> = Mutex::new(Controller::new());
@@ -46,15 +65,74 @@ pub enum DeviceState {
     Available,
     /// The device has been enabled
     Enabled,
+    /// The device was enabled, but has been unplugged. It is set up again once plugged back in.
+    Detached,
 }
 
 /// Represents the port of a device
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum DevicePort {
-    /// The device is in the keyboard port
-    Keyboard,
-    /// The device is in the mouse port
-    Mouse,
+    /// The first port, usually used by a keyboard
+    Port1,
+    /// The second port, usually used by a mouse
+    Port2,
+}
+
+/// Represents the type of a device, as reported by the identify command
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DeviceType {
+    /// The device hasn't been identified, or reported an unknown ID
+    Unknown,
+    /// A mouse with 3 buttons
+    StandardMouse,
+    /// A mouse with 3 buttons and a scroll wheel (IntelliMouse)
+    WheelMouse,
+    /// A mouse with 5 buttons and a scroll wheel (IntelliMouse Explorer)
+    FiveButtonMouse,
+    /// An AT keyboard, which acknowledges identify without sending an ID
+    AtKeyboard,
+    /// A MF2 keyboard
+    Mf2Keyboard,
+    /// A MF2 keyboard whose ID is translated by the controller
+    TranslatedKeyboard,
+}
+
+impl DeviceType {
+    /// Gets the device type for the given ID bytes
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// assert_eq!(DeviceType::from_id(Some(0xAB), Some(0x83)), DeviceType::Mf2Keyboard);
+    /// assert_eq!(DeviceType::from_id(Some(0x03), None), DeviceType::WheelMouse);
+    /// ```
+    pub fn from_id(first: Option<u8>, second: Option<u8>) -> Self {
+        match (first, second) {
+            (Some(0x00), None) => DeviceType::StandardMouse,
+            (Some(0x03), None) => DeviceType::WheelMouse,
+            (Some(0x04), None) => DeviceType::FiveButtonMouse,
+            (None, None) => DeviceType::AtKeyboard,
+            (Some(0xAB), Some(0x83)) => DeviceType::Mf2Keyboard,
+            (Some(0xAB), Some(0x41)) | (Some(0xAB), Some(0xC1)) => DeviceType::TranslatedKeyboard,
+            _ => DeviceType::Unknown,
+        }
+    }
+
+    /// Returns `true` if this is any type of keyboard
+    pub fn is_keyboard(&self) -> bool {
+        match *self {
+            DeviceType::AtKeyboard | DeviceType::Mf2Keyboard | DeviceType::TranslatedKeyboard => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if this is any type of mouse
+    pub fn is_mouse(&self) -> bool {
+        match *self {
+            DeviceType::StandardMouse | DeviceType::WheelMouse | DeviceType::FiveButtonMouse => true,
+            _ => false,
+        }
+    }
 }
 
 /// Represents the PS2 master controller
@@ -67,8 +145,8 @@ impl Controller {
     fn new() -> Self {
         Controller {
             devices: (
-                Device::new(DevicePort::Keyboard),
-                Device::new(DevicePort::Mouse),
+                Device::new(DevicePort::Port1),
+                Device::new(DevicePort::Port2),
             ),
             config: ConfigFlags::empty(),
         }
@@ -78,6 +156,10 @@ impl Controller {
     pub fn initialize(&mut self) -> Result<(), Ps2Error> {
         info!("ps2c: initializing");
 
+        if !controller_present() {
+            return Err(Ps2Error::NoController);
+        }
+
         self.prepare_devices()?;
         debug!("ps2c: disabled devices");
 
@@ -85,9 +167,7 @@ impl Controller {
 
         self.initialize_config()?;
 
-        if !self.test_controller()? {
-            error!("ps2c: controller test failed");
-        }
+        self.test_controller()?;
 
         debug!("ps2c: testing devices");
         match self.test_devices()? {
@@ -121,15 +201,41 @@ impl Controller {
     }
 
     /// Gets the device for the given port
-    #[allow(dead_code)] // To be used by drivers interfacing with PS/2
     pub fn device(&mut self, port: DevicePort) -> &mut Device {
-        if port == DevicePort::Keyboard {
+        if port == DevicePort::Port1 {
             &mut self.devices.0
         } else {
             &mut self.devices.1
         }
     }
 
+    /// Takes the device in the given port out of the controller, for a driver to own without keeping
+    /// the controller locked. An unavailable device is left in its place.
+    pub fn take(&mut self, port: DevicePort) -> Device {
+        mem::replace(self.device(port), Device::new(port))
+    }
+
+    /// Gets the first device identified as a keyboard, whichever port it is in
+    pub fn keyboard(&mut self) -> Option<&mut Device> {
+        self.find(|device_type| device_type.is_keyboard())
+    }
+
+    /// Gets the first device identified as a mouse, whichever port it is in
+    pub fn mouse(&mut self) -> Option<&mut Device> {
+        self.find(|device_type| device_type.is_mouse())
+    }
+
+    /// Gets the first device whose type matches the given predicate
+    fn find<F: Fn(DeviceType) -> bool>(&mut self, predicate: F) -> Option<&mut Device> {
+        if predicate(self.devices.0.device_type) {
+            Some(&mut self.devices.0)
+        } else if predicate(self.devices.1.device_type) {
+            Some(&mut self.devices.1)
+        } else {
+            None
+        }
+    }
+
     /// Resets this controller's devices and prepares them for initialization
     fn prepare_devices(&mut self) -> Result<(), Ps2Error> {
         self.devices.0.disable()?;
@@ -160,8 +266,11 @@ impl Controller {
     }
 
     /// Tests this controller
-    fn test_controller(&self) -> Result<bool, Ps2Error> {
-        Ok(commands::send_ret(ControllerReturnCommand::TestController)? == 0x55)
+    fn test_controller(&self) -> Result<(), Ps2Error> {
+        match commands::send_ret(ControllerReturnCommand::TestController)? {
+            CONTROLLER_TEST_PASSED => Ok(()),
+            result => Err(Ps2Error::ControllerTestFailed(result)),
+        }
     }
 
     /// Tests all of this controller's devices
@@ -180,18 +289,23 @@ impl Controller {
         Ok((first_supported, second_supported))
     }
 
-    /// Resets all devices and returns the count available
+    /// Resets and identifies all devices and returns the count available
     fn reset_devices(&mut self) -> Result<u8, Ps2Error> {
         let mut available_count = 0;
 
-        if self.devices.0.state == DeviceState::Available {
-            self.devices.0.reset()?;
-            available_count += 1;
-        }
-
-        if self.devices.1.state == DeviceState::Available {
-            self.devices.1.reset()?;
-            available_count += 1;
+        for device in [&mut self.devices.0, &mut self.devices.1].iter_mut() {
+            if device.state == DeviceState::Available {
+                match device.reset_and_identify() {
+                    Ok(_) => {
+                        debug!("ps2c: {:?} in {:?}", device.device_type, device.port);
+                        available_count += 1;
+                    }
+                    Err(error) => {
+                        warn!("ps2c: device in {:?} failed to reset: {:?}", device.port, error);
+                        device.state = DeviceState::Unavailable;
+                    }
+                }
+            }
         }
 
         Ok(available_count)
@@ -202,19 +316,22 @@ impl Controller {
 pub struct Device {
     pub state: DeviceState,
     pub port: DevicePort,
+    pub device_type: DeviceType,
 }
 
 impl Device {
-    const fn new(port: DevicePort) -> Self {
+    /// Creates a device for the given port, which is unavailable until tested
+    pub const fn new(port: DevicePort) -> Self {
         Device {
             state: DeviceState::Unavailable,
             port,
+            device_type: DeviceType::Unknown,
         }
     }
 
     /// Tests this device to see if it is available
     pub fn test(&mut self) -> Result<bool, Ps2Error> {
-        let cmd = if self.port == DevicePort::Mouse {
+        let cmd = if self.port == DevicePort::Port2 {
             ControllerReturnCommand::TestPort2
         } else {
             ControllerReturnCommand::TestPort1
@@ -232,7 +349,7 @@ impl Device {
 
     /// Enables this device
     pub fn enable(&mut self) -> Result<(), Ps2Error> {
-        let cmd = if self.port == DevicePort::Mouse {
+        let cmd = if self.port == DevicePort::Port2 {
             ControllerCommand::EnablePort2
         } else {
             ControllerCommand::EnablePort1
@@ -245,7 +362,7 @@ impl Device {
 
     /// Disables this device
     pub fn disable(&mut self) -> Result<(), Ps2Error> {
-        let cmd = if self.port == DevicePort::Mouse {
+        let cmd = if self.port == DevicePort::Port2 {
             ControllerCommand::DisablePort2
         } else {
             ControllerCommand::DisablePort1
@@ -256,57 +373,510 @@ impl Device {
         Ok(())
     }
 
-    /// Resets this device
+    /// Enables or disables interrupts from this device in the controller config. While enabled,
+    /// data sent by this device is received by its interrupt handler
+    pub fn set_interrupts(&mut self, enabled: bool) -> Result<(), Ps2Error> {
+        let flag = if self.port == DevicePort::Port2 {
+            ConfigFlags::PORT_INTERRUPT_2
+        } else {
+            ConfigFlags::PORT_INTERRUPT_1
+        };
+
+        let read = commands::send_ret(ControllerReturnCommand::ReadConfig)?;
+        let mut config = ConfigFlags::from_bits_truncate(read);
+        config.set(flag, enabled);
+
+        commands::send_data(ControllerDataCommand::WriteConfig, config.bits())
+    }
+
+    /// Runs the given closure, which sends commands to this device, with the other port disabled
+    /// and interrupts from both ports disabled in the controller config. The other device holds on
+    /// to what it has to send until its port is enabled again, so it can't be taken as a reply to
+    /// this device, and the replies can't be taken by an interrupt handler. The config is restored
+    /// afterwards.
+    pub fn exclusively<T, F: FnOnce(&mut Device) -> T>(&mut self, f: F) -> Result<T, Ps2Error> {
+        let other_clock = if self.port == DevicePort::Port2 {
+            ConfigFlags::PORT_CLOCK_1
+        } else {
+            ConfigFlags::PORT_CLOCK_2
+        };
+
+        let config = interrupts::without_interrupts(|| -> Result<ConfigFlags, Ps2Error> {
+            let read = commands::send_ret(ControllerReturnCommand::ReadConfig)?;
+            let config = ConfigFlags::from_bits_truncate(read);
+
+            let exclusive = (config | other_clock) - ConfigFlags::PORT_INTERRUPT_1 - ConfigFlags::PORT_INTERRUPT_2;
+            commands::send_data(ControllerDataCommand::WriteConfig, exclusive.bits())?;
+
+            Ok(config)
+        })?;
+
+        let result = f(self);
+        interrupts::without_interrupts(|| {
+            commands::send_data(ControllerDataCommand::WriteConfig, config.bits())
+        })?;
+
+        Ok(result)
+    }
+
+    /// Resets this device, waiting for its self-test to finish
     pub fn reset(&mut self) -> Result<(), Ps2Error> {
-        self.command(DeviceCommand::Reset)?;
+        match self.command(DeviceCommand::Reset)? {
+            ACK => (),
+            response => return Err(Ps2Error::UnexpectedResponse(response)),
+        }
+
+        io::DATA_PORT.with_lock(|mut data_port| {
+            match io::read_within(&mut data_port, io::RESET_TIMEOUT_MS)? {
+                // A mouse's ID follows, which is asked for separately. It can arrive a while after
+                // the self-test result, so is waited for, as it would otherwise be taken as the
+                // reply to the next command.
+                SELF_TEST_PASSED => read_id_byte(&mut data_port).map(|_| ()),
+                result => Err(Ps2Error::UnexpectedResponse(result)),
+            }
+        })
+    }
+
+    /// Resets this device and identifies it, leaving it with scanning disabled
+    pub fn reset_and_identify(&mut self) -> Result<(), Ps2Error> {
+        self.reset()?;
+
+        // A keyboard would otherwise send scancodes which could be mistaken for its ID
+        match self.command(DeviceCommand::DisableScanning)? {
+            ACK => (),
+            response => return Err(Ps2Error::UnexpectedResponse(response)),
+        }
+
+        self.device_type = self.identify()?;
 
         Ok(())
     }
 
+    /// Brings back a device which stopped answering properly, by dropping whatever it sent and
+    /// resetting it. Its port is left enabled if it was, but drivers have to set the device up
+    /// again.
+    pub fn recover(&mut self) -> Result<(), Ps2Error> {
+        if self.state == DeviceState::Unavailable {
+            return Err(Ps2Error::DeviceUnavailable);
+        }
+
+        warn!("ps2c: recovering device in {:?}", self.port);
+
+        // Whatever the device sent could be mistaken for the replies to the reset
+        io::flush_output()?;
+        self.reset_and_identify()
+    }
+
+    /// Identifies the type of this device. A device which doesn't acknowledge the command is
+    /// [DeviceType::Unknown].
+    pub fn identify(&mut self) -> Result<DeviceType, Ps2Error> {
+        if self.command(DeviceCommand::Identify)? != ACK {
+            return Ok(DeviceType::Unknown);
+        }
+
+        io::DATA_PORT.with_lock(|mut data_port| {
+            // Mice send a single byte, and keyboards two starting with 0xAB
+            let first = read_id_byte(&mut data_port)?;
+            let second = match first {
+                Some(0xAB) => read_id_byte(&mut data_port)?,
+                _ => None,
+            };
+
+            Ok(DeviceType::from_id(first, second))
+        })
+    }
+
+    /// Handles the device being plugged in or out since this was last called, as noticed by its
+    /// driver's interrupt handler. A device plugged back in is set up again by the given closure,
+    /// with its interrupts disabled so that its replies aren't taken by the interrupt handler.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// device.handle_hotplug(|device| set_up(device))?;
+    /// if device.state == DeviceState::Detached {
+    ///     return Ok(None);
+    /// }
+    /// ```
+    pub fn handle_hotplug<E, F>(&mut self, set_up: F) -> Result<(), E>
+        where E: From<Ps2Error>, F: FnOnce(&mut Device) -> Result<(), E>
+    {
+        match hotplug::take(self.port) {
+            Some(HotplugChange::Attached) => {
+                info!("ps2c: device attached to {:?}", self.port);
+
+                interrupts::without_interrupts(|| self.set_interrupts(false))?;
+                let result = set_up(self);
+                // The interrupt handler has to keep listening, to notice the device being plugged
+                // in again if this failed
+                interrupts::without_interrupts(|| self.set_interrupts(true))?;
+                result?;
+
+                self.state = DeviceState::Enabled;
+                hotplug::publish(HotplugEvent::Attached(self.port));
+            }
+            Some(HotplugChange::Detached) if self.state == DeviceState::Enabled => {
+                info!("ps2c: device detached from {:?}", self.port);
+
+                self.state = DeviceState::Detached;
+                hotplug::publish(HotplugEvent::Detached(self.port));
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// The IRQ line data from this device is received on
+    pub fn irq(&self) -> u8 {
+        if self.port == DevicePort::Port2 {
+            irq::lines::MOUSE
+        } else {
+            irq::lines::KEYBOARD
+        }
+    }
+
     /// Sends a command for this PS2 device and returns result
     pub fn command(&mut self, cmd: DeviceCommand) -> Result<u8, Ps2Error> {
         self.command_raw(cmd as u8)
     }
 
     /// Sends a command for this PS2 device with data and returns a result
-    #[allow(dead_code)] // To be used by drivers interfacing with PS/2
     pub fn command_data(&mut self, cmd: DeviceDataCommand, data: u8) -> Result<u8, Ps2Error> {
-        if self.state != DeviceState::Unavailable {
-            self.command_raw(cmd as u8).and_then(|result| match result {
-                ACK => {
-                    io::DATA_PORT.with_lock(|mut data_port| {
-                        io::write(&mut data_port, data as u8)?;
-                        io::read(&mut data_port)
-                    })
-                }
-                _ => Ok(result)
-            })
-        } else {
-            Err(Ps2Error::DeviceUnavailable)
+        // The data byte is sent like a command, so that it also reaches the second port
+        match self.command_raw(cmd as u8)? {
+            ACK => self.command_raw(data),
+            result => Ok(result),
         }
     }
 
     /// Sends a raw command code to this device
     fn command_raw(&mut self, cmd: u8) -> Result<u8, Ps2Error> {
-        if self.state != DeviceState::Unavailable {
-            // If second PS2 port, send context switch command
-            if self.port == DevicePort::Mouse {
+        if self.state == DeviceState::Unavailable {
+            return Err(Ps2Error::DeviceUnavailable);
+        }
+
+        for _ in 0..MAX_RESENDS + 1 {
+            // If second PS2 port, send context switch command. This is needed for every byte.
+            if self.port == DevicePort::Port2 {
                 commands::send(ControllerCommand::WriteInputPort2)?;
             }
 
-            io::DATA_PORT.with_lock(|mut data_port| {
-                for _ in 0..4 {
-                    io::write(&mut data_port, cmd)?;
-                    match io::read(&mut data_port) {
-                        Ok(RESEND) => continue,
-                        result => return result,
-                    }
-                }
+            let response = io::DATA_PORT.with_lock(|mut data_port| {
+                io::write(&mut data_port, cmd)?;
+                io::read(&mut data_port)
+            })?;
 
-                Err(Ps2Error::NoData)
-            })
-        } else {
-            Err(Ps2Error::DeviceUnavailable)
+            if response != RESEND {
+                return Ok(response);
+            }
+        }
+
+        Err(Ps2Error::TooManyResends)
+    }
+}
+
+/// Returns `false` if the ACPI FADT says the machine has no PS/2 controller. Without the FADT, the
+/// controller is assumed to be there.
+fn controller_present() -> bool {
+    acpi::tables()
+        .and_then(|tables| Fadt::find(tables))
+        .map_or(true, |fadt| fadt.has_8042())
+}
+
+/// Reads a byte of a device's ID, returning `None` if the device sent no more
+fn read_id_byte(data_port: &mut Port<u8>) -> Result<Option<u8>, Ps2Error> {
+    match io::read(data_port) {
+        Ok(byte) => Ok(Some(byte)),
+        Err(Ps2Error::Timeout) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use drivers::ps2::fake::{FakePs2, COMMAND, DATA};
+    use io::mock::{self, MockIo};
+    use super::*;
+
+    const KEYBOARD_ID: &[u8] = &[0xAB, 0x83];
+    const MOUSE_ID: &[u8] = &[0x00];
+
+    /// A controller with two working ports, with devices answering identify with the given IDs
+    fn fake_controller(first_id: &[u8], second_id: &[u8]) -> FakePs2 {
+        // Mice send their ID right after passing their self-test
+        let reset = |id: &[u8]| {
+            let mut response = Vec::new();
+            response.push(SELF_TEST_PASSED);
+            if id.len() == 1 {
+                response.extend_from_slice(id);
+            }
+            response
+        };
+
+        FakePs2::new()
+            .controller_replies(ControllerReturnCommand::ReadConfig as u8, &[0x43])
+            .controller_replies(ControllerReturnCommand::TestController as u8, &[0x55])
+            .controller_replies(ControllerReturnCommand::TestPort1 as u8, &[0x00])
+            .controller_replies(ControllerReturnCommand::TestPort2 as u8, &[0x00])
+            .device_replies(DeviceCommand::Reset as u8, &reset(first_id))
+            .device_replies(DeviceCommand::Reset as u8, &reset(second_id))
+            .acks(DeviceCommand::DisableScanning as u8)
+            .device_replies(DeviceCommand::Identify as u8, first_id)
+            .device_replies(DeviceCommand::Identify as u8, second_id)
+    }
+
+    kernel_test! {
+        fn initialize_sends_commands_in_order() {
+            let mut controller = Controller::new();
+            let mock = fake_controller(KEYBOARD_ID, MOUSE_ID).build();
+            let (mock, result) = mock::run(mock, || controller.initialize());
+
+            assert_eq!(result, Ok(()));
+            assert_eq!(mock.writes_to(COMMAND), [
+                0xAD, 0xA7, // Disable both ports
+                0x20, 0x60, // Read and write the config
+                0xAA, // Test the controller
+                0xAB, 0xA9, // Test both ports
+                0xD4, 0xD4, 0xD4, // Address the second port for its reset and identification
+            ]);
+            // The config is written back with interrupts and translation disabled, and then each
+            // device is reset, has scanning disabled, and is identified
+            assert_eq!(mock.writes_to(DATA), [0x00, 0xFF, 0xF5, 0xF2, 0xFF, 0xF5, 0xF2]);
+            assert!(controller.devices.0.state == DeviceState::Available);
+            assert!(controller.devices.1.state == DeviceState::Available);
+        }
+
+        fn initialize_leaves_output_empty() {
+            let mut controller = Controller::new();
+            let (_, empty) = mock::run(fake_controller(KEYBOARD_ID, MOUSE_ID).build(), || {
+                controller.initialize().unwrap();
+                !io::can_read().unwrap()
+            });
+
+            assert!(empty);
+        }
+
+        fn identifies_devices() {
+            let mut controller = Controller::new();
+            let mock = fake_controller(KEYBOARD_ID, &[0x03]).build();
+            mock::run(mock, || controller.initialize().unwrap());
+
+            assert_eq!(controller.devices.0.device_type, DeviceType::Mf2Keyboard);
+            assert_eq!(controller.devices.1.device_type, DeviceType::WheelMouse);
+        }
+
+        fn binds_by_device_type_when_swapped() {
+            let mut controller = Controller::new();
+            let mock = fake_controller(MOUSE_ID, KEYBOARD_ID).build();
+            mock::run(mock, || controller.initialize().unwrap());
+
+            assert_eq!(controller.keyboard().map(|device| device.port), Some(DevicePort::Port2));
+            assert_eq!(controller.mouse().map(|device| device.port), Some(DevicePort::Port1));
+            assert_eq!(controller.keyboard().map(|device| device.irq()), Some(irq::lines::MOUSE));
+        }
+
+        fn device_without_id_is_at_keyboard() {
+            let mut controller = Controller::new();
+            let mock = fake_controller(&[], MOUSE_ID).build();
+            mock::run(mock, || controller.initialize().unwrap());
+
+            assert_eq!(controller.devices.0.device_type, DeviceType::AtKeyboard);
+            assert_eq!(controller.keyboard().map(|device| device.port), Some(DevicePort::Port1));
+        }
+
+        fn classifies_ids() {
+            assert_eq!(DeviceType::from_id(Some(0x00), None), DeviceType::StandardMouse);
+            assert_eq!(DeviceType::from_id(Some(0x04), None), DeviceType::FiveButtonMouse);
+            assert_eq!(DeviceType::from_id(Some(0xAB), Some(0x41)), DeviceType::TranslatedKeyboard);
+            assert_eq!(DeviceType::from_id(Some(0xAB), Some(0xC1)), DeviceType::TranslatedKeyboard);
+            assert_eq!(DeviceType::from_id(Some(0xAB), None), DeviceType::Unknown);
+            assert_eq!(DeviceType::from_id(Some(0xAB), Some(0x12)), DeviceType::Unknown);
+            assert_eq!(DeviceType::from_id(None, None), DeviceType::AtKeyboard);
+        }
+
+        fn command_resends_when_asked() {
+            let mut device = Device::new(DevicePort::Port1);
+            device.state = DeviceState::Available;
+
+            let mock = fake_controller(KEYBOARD_ID, MOUSE_ID).sends(&[RESEND, ACK]).build();
+            let (mock, result) = mock::run(mock, || device.command(DeviceCommand::EnableScanning));
+
+            assert_eq!(result, Ok(ACK));
+            assert_eq!(mock.writes_to(DATA), [0xF4, 0xF4]);
+        }
+
+        fn command_data_reaches_second_port() {
+            let mut device = Device::new(DevicePort::Port2);
+            device.state = DeviceState::Available;
+
+            let mock = fake_controller(KEYBOARD_ID, MOUSE_ID).sends(&[ACK, ACK]).build();
+            let (mock, result) = mock::run(mock, || {
+                device.command_data(DeviceDataCommand::SetSampleRate, 100)
+            });
+
+            assert_eq!(result, Ok(ACK));
+            assert_eq!(mock.writes(), [(COMMAND, 0xD4), (DATA, 0xF3), (COMMAND, 0xD4), (DATA, 100)]);
+        }
+
+        fn command_gives_up_after_resends() {
+            let mut device = Device::new(DevicePort::Port1);
+            device.state = DeviceState::Available;
+
+            let mock = fake_controller(KEYBOARD_ID, MOUSE_ID).sends(&[RESEND; 4]).build();
+            let (mock, result) = mock::run(mock, || device.command(DeviceCommand::EnableScanning));
+
+            assert_eq!(result, Err(Ps2Error::TooManyResends));
+            assert_eq!(mock.writes_to(DATA), [0xF4; 4]);
+        }
+
+        fn failed_self_test_is_reported() {
+            let mut device = Device::new(DevicePort::Port1);
+            device.state = DeviceState::Available;
+
+            let mock = FakePs2::new().device_replies(DeviceCommand::Reset as u8, &[0xFC]).build();
+            let (_, result) = mock::run(mock, || device.reset());
+
+            assert_eq!(result, Err(Ps2Error::UnexpectedResponse(0xFC)));
+        }
+
+        fn failed_controller_test_is_reported() {
+            let controller = Controller::new();
+            let mock = FakePs2::new()
+                .controller_replies(ControllerReturnCommand::TestController as u8, &[0xFC])
+                .build();
+            let (_, result) = mock::run(mock, || controller.test_controller());
+
+            assert_eq!(result, Err(Ps2Error::ControllerTestFailed(0xFC)));
+        }
+
+        fn missing_controller_times_out() {
+            let mut controller = Controller::new();
+
+            // Without a controller, the ports read as all ones
+            let mock = MockIo::new().port(DATA, 0xFF).port(COMMAND, 0xFF);
+            let (_, result) = mock::run(mock, || controller.initialize());
+
+            assert_eq!(result, Err(Ps2Error::Timeout));
+        }
+
+        fn parity_error_is_reported() {
+            let mock = MockIo::new().port(DATA, 0).queue(COMMAND, &[0x81]);
+            let (mock, result) = mock::run(mock, || io::read(&mut io::DATA_PORT.lock()));
+
+            assert_eq!(result, Err(Ps2Error::ParityError));
+            // The bad byte is still read, so that the next one can be received
+            assert_eq!(mock.log().last(), Some(&mock::Access::Read(DATA, 0)));
+        }
+
+        fn recover_resets_and_identifies() {
+            let mut device = Device::new(DevicePort::Port1);
+            device.state = DeviceState::Enabled;
+
+            // The wedged keyboard left a partial scancode behind
+            let mock = fake_controller(KEYBOARD_ID, MOUSE_ID).sends(&[0xF0]).build();
+            let (mock, result) = mock::run(mock, || device.recover());
+
+            assert_eq!(result, Ok(()));
+            assert_eq!(mock.writes_to(DATA), [0xFF, 0xF5, 0xF2]);
+            assert_eq!(device.device_type, DeviceType::Mf2Keyboard);
+            assert!(device.state == DeviceState::Enabled);
+        }
+
+        fn attached_device_is_set_up_again() {
+            let mut device = Device::new(DevicePort::Port1);
+            device.state = DeviceState::Detached;
+
+            let mock = fake_controller(KEYBOARD_ID, MOUSE_ID)
+                .acks(DeviceCommand::EnableScanning as u8)
+                .build();
+            let (mock, (result, event)) = mock::run(mock, || {
+                hotplug::report(DevicePort::Port1, SELF_TEST_PASSED);
+                let result = device.handle_hotplug(|device| {
+                    device.command(DeviceCommand::EnableScanning).map(|_| ())
+                });
+
+                (result, hotplug::read_event())
+            });
+
+            assert_eq!(result, Ok(()));
+            // Interrupts are disabled in the config while the device is set up
+            assert_eq!(mock.writes_to(COMMAND), [0x20, 0x60, 0x20, 0x60]);
+            assert_eq!(mock.writes_to(DATA), [0x42, 0xF4, 0x43]);
+            assert!(device.state == DeviceState::Enabled);
+            assert_eq!(event, Some(HotplugEvent::Attached(DevicePort::Port1)));
+        }
+
+        fn detached_device_is_marked() {
+            let mut device = Device::new(DevicePort::Port2);
+            device.state = DeviceState::Enabled;
+
+            let (mock, (result, event)) = mock::run(MockIo::new(), || {
+                hotplug::report(DevicePort::Port2, 0xFC);
+                let result: Result<(), Ps2Error> = device.handle_hotplug(|_| {
+                    unreachable!("detached device set up")
+                });
+
+                (result, hotplug::read_event())
+            });
+
+            assert_eq!(result, Ok(()));
+            assert!(mock.log().is_empty());
+            assert!(device.state == DeviceState::Detached);
+            assert_eq!(event, Some(HotplugEvent::Detached(DevicePort::Port2)));
+        }
+
+        fn reset_waits_for_mouse_id() {
+            let mut device = Device::new(DevicePort::Port1);
+            device.state = DeviceState::Available;
+
+            let mock = FakePs2::new()
+                .device_replies(DeviceCommand::Reset as u8, &[SELF_TEST_PASSED, 0x00])
+                .build();
+            let (_, empty) = mock::run(mock, || {
+                device.reset().unwrap();
+                !io::can_read().unwrap()
+            });
+
+            // The ID isn't left behind to be taken as the reply to the next command
+            assert!(empty);
+        }
+
+        fn take_leaves_unavailable_device() {
+            let mut controller = Controller::new();
+            mock::run(fake_controller(KEYBOARD_ID, MOUSE_ID).build(), || controller.initialize().unwrap());
+
+            let mouse = controller.take(DevicePort::Port2);
+
+            assert_eq!(mouse.device_type, DeviceType::StandardMouse);
+            assert!(controller.devices.1.state == DeviceState::Unavailable);
+            assert!(controller.mouse().is_none());
+        }
+
+        fn command_to_unavailable_device_sends_nothing() {
+            let mut device = Device::new(DevicePort::Port2);
+            let mock = fake_controller(KEYBOARD_ID, MOUSE_ID).build();
+            let (mock, result) = mock::run(mock, || device.command(DeviceCommand::Reset));
+
+            assert_eq!(result, Err(Ps2Error::DeviceUnavailable));
+            assert!(mock.log().is_empty());
+        }
+
+        fn replays_controller_self_test() {
+            let trace = [
+                mock::Access::Read(COMMAND, 0x00),
+                mock::Access::Write(COMMAND, 0xAA),
+                mock::Access::Read(COMMAND, 0x01),
+                mock::Access::Read(DATA, 0x55),
+            ];
+
+            let controller = Controller::new();
+            let mock = MockIo::replay(&trace);
+            let (mock, passed) = mock::run(mock, || controller.test_controller());
+
+            assert_eq!(passed, Ok(()));
+            assert_eq!(mock.log(), trace);
         }
     }
 }
@@ -0,0 +1,659 @@
+//! # Serial Driver
+//!
+//! Drives the 16550 UARTs behind the COM1 to COM4 ports. Each port is accessed through its static,
+//! such as `COM1`, and must be initialized with a [SerialConfig] before use. Initialization sets
+//! the baud rate and FIFO, and runs a loopback self-test to check that the UART is really there.
+//!
+//! Data is received by polling, or once `enable_interrupts` is called, by an IRQ handler which
+//! queues it until read. Output is written through [TerminalOutput], using ANSI escape codes for
+//! colors and cursor movement, so a port can be plugged into `STDOUT` for headless runs.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! serial::COM1.write().init(SerialConfig::default())?;
+//! *terminal::STDOUT.write() = Stdout(&serial::COM1);
+//! ```
+
+use color::{Color, ColorPair};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use interrupts::{self, irq::{self, IrqError}};
+use io::Port;
+use ring_buffer::RingBuffer;
+use spin::RwLock;
+use terminal::*;
+use time::Deadline;
+
+pub static COM1: RwLock<SerialPort> = RwLock::new(SerialPort::new(ComPort::Com1));
+#[allow(dead_code)] // Part of API
+pub static COM2: RwLock<SerialPort> = RwLock::new(SerialPort::new(ComPort::Com2));
+#[allow(dead_code)] // Part of API
+pub static COM3: RwLock<SerialPort> = RwLock::new(SerialPort::new(ComPort::Com3));
+#[allow(dead_code)] // Part of API
+pub static COM4: RwLock<SerialPort> = RwLock::new(SerialPort::new(ComPort::Com4));
+
+/// The nominal resolution of a serial terminal. The real size is unknown, so this matches VGA.
+pub const RESOLUTION: Resolution = Resolution::new(80, 25);
+
+/// The rate of the UART's clock, which the baud rate divisor divides
+const BASE_BAUD_RATE: u32 = 115200;
+
+/// How long to wait for the UART to transmit or receive a byte, in milliseconds
+const WAIT_TIMEOUT_MS: u64 = 100;
+
+/// The byte sent during the loopback self-test
+const TEST_BYTE: u8 = 0xAE;
+
+/// Received bytes, queued by the IRQ handler until read, for each port
+static RECEIVE_QUEUES: [RingBuffer<u8, [Option<u8>; 256]>; 4] = [
+    RingBuffer::new([None; 256]),
+    RingBuffer::new([None; 256]),
+    RingBuffer::new([None; 256]),
+    RingBuffer::new([None; 256]),
+];
+
+/// Which ports have been initialized, so that the panic handler knows which it can use
+static INITIALIZED: [AtomicBool; 4] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+/// Which ports have receive interrupts enabled, read by the IRQ handlers
+static IRQ_ENABLED: [AtomicBool; 4] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+#[allow(dead_code)] // Dead constants for completeness
+mod registers {
+    //! Offsets of the UART's registers from its base port
+
+    pub const DATA: u16 = 0;
+    pub const INTERRUPT_ENABLE: u16 = 1;
+    /// The low byte of the baud rate divisor, in place of `DATA` while DLAB is set
+    pub const DIVISOR_LOW: u16 = 0;
+    /// The high byte of the baud rate divisor, in place of `INTERRUPT_ENABLE` while DLAB is set
+    pub const DIVISOR_HIGH: u16 = 1;
+    /// Read only
+    pub const INTERRUPT_ID: u16 = 2;
+    /// Write only
+    pub const FIFO_CONTROL: u16 = 2;
+    pub const LINE_CONTROL: u16 = 3;
+    pub const MODEM_CONTROL: u16 = 4;
+    pub const LINE_STATUS: u16 = 5;
+    pub const MODEM_STATUS: u16 = 6;
+    pub const SCRATCH: u16 = 7;
+}
+
+bitflags! {
+    struct LineStatus: u8 {
+        /// If a received byte can be read
+        const DATA_READY = 1 << 0;
+        const OVERRUN_ERROR = 1 << 1;
+        const PARITY_ERROR = 1 << 2;
+        const FRAMING_ERROR = 1 << 3;
+        const BREAK_INDICATOR = 1 << 4;
+        /// If the transmit holding register is empty (a byte can be written)
+        const TRANSMITTER_EMPTY = 1 << 5;
+        /// If nothing is being transmitted at all
+        const TRANSMITTER_IDLE = 1 << 6;
+        const FIFO_ERROR = 1 << 7;
+    }
+}
+
+bitflags! {
+    struct ModemControl: u8 {
+        const DATA_TERMINAL_READY = 1 << 0;
+        const REQUEST_TO_SEND = 1 << 1;
+        const OUT_1 = 1 << 2;
+        /// Connects the UART's interrupt to the IRQ line
+        const OUT_2 = 1 << 3;
+        /// Routes output back into input, for testing
+        const LOOPBACK = 1 << 4;
+    }
+}
+
+/// Set in the line control register to access the baud rate divisor
+const DIVISOR_LATCH_ACCESS: u8 = 1 << 7;
+/// Eight data bits, no parity and one stop bit
+const LINE_8N1: u8 = 0b11;
+
+/// Enables the FIFOs and clears both of them
+const FIFO_ENABLE_AND_CLEAR: u8 = 0b111;
+
+/// Interrupt when data is received
+const INTERRUPT_DATA_RECEIVED: u8 = 1 << 0;
+
+/// The base port of each serial port
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u16)]
+pub enum ComPort {
+    Com1 = 0x3F8,
+    Com2 = 0x2F8,
+    Com3 = 0x3E8,
+    Com4 = 0x2E8,
+}
+
+impl ComPort {
+    fn index(&self) -> usize {
+        match *self {
+            ComPort::Com1 => 0,
+            ComPort::Com2 => 1,
+            ComPort::Com3 => 2,
+            ComPort::Com4 => 3,
+        }
+    }
+
+    /// The IRQ line of this port. COM1 and COM3 share a line, as do COM2 and COM4.
+    fn irq(&self) -> u8 {
+        match *self {
+            ComPort::Com1 | ComPort::Com3 => irq::lines::COM1,
+            ComPort::Com2 | ComPort::Com4 => irq::lines::COM2,
+        }
+    }
+
+    /// The other port sharing this port's IRQ line
+    fn sharing(&self) -> ComPort {
+        match *self {
+            ComPort::Com1 => ComPort::Com3,
+            ComPort::Com2 => ComPort::Com4,
+            ComPort::Com3 => ComPort::Com1,
+            ComPort::Com4 => ComPort::Com2,
+        }
+    }
+
+    fn handler(&self) -> irq::IrqHandler {
+        match self.irq() {
+            irq::lines::COM1 => handle_com1_interrupt,
+            _ => handle_com2_interrupt,
+        }
+    }
+
+    fn register(&self, offset: u16) -> Port<u8> {
+        unsafe { Port::new(*self as u16 + offset) }
+    }
+}
+
+/// How many bytes the receive FIFO holds before raising an interrupt
+#[allow(dead_code)] // Dead variants for completeness
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum FifoTrigger {
+    Bytes1 = 0b00 << 6,
+    Bytes4 = 0b01 << 6,
+    Bytes8 = 0b10 << 6,
+    Bytes14 = 0b11 << 6,
+}
+
+/// The configuration a serial port is initialized with
+#[derive(Copy, Clone, Debug)]
+pub struct SerialConfig {
+    /// The baud rate, which must evenly divide 115200
+    pub baud_rate: u32,
+    /// The receive FIFO's trigger level, or `None` to disable the FIFOs
+    pub fifo: Option<FifoTrigger>,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            baud_rate: BASE_BAUD_RATE,
+            fifo: Some(FifoTrigger::Bytes14),
+        }
+    }
+}
+
+/// An error which occurred while using a serial port
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SerialError {
+    /// The baud rate does not evenly divide 115200
+    InvalidBaudRate(u32),
+    /// There is no UART at the port
+    NotPresent,
+    /// The UART did not echo the test byte back in loopback mode
+    LoopbackFailed,
+    /// The port has not been initialized
+    NotInitialized,
+    /// The UART did not become ready to transmit in time
+    TransmitTimeout,
+    /// The port's IRQ line could not be claimed
+    IrqUnavailable(IrqError),
+}
+
+/// Creates a second handle to the given port, bypassing the lock of its static, if the port has
+/// been initialized. The handle has its own terminal state, and receives by polling.
+///
+/// # Safety
+///
+/// Nothing else may be using the port, for instance because the kernel has panicked.
+pub unsafe fn steal(com: ComPort) -> Option<SerialPort> {
+    if INITIALIZED[com.index()].load(Ordering::Acquire) {
+        Some(SerialPort { initialized: true, ..SerialPort::new(com) })
+    } else {
+        None
+    }
+}
+
+/// A 16550 UART, along with the state of the terminal written through it
+pub struct SerialPort {
+    com: ComPort,
+    initialized: bool,
+    interrupts: bool,
+    /// Set if the UART didn't become ready to transmit the last byte in time
+    stalled: bool,
+    cursor: Point,
+    color: ColorPair,
+    /// The color last sent to the other end, if any
+    sent_color: Option<ColorPair>,
+}
+
+impl fmt::Debug for SerialPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SerialPort({:?})", self.com)
+    }
+}
+
+impl SerialPort {
+    pub const fn new(com: ComPort) -> Self {
+        SerialPort {
+            com,
+            initialized: false,
+            interrupts: false,
+            stalled: false,
+            cursor: Point::new(0, RESOLUTION.y - 1),
+            color: color!(White on Black),
+            sent_color: None,
+        }
+    }
+
+    /// Sets up the UART with the given configuration and tests it in loopback mode. Interrupts are
+    /// left disabled.
+    pub fn init(&mut self, config: SerialConfig) -> Result<(), SerialError> {
+        if config.baud_rate == 0 || BASE_BAUD_RATE % config.baud_rate != 0 {
+            return Err(SerialError::InvalidBaudRate(config.baud_rate));
+        }
+
+        // Nothing answers at the ports if there is no UART
+        if self.com.register(registers::LINE_STATUS).read() == 0xFF {
+            return Err(SerialError::NotPresent);
+        }
+
+        self.disable_interrupts()?;
+
+        let divisor = (BASE_BAUD_RATE / config.baud_rate) as u16;
+        self.com.register(registers::LINE_CONTROL).write(DIVISOR_LATCH_ACCESS);
+        self.com.register(registers::DIVISOR_LOW).write(divisor as u8);
+        self.com.register(registers::DIVISOR_HIGH).write((divisor >> 8) as u8);
+        self.com.register(registers::LINE_CONTROL).write(LINE_8N1);
+
+        let fifo_control = match config.fifo {
+            Some(trigger) => FIFO_ENABLE_AND_CLEAR | trigger as u8,
+            None => 0,
+        };
+        self.com.register(registers::FIFO_CONTROL).write(fifo_control);
+
+        self.self_test()?;
+
+        let modem_control = ModemControl::DATA_TERMINAL_READY | ModemControl::REQUEST_TO_SEND;
+        self.com.register(registers::MODEM_CONTROL).write(modem_control.bits());
+
+        self.initialized = true;
+        self.sent_color = None;
+        INITIALIZED[self.com.index()].store(true, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Sends a byte in loopback mode and checks that it is received back
+    fn self_test(&mut self) -> Result<(), SerialError> {
+        let loopback = ModemControl::LOOPBACK | ModemControl::REQUEST_TO_SEND | ModemControl::OUT_1;
+        self.com.register(registers::MODEM_CONTROL).write(loopback.bits());
+
+        self.com.register(registers::DATA).write(TEST_BYTE);
+
+        let mut deadline = Deadline::after(WAIT_TIMEOUT_MS);
+        let mut received = None;
+
+        while !deadline.passed() {
+            if self.line_status().contains(LineStatus::DATA_READY) {
+                received = Some(self.com.register(registers::DATA).read());
+                break;
+            }
+        }
+
+        if received == Some(TEST_BYTE) {
+            Ok(())
+        } else {
+            Err(SerialError::LoopbackFailed)
+        }
+    }
+
+    /// The port this UART is at
+    #[allow(dead_code)] // Part of API
+    pub fn com(&self) -> ComPort {
+        self.com
+    }
+
+    /// Returns `true` if the port has been successfully initialized
+    pub fn initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Starts receiving data through the IRQ handler, which queues it until read
+    #[allow(dead_code)] // Part of API
+    pub fn enable_interrupts(&mut self) -> Result<(), SerialError> {
+        if !self.initialized {
+            return Err(SerialError::NotInitialized);
+        }
+
+        if self.interrupts {
+            return Ok(());
+        }
+
+        let com = self.com;
+
+        interrupts::without_interrupts(|| {
+            // The handler is already registered if the port sharing the line uses it
+            if !IRQ_ENABLED[com.sharing().index()].load(Ordering::Acquire) {
+                irq::register(com.irq(), com.handler()).map_err(SerialError::IrqUnavailable)?;
+            }
+
+            IRQ_ENABLED[com.index()].store(true, Ordering::Release);
+
+            let modem_control = ModemControl::DATA_TERMINAL_READY
+                | ModemControl::REQUEST_TO_SEND
+                | ModemControl::OUT_2;
+            com.register(registers::MODEM_CONTROL).write(modem_control.bits());
+            com.register(registers::INTERRUPT_ENABLE).write(INTERRUPT_DATA_RECEIVED);
+
+            Ok(())
+        })?;
+
+        self.interrupts = true;
+
+        Ok(())
+    }
+
+    /// Stops receiving data through the IRQ handler. Data already queued can still be read.
+    pub fn disable_interrupts(&mut self) -> Result<(), SerialError> {
+        let com = self.com;
+
+        interrupts::without_interrupts(|| {
+            com.register(registers::INTERRUPT_ENABLE).write(0);
+
+            if !IRQ_ENABLED[com.index()].swap(false, Ordering::AcqRel) {
+                return Ok(());
+            }
+
+            let modem_control = ModemControl::DATA_TERMINAL_READY | ModemControl::REQUEST_TO_SEND;
+            com.register(registers::MODEM_CONTROL).write(modem_control.bits());
+
+            if !IRQ_ENABLED[com.sharing().index()].load(Ordering::Acquire) {
+                irq::unregister(com.irq()).map_err(SerialError::IrqUnavailable)?;
+            }
+
+            Ok(())
+        })?;
+
+        self.interrupts = false;
+
+        Ok(())
+    }
+
+    /// Reads a received byte, or returns `None` if there is none
+    pub fn read_byte(&mut self) -> Result<Option<u8>, SerialError> {
+        if !self.initialized {
+            return Err(SerialError::NotInitialized);
+        }
+
+        let queued = RECEIVE_QUEUES[self.com.index()].pop();
+        if queued.is_some() || self.interrupts {
+            return Ok(queued);
+        }
+
+        if self.line_status().contains(LineStatus::DATA_READY) {
+            Ok(Some(self.com.register(registers::DATA).read()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Waits until a byte is received and returns it. With interrupts enabled, this halts in
+    /// between checks.
+    #[allow(dead_code)] // Part of API
+    pub fn wait_byte(&mut self) -> Result<u8, SerialError> {
+        loop {
+            if !self.interrupts {
+                match self.read_byte()? {
+                    Some(byte) => return Ok(byte),
+                    None => continue,
+                }
+            }
+
+            // Check the queue with interrupts disabled so that a byte can't arrive between the
+            // check and the halt, which would leave it waiting until the next interrupt
+            interrupts::disable();
+
+            match self.read_byte() {
+                Ok(Some(byte)) => {
+                    interrupts::enable();
+                    return Ok(byte);
+                }
+                Ok(None) => interrupts::enable_and_halt(),
+                Err(error) => {
+                    interrupts::enable();
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Waits until the UART can transmit, and sends a byte. If the UART is stalled, this fails at
+    /// once rather than waiting again.
+    pub fn write_byte(&mut self, byte: u8) -> Result<(), SerialError> {
+        if !self.initialized {
+            return Err(SerialError::NotInitialized);
+        }
+
+        let mut deadline = Deadline::after(if self.stalled { 0 } else { WAIT_TIMEOUT_MS });
+
+        loop {
+            if self.line_status().contains(LineStatus::TRANSMITTER_EMPTY) {
+                self.com.register(registers::DATA).write(byte);
+                self.stalled = false;
+                return Ok(());
+            }
+
+            if deadline.passed() {
+                self.stalled = true;
+                return Err(SerialError::TransmitTimeout);
+            }
+        }
+    }
+
+    /// Returns `true` if the UART didn't become ready to transmit the last byte in time, for
+    /// instance because the other end holds up flow control. Until the UART is ready again,
+    /// writes fail without waiting.
+    pub fn stalled(&self) -> bool {
+        self.stalled
+    }
+
+    fn line_status(&self) -> LineStatus {
+        LineStatus::from_bits_truncate(self.com.register(registers::LINE_STATUS).read())
+    }
+
+    /// Sends raw text, such as escape codes, without tracking the cursor
+    fn send_fmt(&mut self, args: fmt::Arguments) -> Result<(), TerminalOutputError<()>> {
+        RawWriter(self).write_fmt(args).map_err(|_| TerminalOutputError::Other(()))
+    }
+
+    /// Sends the escape code selecting the given color, unless it is already selected
+    fn send_color(&mut self, color: ColorPair) -> Result<(), TerminalOutputError<()>> {
+        if self.sent_color == Some(color) {
+            return Ok(());
+        }
+
+        let (foreground, background) = (ansi_color(color.foreground), ansi_color(color.background));
+        self.send_fmt(format_args!("\x1b[{};{}m", 30 + foreground, 40 + background))?;
+        self.sent_color = Some(color);
+
+        Ok(())
+    }
+
+    /// Sends the escape code moving the cursor to the given point
+    fn send_cursor(&mut self, point: Point) -> Result<(), TerminalOutputError<()>> {
+        self.send_fmt(format_args!("\x1b[{};{}H", RESOLUTION.y - point.y, point.x + 1))
+    }
+}
+
+impl TerminalOutput<()> for SerialPort {
+    fn resolution(&self) -> Resolution {
+        RESOLUTION
+    }
+
+    fn color_supported(&self, _color: Color) -> bool {
+        true // Every color has a (bright) ANSI equivalent
+    }
+
+    fn cursor_pos(&self) -> Point {
+        self.cursor
+    }
+
+    fn set_cursor_pos(&mut self, cursor: Point) -> Result<(), TerminalOutputError<()>> {
+        if !self.in_bounds(cursor) {
+            return Err(TerminalOutputError::OutOfBounds(cursor));
+        }
+
+        self.send_cursor(cursor)?;
+        self.cursor = cursor;
+
+        Ok(())
+    }
+
+    fn color(&self) -> ColorPair {
+        self.color
+    }
+
+    fn set_color(&mut self, color: ColorPair) -> Result<(), TerminalOutputError<()>> {
+        self.color = color;
+        Ok(())
+    }
+
+    fn write_colored(&mut self, character: char, color: ColorPair) -> Result<(), TerminalOutputError<()>> {
+        if character == '\n' {
+            self.send_fmt(format_args!("\r\n"))?;
+        } else {
+            self.send_color(color)?;
+            self.send_fmt(format_args!("{}", character))?;
+
+            self.cursor.x += 1;
+            if self.cursor.x < RESOLUTION.x {
+                return Ok(());
+            }
+        }
+
+        // The other end scrolls by itself, so only the cursor needs to be tracked
+        self.cursor.x = 0;
+        self.cursor.y = self.cursor.y.saturating_sub(1);
+
+        Ok(())
+    }
+
+    fn set_char(&mut self, char: TerminalCharacter, point: Point) -> Result<(), TerminalOutputError<()>> {
+        if !self.in_bounds(point) {
+            return Err(TerminalOutputError::OutOfBounds(point));
+        }
+
+        // Save the cursor, write the character in place and restore it
+        self.send_fmt(format_args!("\x1b7"))?;
+        self.send_cursor(point)?;
+        self.send_color(char.color)?;
+        self.send_fmt(format_args!("{}\x1b8", char.character))
+    }
+
+    fn clear_line(&mut self, y: usize) -> Result<(), TerminalOutputError<()>> {
+        if !self.in_bounds(Point::new(0, y)) {
+            return Err(TerminalOutputError::OutOfBounds(Point::new(0, y)));
+        }
+
+        let color = self.color;
+        self.send_color(color)?;
+        self.send_fmt(format_args!("\x1b7"))?;
+        self.send_cursor(Point::new(0, y))?;
+        self.send_fmt(format_args!("\x1b[2K\x1b8"))
+    }
+
+    fn clear(&mut self) -> Result<(), TerminalOutputError<()>> {
+        let color = self.color;
+        self.send_color(color)?;
+        self.send_fmt(format_args!("\x1b[2J"))
+    }
+
+    fn scroll_down(&mut self, lines: usize) -> Result<(), TerminalOutputError<()>> {
+        let color = self.color;
+        self.send_color(color)?;
+        self.send_fmt(format_args!("\x1b[{}S", lines))
+    }
+}
+
+impl Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        self.write_string(s).map_err(|_| fmt::Error)
+    }
+}
+
+/// Writes text to a serial port as is, without the escapes of its terminal output
+pub struct RawWriter<'a>(pub &'a mut SerialPort);
+
+impl<'a> Write for RawWriter<'a> {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        for byte in s.bytes() {
+            self.0.write_byte(byte).map_err(|_| fmt::Error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts a color to its ANSI color number. Bright colors are numbered from 60 up.
+fn ansi_color(color: Color) -> u8 {
+    // The VGA colors swap red and blue, and cyan and brown, compared to ANSI
+    const ANSI_ORDER: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+    let color = color as u8;
+    let bright = if color >= 8 { 60 } else { 0 };
+
+    ANSI_ORDER[(color % 8) as usize] + bright
+}
+
+/// Handles an interrupt on the line shared by COM1 and COM3
+fn handle_com1_interrupt() {
+    receive(ComPort::Com1);
+    receive(ComPort::Com3);
+}
+
+/// Handles an interrupt on the line shared by COM2 and COM4
+fn handle_com2_interrupt() {
+    receive(ComPort::Com2);
+    receive(ComPort::Com4);
+}
+
+/// Queues everything the given port has received, if it has interrupts enabled
+fn receive(com: ComPort) {
+    if !IRQ_ENABLED[com.index()].load(Ordering::Acquire) {
+        return;
+    }
+
+    let mut line_status = com.register(registers::LINE_STATUS);
+    let mut data = com.register(registers::DATA);
+
+    while LineStatus::from_bits_truncate(line_status.read()).contains(LineStatus::DATA_READY) {
+        // If nobody is reading, the queue may fill up, in which case the byte is dropped
+        let _ = RECEIVE_QUEUES[com.index()].push(data.read());
+    }
+}
@@ -1,4 +1,6 @@
 use volatile::Volatile;
+#[cfg(test)]
+use alloc::string::{String, ToString};
 use core::{cmp, fmt};
 use core::convert::TryFrom;
 use core::ptr::Unique;
@@ -14,14 +16,15 @@ pub static WRITER: RwLock<VgaWriter> = RwLock::new(VgaWriter::new());
 /// The resolution of VGA
 pub const RESOLUTION: Resolution = Resolution::new(80, 25);
 
-/// Interface to VGA, allowing write
-pub struct VgaWriter {
-    buffer: Unique<VgaBuffer>,
+/// Interface to VGA, allowing write. Draws into the VGA text buffer unless given another
+/// [TextBuffer], such as a [MemoryBuffer] in tests.
+pub struct VgaWriter<B = VgaBuffer> {
+    buffer: B,
     cursor: Point,
     color: ColorPair,
 }
 
-impl fmt::Debug for VgaWriter {
+impl<B> fmt::Debug for VgaWriter<B> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "VgaWriter")
     }
@@ -30,18 +33,32 @@ impl fmt::Debug for VgaWriter {
 impl VgaWriter {
     pub const fn new() -> Self {
         VgaWriter {
-            buffer: unsafe { Unique::new_unchecked(0xb8000 as *mut _) },
+            buffer: VgaBuffer(unsafe { Unique::new_unchecked(0xb8000 as *mut _) }),
+            cursor: Point::new(0, RESOLUTION.y - 1),
+            color: color!(White on Black),
+        }
+    }
+}
+
+impl<B: TextBuffer> VgaWriter<B> {
+    /// Creates a writer drawing into the given buffer
+    #[allow(dead_code)] // Part of API
+    pub fn with_buffer(buffer: B) -> Self {
+        VgaWriter {
+            buffer,
             cursor: Point::new(0, RESOLUTION.y - 1),
             color: color!(White on Black),
         }
     }
 
-    fn buffer(&mut self) -> &mut VgaBuffer {
-        unsafe { self.buffer.as_mut() }
+    /// The buffer this writer draws into
+    #[allow(dead_code)] // Part of API
+    pub fn buffer(&self) -> &B {
+        &self.buffer
     }
 }
 
-impl TerminalOutput<()> for VgaWriter {
+impl<B: TextBuffer> TerminalOutput<()> for VgaWriter<B> {
 
     fn resolution(&self) -> Resolution {
         RESOLUTION
@@ -103,8 +120,6 @@ impl TerminalOutput<()> for VgaWriter {
     }
 
     fn set_char(&mut self, char: TerminalCharacter, point: Point) -> Result<(), TerminalOutputError<()>> {
-        let point = Point::new(point.x, RESOLUTION.y - 1 - point.y);
-
         if !self.color_supported(char.color.foreground) {
             return Err(TerminalOutputError::ColorUnsupported(char.color.foreground));
         }
@@ -116,9 +131,10 @@ impl TerminalOutput<()> for VgaWriter {
             return Err(TerminalOutputError::OutOfBounds(point));
         }
 
-        self.buffer().set_char(
+        // The buffer's rows start at the top
+        self.buffer.set_char(
             point.x,
-            point.y,
+            RESOLUTION.y - 1 - point.y,
             VgaChar::new(
                 VgaColor::from(char.color),
                 char.character as u8
@@ -130,7 +146,7 @@ impl TerminalOutput<()> for VgaWriter {
     fn clear_line(&mut self, y: usize) -> Result<(), TerminalOutputError<()>> {
         if self.in_bounds(Point::new(0, y)) {
             let background = self.color.background;
-            self.buffer().clear_row(y, background);
+            self.buffer.clear_row(RESOLUTION.y - 1 - y, background);
             Ok(())
         } else {
             Err(TerminalOutputError::OutOfBounds(Point::new(0, y)))
@@ -147,26 +163,29 @@ impl TerminalOutput<()> for VgaWriter {
 
     fn scroll_down(&mut self, amount: usize) -> Result<(), TerminalOutputError<()>> {
         let background = self.color.background;
-        self.buffer().scroll_down(amount, background);
+        self.buffer.scroll_down(amount, background);
 
         Ok(())
     }
 }
 
-/// Represents the complete VGA character buffer, containing a 2D array of VgaChar
-#[repr(C)]
-struct VgaBuffer([[Volatile<VgaChar>; RESOLUTION.x]; RESOLUTION.y]);
+/// A grid of [VgaChar]s the size of the VGA [RESOLUTION], which a [VgaWriter] draws into. Row 0
+/// is the top of the screen.
+pub trait TextBuffer {
+    fn char_at(&self, x: usize, y: usize) -> VgaChar;
 
-impl VgaBuffer {
-    pub fn set_char(&mut self, x: usize, y: usize, value: VgaChar) {
-        self.0[y][x].write(value);
-    }
+    fn set_char(&mut self, x: usize, y: usize, value: VgaChar);
 
-    pub fn scroll_down(&mut self, amount: usize, background_color: Color) {
-        // Shift lines left (up) by amount only if amount < Y resolution
-        // If amount is any more then the data will be cleared anyway
-        if cmp::min(amount, RESOLUTION.y) < RESOLUTION.y {
-            self.0.rotate_left(amount);
+    /// Moves every row up by the given amount, clearing the rows left at the bottom
+    fn scroll_down(&mut self, amount: usize, background_color: Color) {
+        // If amount is the Y resolution or more then everything will be cleared anyway
+        let amount = cmp::min(amount, RESOLUTION.y);
+
+        for y in amount..RESOLUTION.y {
+            for x in 0..RESOLUTION.x {
+                let character = self.char_at(x, y);
+                self.set_char(x, y - amount, character);
+            }
         }
 
         // Clear rows up to the amount
@@ -175,20 +194,64 @@ impl VgaBuffer {
         }
     }
 
-    pub fn clear_row(&mut self, y: usize, color: Color) {
+    fn clear_row(&mut self, y: usize, color: Color) {
         let blank = VgaChar::new(
             VgaColor::new(Color::Black, color),
             b' '
         );
 
         for x in 0..RESOLUTION.x {
-            self.0[y][x].write(blank);
+            self.set_char(x, y, blank);
         }
     }
 }
 
+/// The VGA text buffer at `0xb8000`
+pub struct VgaBuffer(Unique<[[Volatile<VgaChar>; RESOLUTION.x]; RESOLUTION.y]>);
+
+impl TextBuffer for VgaBuffer {
+    fn char_at(&self, x: usize, y: usize) -> VgaChar {
+        unsafe { self.0.as_ref()[y][x].read() }
+    }
+
+    fn set_char(&mut self, x: usize, y: usize, value: VgaChar) {
+        unsafe { self.0.as_mut()[y][x].write(value); }
+    }
+}
+
+/// A text buffer in plain memory, for drawing off screen
+#[cfg(test)]
+#[derive(Clone)]
+pub struct MemoryBuffer([[VgaChar; RESOLUTION.x]; RESOLUTION.y]);
+
+#[cfg(test)]
+impl MemoryBuffer {
+    /// Creates a buffer filled with spaces
+    pub fn new() -> Self {
+        let blank = VgaChar::new(VgaColor::new(Color::White, Color::Black), b' ');
+        MemoryBuffer([[blank; RESOLUTION.x]; RESOLUTION.y])
+    }
+
+    /// The characters of the given row, with trailing spaces trimmed
+    pub fn row(&self, y: usize) -> String {
+        let row: String = self.0[y].iter().map(|character| character.character as char).collect();
+        row.trim_right().to_string()
+    }
+}
+
+#[cfg(test)]
+impl TextBuffer for MemoryBuffer {
+    fn char_at(&self, x: usize, y: usize) -> VgaChar {
+        self.0[y][x]
+    }
+
+    fn set_char(&mut self, x: usize, y: usize, value: VgaChar) {
+        self.0[y][x] = value;
+    }
+}
+
 /// Represents a full character in the VGA buffer, with a character code, foreground and background
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[repr(C)]
 pub struct VgaChar {
     pub character: u8,
@@ -202,7 +265,7 @@ impl VgaChar {
 }
 
 /// Represents a VGA colour, with both a foreground and background
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
 #[repr(C)]
 pub struct VgaColor(u8);
 
@@ -231,3 +294,102 @@ impl TryFrom<VgaColor> for (Color, Color) {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn writer() -> VgaWriter<MemoryBuffer> {
+        VgaWriter::with_buffer(MemoryBuffer::new())
+    }
+
+    kernel_test! {
+        fn writes_from_top_left() {
+            let mut writer = writer();
+            writer.write_string("hello\nworld").unwrap();
+
+            assert_eq!(writer.buffer().row(0), "hello");
+            assert_eq!(writer.buffer().row(1), "world");
+            assert_eq!(writer.cursor_pos(), Point::new(5, RESOLUTION.y - 2));
+        }
+
+        fn wraps_long_lines() {
+            let mut writer = writer();
+            for _ in 0..RESOLUTION.x + 3 {
+                writer.write('x').unwrap();
+            }
+
+            assert_eq!(writer.buffer().row(0).len(), RESOLUTION.x);
+            assert_eq!(writer.buffer().row(1), "xxx");
+        }
+
+        fn scrolls_at_bottom() {
+            let mut writer = writer();
+            for line in 0..RESOLUTION.y + 1 {
+                writer.write_string(&line.to_string()).unwrap();
+                writer.new_line().unwrap();
+            }
+
+            // The first two lines have scrolled off, and the cursor stays on the last row
+            assert_eq!(writer.buffer().row(0), "2");
+            assert_eq!(writer.buffer().row(RESOLUTION.y - 2), RESOLUTION.y.to_string());
+            assert_eq!(writer.buffer().row(RESOLUTION.y - 1), "");
+            assert_eq!(writer.cursor_pos().y, 0);
+        }
+
+        fn backspace_moves_to_end_of_previous_line() {
+            let mut writer = writer();
+            writer.write_string("ab\n").unwrap();
+            writer.backspace().unwrap();
+            writer.backspace().unwrap();
+
+            assert_eq!(writer.cursor_pos(), Point::new(RESOLUTION.x - 2, RESOLUTION.y - 1));
+            assert_eq!(writer.buffer().row(0), "ab");
+
+            writer.set_cursor_pos(Point::new(2, RESOLUTION.y - 1)).unwrap();
+            writer.backspace().unwrap();
+            assert_eq!(writer.buffer().row(0), "a");
+        }
+
+        fn backspace_unavailable_at_top_left() {
+            let mut writer = writer();
+
+            assert_eq!(
+                writer.backspace(),
+                Err(TerminalOutputError::BackspaceUnavailable(BackspaceUnavailableCause::TopOfTerminal)),
+            );
+        }
+
+        fn clear_line_counts_from_bottom() {
+            let mut writer = writer();
+            writer.write_string("top\nsecond").unwrap();
+            writer.clear_line(RESOLUTION.y - 2).unwrap();
+
+            assert_eq!(writer.buffer().row(0), "top");
+            assert_eq!(writer.buffer().row(1), "");
+        }
+
+        fn set_char_rejects_out_of_bounds() {
+            let mut writer = writer();
+            let character = TerminalCharacter::new('x', color!(White on Black));
+            let point = Point::new(0, RESOLUTION.y);
+
+            assert_eq!(writer.set_char(character, point), Err(TerminalOutputError::OutOfBounds(point)));
+        }
+
+        fn scroll_down_clears_bottom_rows() {
+            let mut buffer = MemoryBuffer::new();
+            let character = VgaChar::new(VgaColor::new(Color::White, Color::Black), b'x');
+            buffer.set_char(0, 3, character);
+            buffer.set_char(0, RESOLUTION.y - 1, character);
+
+            buffer.scroll_down(2, Color::Black);
+            assert_eq!(buffer.row(1), "x");
+            assert_eq!(buffer.row(RESOLUTION.y - 3), "x");
+            assert_eq!(buffer.row(RESOLUTION.y - 1), "");
+
+            buffer.scroll_down(RESOLUTION.y + 1, Color::Black);
+            assert!((0..RESOLUTION.y).all(|y| buffer.row(y).is_empty()));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! # GDT
+//!
+//! The global descriptor table and task state segment. `boot.asm` loads a minimal GDT to get into
+//! long mode, which this replaces with one that also describes a TSS. The TSS's interrupt stack
+//! table gives the double fault handler a known good stack, so that a kernel stack overflow can be
+//! reported instead of causing a triple fault.
+
+use core::mem;
+use memory::stack::{self, StackError};
+use spin::Once;
+
+/// The index in the interrupt stack table of the stack used by the double fault handler
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// The size of the double fault handler's stack, in pages
+const DOUBLE_FAULT_STACK_PAGES: usize = 4;
+
+/// A 64 bit, present, ring 0 code segment
+const KERNEL_CODE_SEGMENT: u64 = (1 << 41) | (1 << 43) | (1 << 44) | (1 << 47) | (1 << 53);
+/// The type of an available 64 bit TSS in a system segment descriptor
+const TSS_AVAILABLE: u64 = 0b1001 << 40;
+const SEGMENT_PRESENT: u64 = 1 << 47;
+
+static TSS: Once<TaskStateSegment> = Once::new();
+static GDT: Once<Gdt> = Once::new();
+
+/// The 64 bit task state segment, which in long mode only holds stack pointers
+#[allow(dead_code)] // Dead fields for completeness
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    /// The stacks switched to when changing to rings 0 to 2
+    pub privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    /// The stacks which interrupt handlers can be set to run on, regardless of the current stack
+    pub interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    pub iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    pub fn new() -> Self {
+        TaskStateSegment {
+            reserved_1: 0,
+            privilege_stack_table: [0; 3],
+            reserved_2: 0,
+            interrupt_stack_table: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            iomap_base: mem::size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+/// The pointer to a descriptor table, as loaded by `lgdt`
+#[repr(C, packed)]
+struct DescriptorTablePointer {
+    limit: u16,
+    base: u64,
+}
+
+struct Gdt {
+    entries: [u64; 8],
+    len: usize,
+}
+
+impl Gdt {
+    /// Creates a GDT holding only the null descriptor
+    fn new() -> Self {
+        Gdt { entries: [0; 8], len: 1 }
+    }
+
+    /// Adds an entry, returning its segment selector
+    fn add_entry(&mut self, entry: u64) -> u16 {
+        assert!(self.len < self.entries.len(), "GDT is full");
+
+        let index = self.len;
+        self.entries[index] = entry;
+        self.len += 1;
+
+        (index * mem::size_of::<u64>()) as u16
+    }
+
+    /// Adds a descriptor for the given TSS, which takes up two entries, returning its selector
+    fn add_tss(&mut self, tss: &'static TaskStateSegment) -> u16 {
+        let base = tss as *const TaskStateSegment as u64;
+        let limit = (mem::size_of::<TaskStateSegment>() - 1) as u64;
+
+        let low = SEGMENT_PRESENT
+            | TSS_AVAILABLE
+            | (limit & 0xFFFF)
+            | (base & 0xFF_FFFF) << 16
+            | ((base >> 24) & 0xFF) << 56;
+
+        let selector = self.add_entry(low);
+        self.add_entry(base >> 32);
+
+        selector
+    }
+
+    fn load(&'static self) {
+        let pointer = DescriptorTablePointer {
+            limit: (self.len * mem::size_of::<u64>() - 1) as u16,
+            base: self.entries.as_ptr() as u64,
+        };
+
+        unsafe {
+            asm!("lgdt ($0)" :: "r"(&pointer) : "memory" : "volatile");
+        }
+    }
+}
+
+/// Allocates the interrupt stacks, and loads the new GDT and TSS. Must be called before the IDT is
+/// loaded, as the double fault handler relies on the interrupt stack table.
+pub fn init() -> Result<(), StackError> {
+    let double_fault_stack = stack::allocate(DOUBLE_FAULT_STACK_PAGES)?;
+
+    let tss = TSS.call_once(|| {
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack.top() as u64;
+        tss
+    });
+
+    let mut code_selector = 0;
+    let mut tss_selector = 0;
+
+    let gdt = GDT.call_once(|| {
+        let mut gdt = Gdt::new();
+        code_selector = gdt.add_entry(KERNEL_CODE_SEGMENT);
+        tss_selector = gdt.add_tss(tss);
+        gdt
+    });
+
+    gdt.load();
+
+    unsafe {
+        // Reload CS through a far return, as it can't be moved to directly
+        asm!("pushq $0; leaq 1f(%rip), %rax; pushq %rax; lretq; 1:"
+            :: "r"(code_selector as u64) : "rax", "memory" : "volatile");
+
+        asm!("ltr $0" :: "r"(tss_selector) :: "volatile");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! I/O APIC driver. The I/O APIC receives interrupts from devices (global system interrupts) and
+//! redirects them to local APICs as configured in its redirection table.
+
+use core::ptr;
+
+/// Offset of the register select register from the base address
+const REGISTER_SELECT: usize = 0x00;
+/// Offset of the register data window from the base address
+const REGISTER_WINDOW: usize = 0x10;
+
+/// The version register, which also holds the amount of redirection entries
+const VERSION: u8 = 0x01;
+/// The first register of the redirection table. Each entry takes up two registers
+const REDIRECTION_TABLE: u8 = 0x10;
+
+bitflags! {
+    pub struct RedirectionFlags: u64 {
+        /// If the interrupt signal is active low, rather than active high
+        const ACTIVE_LOW = 1 << 13;
+        /// If the interrupt signal is level triggered, rather than edge triggered
+        const LEVEL_TRIGGERED = 1 << 15;
+        /// If the interrupt is masked
+        const MASKED = 1 << 16;
+    }
+}
+
+/// An entry in an I/O APIC's redirection table, describing how one global system interrupt is
+/// delivered. Always uses fixed delivery in physical destination mode.
+#[derive(Copy, Clone, Debug)]
+pub struct RedirectionEntry {
+    pub vector: u8,
+    /// The APIC ID of the local APIC to deliver the interrupt to
+    pub destination: u8,
+    pub flags: RedirectionFlags,
+}
+
+impl RedirectionEntry {
+    fn bits(&self) -> u64 {
+        self.vector as u64 | self.flags.bits() | (self.destination as u64) << 56
+    }
+}
+
+/// Interface to an I/O APIC
+pub struct IoApic {
+    base: usize,
+    /// The first global system interrupt handled by this I/O APIC
+    gsi_base: u32,
+    /// The amount of redirection entries (and so global system interrupts) this I/O APIC handles
+    redirection_entries: u32,
+}
+
+impl IoApic {
+    /// Creates a new interface to the I/O APIC mapped at the given address, handling the global
+    /// system interrupts from `gsi_base` onwards.
+    ///
+    /// # Safety
+    ///
+    /// The address must be the identity mapped, uncached base of an I/O APIC
+    pub unsafe fn new(base: usize, gsi_base: u32) -> Self {
+        let mut io_apic = IoApic { base, gsi_base, redirection_entries: 0 };
+        io_apic.redirection_entries = ((io_apic.read(VERSION) >> 16) & 0xFF) + 1;
+
+        io_apic
+    }
+
+    /// Returns `true` if the given global system interrupt is handled by this I/O APIC
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.redirection_entries
+    }
+
+    /// Masks every interrupt handled by this I/O APIC
+    pub fn mask_all(&mut self) {
+        for index in 0..self.redirection_entries {
+            let gsi = self.gsi_base + index;
+            self.set_masked(gsi, true);
+        }
+    }
+
+    /// Sets the redirection entry for the given global system interrupt
+    ///
+    /// # Panics
+    ///
+    /// Panics if the global system interrupt is not handled by this I/O APIC
+    pub fn set_redirection(&mut self, gsi: u32, entry: RedirectionEntry) {
+        let register = self.redirection_register(gsi);
+        let bits = entry.bits();
+
+        // Write the high half first, so the entry is never unmasked with a stale destination
+        self.write(register + 1, (bits >> 32) as u32);
+        self.write(register, bits as u32);
+    }
+
+    /// Masks or unmasks the given global system interrupt
+    ///
+    /// # Panics
+    ///
+    /// Panics if the global system interrupt is not handled by this I/O APIC
+    pub fn set_masked(&mut self, gsi: u32, masked: bool) {
+        let register = self.redirection_register(gsi);
+
+        let low = self.read(register);
+        let low = if masked {
+            low | RedirectionFlags::MASKED.bits() as u32
+        } else {
+            low & !(RedirectionFlags::MASKED.bits() as u32)
+        };
+
+        self.write(register, low);
+    }
+
+    fn redirection_register(&self, gsi: u32) -> u8 {
+        assert!(self.handles(gsi), "gsi {} not handled by this I/O APIC", gsi);
+        REDIRECTION_TABLE + ((gsi - self.gsi_base) * 2) as u8
+    }
+
+    fn read(&self, register: u8) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base + REGISTER_SELECT) as *mut u32, register as u32);
+            ptr::read_volatile((self.base + REGISTER_WINDOW) as *const u32)
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base + REGISTER_SELECT) as *mut u32, register as u32);
+            ptr::write_volatile((self.base + REGISTER_WINDOW) as *mut u32, value);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Local APIC driver. Each CPU has its own local APIC, which receives interrupts from the I/O APIC
+//! and has its own timer.
+
+use core::ptr;
+use x86_64::registers::msr::{rdmsr, wrmsr};
+
+/// The MSR holding the local APIC's base address and global enable bit
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// Masks a local vector table entry
+const LVT_MASKED: u32 = 1 << 16;
+/// Enables the APIC in the spurious interrupt vector register
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+
+#[allow(dead_code)] // Dead constants for completeness
+mod registers {
+    //! Offsets of the local APIC's registers from its base address
+
+    pub const ID: usize = 0x20;
+    pub const VERSION: usize = 0x30;
+    pub const TASK_PRIORITY: usize = 0x80;
+    pub const END_OF_INTERRUPT: usize = 0xB0;
+    pub const SPURIOUS_INTERRUPT_VECTOR: usize = 0xF0;
+    pub const ERROR_STATUS: usize = 0x280;
+    pub const LVT_TIMER: usize = 0x320;
+    pub const LVT_LINT0: usize = 0x350;
+    pub const LVT_LINT1: usize = 0x360;
+    pub const LVT_ERROR: usize = 0x370;
+    pub const TIMER_INITIAL_COUNT: usize = 0x380;
+    pub const TIMER_CURRENT_COUNT: usize = 0x390;
+    pub const TIMER_DIVIDE: usize = 0x3E0;
+}
+
+/// The mode of the local APIC timer
+#[allow(dead_code)] // Dead variants for completeness
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u32)]
+pub enum TimerMode {
+    /// Fires once when the count reaches zero
+    OneShot = 0,
+    /// Fires every time the count reaches zero, and then restarts it
+    Periodic = 1 << 17,
+}
+
+/// What the bus frequency is divided by to get the local APIC timer's frequency
+#[allow(dead_code)] // Dead variants for completeness
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u32)]
+pub enum TimerDivide {
+    By1 = 0b1011,
+    By2 = 0b0000,
+    By4 = 0b0001,
+    By8 = 0b0010,
+    By16 = 0b0011,
+    By32 = 0b1000,
+    By64 = 0b1001,
+    By128 = 0b1010,
+}
+
+/// Interface to the current CPU's local APIC
+pub struct LocalApic {
+    base: usize,
+}
+
+impl LocalApic {
+    /// Creates a new interface to the local APIC mapped at the given address
+    ///
+    /// # Safety
+    ///
+    /// The address must be the identity mapped, uncached base of the local APIC
+    pub const unsafe fn new(base: usize) -> Self {
+        LocalApic { base }
+    }
+
+    /// Enables this local APIC, delivering spurious interrupts to the given vector
+    pub fn enable(&mut self, spurious_vector: u8) {
+        unsafe {
+            wrmsr(IA32_APIC_BASE, rdmsr(IA32_APIC_BASE) | APIC_BASE_ENABLE);
+        }
+
+        self.write(registers::SPURIOUS_INTERRUPT_VECTOR, spurious_vector as u32 | SPURIOUS_APIC_ENABLE);
+
+        // Accept interrupts of all priorities
+        self.write(registers::TASK_PRIORITY, 0);
+    }
+
+    /// Gets the ID of this local APIC, used to address it as an interrupt destination
+    pub fn id(&self) -> u8 {
+        (self.read(registers::ID) >> 24) as u8
+    }
+
+    /// Signals to this local APIC that the current interrupt has been handled
+    pub fn end_of_interrupt(&self) {
+        self.write(registers::END_OF_INTERRUPT, 0);
+    }
+
+    /// Starts this local APIC's timer, firing the given vector when the count reaches zero
+    #[allow(dead_code)] // Part of API
+    pub fn start_timer(&mut self, vector: u8, mode: TimerMode, divide: TimerDivide, initial_count: u32) {
+        self.write(registers::TIMER_DIVIDE, divide as u32);
+        self.write(registers::LVT_TIMER, vector as u32 | mode as u32);
+        self.write(registers::TIMER_INITIAL_COUNT, initial_count);
+    }
+
+    /// Stops this local APIC's timer
+    #[allow(dead_code)] // Part of API
+    pub fn stop_timer(&mut self) {
+        self.write(registers::LVT_TIMER, LVT_MASKED);
+        self.write(registers::TIMER_INITIAL_COUNT, 0);
+    }
+
+    /// Gets the current count of this local APIC's timer
+    #[allow(dead_code)] // Part of API
+    pub fn timer_count(&self) -> u32 {
+        self.read(registers::TIMER_CURRENT_COUNT)
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.base + register) as *const u32) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.base + register) as *mut u32, value); }
+    }
+}
+
+/// Gets the physical base address of the local APIC from the MSR
+pub fn base_address() -> u64 {
+    unsafe { rdmsr(IA32_APIC_BASE) & 0xF_FFFF_F000 }
+}
@@ -0,0 +1,244 @@
+//! # APIC
+//!
+//! Support for the local APIC and I/O APIC, which supersede the 8259 PICs. If the machine has them,
+//! ISA IRQs are routed through the I/O APIC, taking the interrupt source overrides from the ACPI
+//! MADT into account. Otherwise, `init` fails and the legacy PICs keep being used.
+
+pub mod local_apic;
+pub mod io_apic;
+
+use acpi::{self, madt::{Madt, MadtEntry, Polarity, TriggerMode}};
+use core::sync::atomic::{AtomicBool, Ordering};
+use memory;
+use spin::{Mutex, RwLock};
+use x86_64::structures::idt::{ExceptionStackFrame, Idt};
+use self::io_apic::{IoApic, RedirectionEntry, RedirectionFlags};
+use self::local_apic::{LocalApic, TimerDivide, TimerMode};
+use super::irq::{self, lines};
+
+/// The offset of the ISA IRQs in the IDT when routed through the I/O APIC. This is separate from
+/// the legacy PICs' offsets so that spurious IRQs from the (masked) PICs can be told apart.
+pub const ISA_OFFSET: u8 = 0x30;
+/// The vector of the local APIC timer
+pub const TIMER_VECTOR: u8 = 0x40;
+/// The vector which the local APIC delivers spurious interrupts to
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// The maximum amount of I/O APICs supported
+const MAX_IO_APICS: usize = 4;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The APICs, once initialized. This is locked by interrupt handlers, so must only be locked
+/// elsewhere with interrupts disabled
+static APIC: Mutex<Option<Apic>> = Mutex::new(None);
+
+/// The handler for the local APIC timer. This is read by the interrupt handler, so must only be
+/// written to with interrupts disabled
+static TIMER_HANDLER: RwLock<Option<fn()>> = RwLock::new(None);
+
+/// An error which occurred while setting up the APICs
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ApicError {
+    /// The CPU has no local APIC
+    Unsupported,
+    /// The ACPI tables, or the MADT within them, could not be found
+    NoMadt,
+    /// The MADT describes no I/O APIC
+    NoIoApic,
+    /// The APIC at the given physical address is not mapped
+    Unmapped(u64),
+}
+
+/// Describes how an ISA IRQ is connected to the I/O APICs
+#[derive(Copy, Clone, Debug)]
+struct IsaRoute {
+    gsi: u32,
+    flags: RedirectionFlags,
+}
+
+struct Apic {
+    local: LocalApic,
+    io_apics: [Option<IoApic>; MAX_IO_APICS],
+    isa_routes: [IsaRoute; irq::IRQ_COUNT as usize],
+}
+
+impl Apic {
+    /// Gets the I/O APIC handling the given global system interrupt
+    fn io_apic(&mut self, gsi: u32) -> Option<&mut IoApic> {
+        self.io_apics.iter_mut()
+            .filter_map(|io_apic| io_apic.as_mut())
+            .find(|io_apic| io_apic.handles(gsi))
+    }
+}
+
+/// Enables the local APIC and routes every ISA IRQ through the I/O APICs described by the MADT.
+/// The ISA IRQs are left masked. The legacy PICs must already be remapped and masked.
+pub fn init() -> Result<(), ApicError> {
+    if !cpu_has_apic() {
+        return Err(ApicError::Unsupported);
+    }
+
+    let madt = acpi::tables().and_then(Madt::find).ok_or(ApicError::NoMadt)?;
+
+    let local_address = check_mapped(madt.local_apic_address())?;
+    let mut local = unsafe { LocalApic::new(local_address) };
+
+    let mut io_apics = [None, None, None, None];
+    let mut count = 0;
+
+    for entry in madt.entries() {
+        if let MadtEntry::IoApic { address, gsi_base, .. } = entry {
+            if count == MAX_IO_APICS {
+                warn!("apic: more than {} I/O APICs, ignoring the rest", MAX_IO_APICS);
+                break;
+            }
+
+            let address = check_mapped(address as u64)?;
+            let mut io_apic = unsafe { IoApic::new(address, gsi_base) };
+            io_apic.mask_all();
+
+            io_apics[count] = Some(io_apic);
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        return Err(ApicError::NoIoApic);
+    }
+
+    local.enable(SPURIOUS_VECTOR);
+    let destination = local.id();
+
+    let default_route = IsaRoute { gsi: 0, flags: RedirectionFlags::empty() };
+    let mut apic = Apic { local, io_apics, isa_routes: [default_route; irq::IRQ_COUNT as usize] };
+
+    for irq in 0..irq::IRQ_COUNT {
+        // The cascade line only exists on the PICs
+        if irq == lines::CASCADE {
+            continue;
+        }
+
+        let route = isa_route(madt, irq);
+        apic.isa_routes[irq as usize] = route;
+
+        let entry = RedirectionEntry {
+            vector: ISA_OFFSET + irq,
+            destination,
+            flags: route.flags | RedirectionFlags::MASKED,
+        };
+
+        match apic.io_apic(route.gsi) {
+            Some(io_apic) => io_apic.set_redirection(route.gsi, entry),
+            None => warn!("apic: no I/O APIC handles irq {} (gsi {})", irq, route.gsi),
+        }
+    }
+
+    *APIC.lock() = Some(apic);
+    ENABLED.store(true, Ordering::Release);
+
+    debug!("apic: initialized with {} I/O APIC(s)", count);
+
+    Ok(())
+}
+
+/// Returns `true` if ISA IRQs are being routed through the APICs
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Installs the handlers of the local APIC's own vectors into the given IDT
+pub fn install(idt: &mut Idt) {
+    idt[TIMER_VECTOR as usize].set_handler_fn(timer_interrupt);
+    idt[SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt);
+}
+
+/// Masks or unmasks the given ISA IRQ in the I/O APIC. Must be called with interrupts disabled.
+pub fn set_masked(irq: u8, masked: bool) {
+    if let Some(ref mut apic) = *APIC.lock() {
+        let gsi = apic.isa_routes[irq as usize].gsi;
+
+        if let Some(io_apic) = apic.io_apic(gsi) {
+            io_apic.set_masked(gsi, masked);
+        }
+    }
+}
+
+/// Signals to the local APIC that the current interrupt has been handled
+pub fn end_of_interrupt() {
+    if let Some(ref apic) = *APIC.lock() {
+        apic.local.end_of_interrupt();
+    }
+}
+
+/// Starts the local APIC timer, calling the given handler every time it fires
+#[allow(dead_code)] // Part of API
+pub fn start_timer(handler: fn(), mode: TimerMode, divide: TimerDivide, initial_count: u32) {
+    super::without_interrupts(|| {
+        *TIMER_HANDLER.write() = Some(handler);
+
+        if let Some(ref mut apic) = *APIC.lock() {
+            apic.local.start_timer(TIMER_VECTOR, mode, divide, initial_count);
+        }
+    });
+}
+
+/// Stops the local APIC timer
+#[allow(dead_code)] // Part of API
+pub fn stop_timer() {
+    super::without_interrupts(|| {
+        if let Some(ref mut apic) = *APIC.lock() {
+            apic.local.stop_timer();
+        }
+
+        *TIMER_HANDLER.write() = None;
+    });
+}
+
+/// Finds how the given ISA IRQ is connected to the I/O APICs. ISA IRQs are identity mapped to global
+/// system interrupts, edge triggered and active high, unless overridden in the MADT
+fn isa_route(madt: &Madt, irq: u8) -> IsaRoute {
+    match madt.isa_override(irq) {
+        Some(source_override) => {
+            let mut flags = RedirectionFlags::empty();
+            flags.set(RedirectionFlags::ACTIVE_LOW, source_override.polarity() == Polarity::ActiveLow);
+            flags.set(RedirectionFlags::LEVEL_TRIGGERED, source_override.trigger_mode() == TriggerMode::Level);
+
+            debug!("apic: irq {} overridden to gsi {}", irq, source_override.gsi);
+
+            IsaRoute { gsi: source_override.gsi, flags }
+        }
+        None => IsaRoute { gsi: irq as u32, flags: RedirectionFlags::empty() },
+    }
+}
+
+/// Checks that the given physical address is identity mapped, returning it as a pointer address
+fn check_mapped(address: u64) -> Result<usize, ApicError> {
+    if address < memory::IDENTITY_MAPPED_END {
+        Ok(address as usize)
+    } else {
+        Err(ApicError::Unmapped(address))
+    }
+}
+
+/// Returns `true` if CPUID reports that the CPU has a local APIC
+fn cpu_has_apic() -> bool {
+    let edx: u32;
+    unsafe {
+        asm!("cpuid" : "={edx}"(edx) : "{eax}"(1) : "eax", "ebx", "ecx" : "volatile");
+    }
+
+    edx & (1 << 9) != 0
+}
+
+extern "x86-interrupt" fn timer_interrupt(_stack_frame: &mut ExceptionStackFrame) {
+    let handler = *TIMER_HANDLER.read();
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    end_of_interrupt();
+}
+
+/// Spurious interrupts from the local APIC must not be acknowledged
+extern "x86-interrupt" fn spurious_interrupt(_stack_frame: &mut ExceptionStackFrame) {}
@@ -0,0 +1,174 @@
+//! The state saved when an exception occurs, and readable dumps of it
+
+use core::fmt;
+
+/// The general purpose registers, in the order `exceptions.asm` leaves them on the stack
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct Registers {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+/// The frame pushed by the CPU when an interrupt occurs
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct InterruptFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Everything saved by `exceptions.asm` when an exception occurs. Changes to the registers and frame
+/// take effect when the handler returns.
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct ExceptionContext {
+    pub registers: Registers,
+    pub vector: u64,
+    /// The error code pushed by the CPU, or 0 for exceptions without one
+    pub error_code: u64,
+    pub frame: InterruptFrame,
+}
+
+/// The control registers, as read when a dump is made
+pub struct ControlRegisters {
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+}
+
+impl ControlRegisters {
+    pub fn read() -> Self {
+        let (cr0, cr2, cr3, cr4): (u64, u64, u64, u64);
+
+        unsafe {
+            asm!("mov %cr0, $0" : "=r"(cr0));
+            asm!("mov %cr2, $0" : "=r"(cr2));
+            asm!("mov %cr3, $0" : "=r"(cr3));
+            asm!("mov %cr4, $0" : "=r"(cr4));
+        }
+
+        ControlRegisters { cr0, cr2, cr3, cr4 }
+    }
+}
+
+const RFLAGS_NAMES: &[(u64, &str)] = &[
+    (1 << 0, "CF"),
+    (1 << 2, "PF"),
+    (1 << 4, "AF"),
+    (1 << 6, "ZF"),
+    (1 << 7, "SF"),
+    (1 << 8, "TF"),
+    (1 << 9, "IF"),
+    (1 << 10, "DF"),
+    (1 << 11, "OF"),
+    (1 << 14, "NT"),
+    (1 << 16, "RF"),
+    (1 << 17, "VM"),
+    (1 << 18, "AC"),
+    (1 << 19, "VIF"),
+    (1 << 20, "VIP"),
+    (1 << 21, "ID"),
+];
+
+const CR0_NAMES: &[(u64, &str)] = &[
+    (1 << 0, "PE"),
+    (1 << 1, "MP"),
+    (1 << 2, "EM"),
+    (1 << 3, "TS"),
+    (1 << 4, "ET"),
+    (1 << 5, "NE"),
+    (1 << 16, "WP"),
+    (1 << 18, "AM"),
+    (1 << 29, "NW"),
+    (1 << 30, "CD"),
+    (1 << 31, "PG"),
+];
+
+const CR4_NAMES: &[(u64, &str)] = &[
+    (1 << 0, "VME"),
+    (1 << 1, "PVI"),
+    (1 << 2, "TSD"),
+    (1 << 3, "DE"),
+    (1 << 4, "PSE"),
+    (1 << 5, "PAE"),
+    (1 << 6, "MCE"),
+    (1 << 7, "PGE"),
+    (1 << 8, "PCE"),
+    (1 << 9, "OSFXSR"),
+    (1 << 10, "OSXMMEXCPT"),
+    (1 << 11, "UMIP"),
+    (1 << 13, "VMXE"),
+    (1 << 14, "SMXE"),
+    (1 << 16, "FSGSBASE"),
+    (1 << 17, "PCIDE"),
+    (1 << 18, "OSXSAVE"),
+    (1 << 20, "SMEP"),
+    (1 << 21, "SMAP"),
+];
+
+/// Formats a register as hex followed by the names of its set flags, such as `0x202 [IF]`
+struct Flags(u64, &'static [(u64, &'static str)]);
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x} [", self.0)?;
+
+        let mut first = true;
+        for &(_, name) in self.1.iter().filter(|&&(bit, _)| self.0 & bit != 0) {
+            if !first {
+                write!(f, " ")?;
+            }
+
+            write!(f, "{}", name)?;
+            first = false;
+        }
+
+        write!(f, "]")
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rax {:#018x}  rbx {:#018x}  rcx {:#018x}", self.rax, self.rbx, self.rcx)?;
+        writeln!(f, "rdx {:#018x}  rsi {:#018x}  rdi {:#018x}", self.rdx, self.rsi, self.rdi)?;
+        writeln!(f, "rbp {:#018x}  r8  {:#018x}  r9  {:#018x}", self.rbp, self.r8, self.r9)?;
+        writeln!(f, "r10 {:#018x}  r11 {:#018x}  r12 {:#018x}", self.r10, self.r11, self.r12)?;
+        write!(f, "r13 {:#018x}  r14 {:#018x}  r15 {:#018x}", self.r13, self.r14, self.r15)
+    }
+}
+
+impl fmt::Display for ExceptionContext {
+    /// Dumps the registers at the time of the exception, along with the current control registers
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let control = ControlRegisters::read();
+
+        writeln!(f, "{}", self.registers)?;
+        writeln!(
+            f,
+            "rip {:#018x}  rsp {:#018x}  cs {:#x}  ss {:#x}",
+            self.frame.rip, self.frame.rsp, self.frame.cs, self.frame.ss,
+        )?;
+        writeln!(f, "rflags {}", Flags(self.frame.rflags, RFLAGS_NAMES))?;
+        writeln!(f, "cr0 {}", Flags(control.cr0, CR0_NAMES))?;
+        writeln!(f, "cr2 {:#018x}  cr3 {:#018x}", control.cr2, control.cr3)?;
+        write!(f, "cr4 {}", Flags(control.cr4, CR4_NAMES))
+    }
+}
@@ -0,0 +1,232 @@
+//! Breakpoints and hardware watchpoints
+//!
+//! Breakpoint (`int3`) and debug exceptions are logged and then resumed, so they can be used as a
+//! debugging aid. Up to four hardware breakpoints can be set through the debug registers, to catch
+//! execution of an instruction or accesses to memory.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! // Report every write to the first character of the VGA buffer
+//! let watchpoint = debug::set_breakpoint(0xb8000, BreakCondition::Write, BreakSize::Word)?;
+//! debug::clear_breakpoint(watchpoint);
+//! ```
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use terminal;
+use super::context::ExceptionContext;
+
+/// The amount of hardware breakpoints, each with its own address register (DR0 to DR3)
+pub const BREAKPOINT_COUNT: usize = 4;
+
+/// Which registers are in use. Only ever locked with interrupts disabled or from main code.
+static IN_USE: Mutex<[bool; BREAKPOINT_COUNT]> = Mutex::new([false; BREAKPOINT_COUNT]);
+
+/// The amount of reports dropped because the terminal was busy
+static DROPPED_REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The bits of DR6 telling which breakpoint was hit
+const DR6_HITS: u64 = 0b1111;
+/// Set in DR6 when the exception was caused by single stepping
+const DR6_SINGLE_STEP: u64 = 1 << 14;
+/// Makes the CPU ignore instruction breakpoints for the instruction returned to
+const RFLAGS_RESUME: u64 = 1 << 16;
+
+/// What accesses trigger a hardware breakpoint
+#[allow(dead_code)] // Dead variants for completeness
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u64)]
+pub enum BreakCondition {
+    /// Executing the instruction at the address. Must be used with [BreakSize::Byte].
+    Execute = 0b00,
+    Write = 0b01,
+    ReadWrite = 0b11,
+}
+
+/// The size of the memory watched by a hardware breakpoint. The address must be aligned to it.
+#[allow(dead_code)] // Dead variants for completeness
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u64)]
+pub enum BreakSize {
+    Byte = 0b00,
+    Word = 0b01,
+    DoubleWord = 0b11,
+    QuadWord = 0b10,
+}
+
+impl BreakSize {
+    fn bytes(&self) -> usize {
+        match *self {
+            BreakSize::Byte => 1,
+            BreakSize::Word => 2,
+            BreakSize::DoubleWord => 4,
+            BreakSize::QuadWord => 8,
+        }
+    }
+}
+
+/// An error which occurred while setting a hardware breakpoint
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DebugError {
+    /// All debug address registers are in use
+    NoFreeRegister,
+    /// The address is not aligned to the size of the breakpoint
+    Unaligned(usize),
+    /// Instruction breakpoints must have a size of one byte
+    InvalidSize(BreakSize),
+}
+
+/// A hardware breakpoint which has been set, identifying the debug register it uses
+#[derive(Debug)]
+pub struct Breakpoint(usize);
+
+/// Sets a hardware breakpoint on the given address, which is reported every time it is hit
+#[allow(dead_code)] // Part of API
+pub fn set_breakpoint(address: usize, condition: BreakCondition, size: BreakSize)
+    -> Result<Breakpoint, DebugError>
+{
+    if condition == BreakCondition::Execute && size != BreakSize::Byte {
+        return Err(DebugError::InvalidSize(size));
+    }
+
+    if address % size.bytes() != 0 {
+        return Err(DebugError::Unaligned(address));
+    }
+
+    super::without_interrupts(|| {
+        let mut in_use = IN_USE.lock();
+        let index = in_use.iter().position(|used| !used).ok_or(DebugError::NoFreeRegister)?;
+        in_use[index] = true;
+
+        unsafe {
+            write_address(index, address as u64);
+
+            // Enable the breakpoint locally, with its condition and size
+            let mut dr7 = read_dr7();
+            dr7 &= !(0b1111 << (16 + index * 4));
+            dr7 |= 1 << (index * 2);
+            dr7 |= (condition as u64) << (16 + index * 4);
+            dr7 |= (size as u64) << (18 + index * 4);
+            write_dr7(dr7);
+        }
+
+        Ok(Breakpoint(index))
+    })
+}
+
+/// Removes a hardware breakpoint
+#[allow(dead_code)] // Part of API
+pub fn clear_breakpoint(breakpoint: Breakpoint) {
+    super::without_interrupts(|| {
+        unsafe {
+            write_dr7(read_dr7() & !(1 << (breakpoint.0 * 2)));
+            write_address(breakpoint.0, 0);
+        }
+
+        IN_USE.lock()[breakpoint.0] = false;
+    })
+}
+
+/// The amount of breakpoint reports dropped because the terminal was busy
+#[allow(dead_code)] // Part of API
+pub fn dropped_reports() -> usize {
+    DROPPED_REPORTS.load(Ordering::Relaxed)
+}
+
+/// Handles an `int3` breakpoint, which leaves the instruction pointer just after itself
+pub fn breakpoint(context: &mut ExceptionContext) {
+    report(format_args!(
+        "debug: breakpoint at {:#x}, rsp {:#x}",
+        context.frame.rip - 1,
+        context.frame.rsp,
+    ));
+}
+
+/// Handles a debug exception, caused by a hardware breakpoint or single stepping
+pub fn debug_exception(context: &mut ExceptionContext) {
+    let dr6 = unsafe { read_dr6() };
+
+    for index in (0..BREAKPOINT_COUNT).filter(|index| dr6 & (1 << index) != 0) {
+        let address = unsafe { read_address(index) };
+
+        report(format_args!(
+            "debug: breakpoint {} on {:#x} hit at {:#x}, rsp {:#x}",
+            index,
+            address,
+            context.frame.rip,
+            context.frame.rsp,
+        ));
+    }
+
+    if dr6 & DR6_SINGLE_STEP != 0 {
+        report(format_args!("debug: single step at {:#x}", context.frame.rip));
+    }
+
+    if dr6 & (DR6_HITS | DR6_SINGLE_STEP) == 0 {
+        report(format_args!("debug: unknown debug exception at {:#x}, dr6 {:#x}", context.frame.rip, dr6));
+    }
+
+    unsafe { write_dr6(0) };
+
+    // Instruction breakpoints fault before the instruction, so would otherwise hit again
+    context.frame.rflags |= RFLAGS_RESUME;
+}
+
+/// Logs a debug event. If the event interrupted code writing to the terminal, the report would
+/// deadlock, so is dropped. Watched writes to the VGA buffer through the terminal are expected,
+/// so this also keeps them quiet.
+fn report(args: fmt::Arguments) {
+    let busy = terminal::STDOUT.try_write().map_or(true, |stdout| stdout.0.try_write().is_none());
+
+    if busy {
+        DROPPED_REPORTS.fetch_add(1, Ordering::Relaxed);
+    } else {
+        info!("{}", args);
+    }
+}
+
+unsafe fn read_address(index: usize) -> u64 {
+    let address: u64;
+
+    match index {
+        0 => asm!("mov %dr0, $0" : "=r"(address)),
+        1 => asm!("mov %dr1, $0" : "=r"(address)),
+        2 => asm!("mov %dr2, $0" : "=r"(address)),
+        3 => asm!("mov %dr3, $0" : "=r"(address)),
+        _ => unreachable!("there are only four debug address registers"),
+    }
+
+    address
+}
+
+unsafe fn write_address(index: usize, address: u64) {
+    match index {
+        0 => asm!("mov $0, %dr0" :: "r"(address) :: "volatile"),
+        1 => asm!("mov $0, %dr1" :: "r"(address) :: "volatile"),
+        2 => asm!("mov $0, %dr2" :: "r"(address) :: "volatile"),
+        3 => asm!("mov $0, %dr3" :: "r"(address) :: "volatile"),
+        _ => unreachable!("there are only four debug address registers"),
+    }
+}
+
+unsafe fn read_dr6() -> u64 {
+    let value: u64;
+    asm!("mov %dr6, $0" : "=r"(value));
+    value
+}
+
+unsafe fn write_dr6(value: u64) {
+    asm!("mov $0, %dr6" :: "r"(value) :: "volatile");
+}
+
+unsafe fn read_dr7() -> u64 {
+    let value: u64;
+    asm!("mov %dr7, $0" : "=r"(value));
+    value
+}
+
+unsafe fn write_dr7(value: u64) {
+    asm!("mov $0, %dr7" :: "r"(value) :: "volatile");
+}
@@ -1,75 +1,256 @@
 //! Exception handlers
+//!
+//! Exceptions enter through the stubs in `exceptions.asm`, which save the general purpose registers
+//! and call `exception_dispatch` with an [ExceptionContext]. Breakpoint and debug exceptions are
+//! handled by [debug] and resumed. Every other exception is fatal, so the handlers panic with a
+//! description of the fault, and the panic screen shows the registers and a backtrace starting at
+//! the faulting code.
 
-use x86_64::structures::idt::{ExceptionStackFrame, PageFaultErrorCode};
+use core::{fmt, mem};
+use gdt;
+use memory::stack;
+use panic_screen;
+use x86_64::structures::idt::{HandlerFunc, HandlerFuncWithErrCode, Idt, PageFaultHandlerFunc};
+use super::context::ExceptionContext;
+use super::debug;
 
-pub extern "x86-interrupt" fn divide_by_zero(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: divide by zero\n{:#?}", stack_frame);
+mod vectors {
+    pub const DIVIDE_BY_ZERO: u64 = 0;
+    pub const DEBUG: u64 = 1;
+    pub const BREAKPOINT: u64 = 3;
+    pub const OVERFLOW: u64 = 4;
+    pub const BOUND_RANGE_EXCEEDED: u64 = 5;
+    pub const INVALID_OPCODE: u64 = 6;
+    pub const DEVICE_NOT_AVAILABLE: u64 = 7;
+    pub const DOUBLE_FAULT: u64 = 8;
+    pub const INVALID_TSS: u64 = 10;
+    pub const SEGMENT_NOT_PRESENT: u64 = 11;
+    pub const STACK_SEGMENT_FAULT: u64 = 12;
+    pub const GENERAL_PROTECTION_FAULT: u64 = 13;
+    pub const PAGE_FAULT: u64 = 14;
+    pub const X87_FLOATING_POINT: u64 = 16;
+    pub const ALIGNMENT_CHECK: u64 = 17;
+    pub const MACHINE_CHECK: u64 = 18;
+    pub const SIMD_FLOATING_POINT: u64 = 19;
+    pub const VIRTUALIZATION: u64 = 20;
+    pub const SECURITY_EXCEPTION: u64 = 30;
 }
 
-pub extern "x86-interrupt" fn breakpoint(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: breakpoint\n{:#?}", stack_frame);
+extern "C" {
+    fn exception_stub_0();
+    fn exception_stub_1();
+    fn exception_stub_3();
+    fn exception_stub_4();
+    fn exception_stub_5();
+    fn exception_stub_6();
+    fn exception_stub_7();
+    fn exception_stub_8();
+    fn exception_stub_10();
+    fn exception_stub_11();
+    fn exception_stub_12();
+    fn exception_stub_13();
+    fn exception_stub_14();
+    fn exception_stub_16();
+    fn exception_stub_17();
+    fn exception_stub_18();
+    fn exception_stub_19();
+    fn exception_stub_20();
+    fn exception_stub_30();
 }
 
-pub extern "x86-interrupt" fn overflow(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: overflow\n{:#?}", stack_frame);
+bitflags! {
+    /// The error code of a page fault
+    struct PageFaultCode: u64 {
+        /// Set if the page was present, so the fault was a protection violation
+        const PROTECTION_VIOLATION = 1 << 0;
+        const WRITE = 1 << 1;
+        const USER_MODE = 1 << 2;
+        /// Set if a reserved bit was set in a page table entry
+        const RESERVED_BIT = 1 << 3;
+        const INSTRUCTION_FETCH = 1 << 4;
+        const PROTECTION_KEY = 1 << 5;
+    }
 }
 
-pub extern "x86-interrupt" fn out_of_bounds(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: out of bounds\n{:#?}", stack_frame);
+/// Installs the exception stubs into the given IDT. The double fault handler runs on its own stack
+/// from the interrupt stack table.
+pub fn install(idt: &mut Idt) {
+    // The IDT only takes x86-interrupt handlers, but only their address is used
+    unsafe {
+        idt.divide_by_zero.set_handler_fn(handler(exception_stub_0));
+        idt.debug.set_handler_fn(handler(exception_stub_1));
+        idt.breakpoint.set_handler_fn(handler(exception_stub_3));
+        idt.overflow.set_handler_fn(handler(exception_stub_4));
+        idt.bound_range_exceeded.set_handler_fn(handler(exception_stub_5));
+        idt.invalid_opcode.set_handler_fn(handler(exception_stub_6));
+        idt.device_not_available.set_handler_fn(handler(exception_stub_7));
+        idt.double_fault.set_handler_fn(handler_with_error(exception_stub_8))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.invalid_tss.set_handler_fn(handler_with_error(exception_stub_10));
+        idt.segment_not_present.set_handler_fn(handler_with_error(exception_stub_11));
+        idt.stack_segment_fault.set_handler_fn(handler_with_error(exception_stub_12));
+        idt.general_protection_fault.set_handler_fn(handler_with_error(exception_stub_13));
+        idt.page_fault.set_handler_fn(page_fault_handler(exception_stub_14));
+        idt.x87_floating_point.set_handler_fn(handler(exception_stub_16));
+        idt.alignment_check.set_handler_fn(handler_with_error(exception_stub_17));
+        idt.machine_check.set_handler_fn(handler(exception_stub_18));
+        idt.simd_floating_point.set_handler_fn(handler(exception_stub_19));
+        idt.virtualization.set_handler_fn(handler(exception_stub_20));
+        idt.security_exception.set_handler_fn(handler_with_error(exception_stub_30));
+    }
 }
 
-pub extern "x86-interrupt" fn invalid_opcode(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: invalid opcode\n{:#?}", stack_frame);
+unsafe fn handler(stub: unsafe extern "C" fn()) -> HandlerFunc {
+    mem::transmute(stub)
 }
 
-pub extern "x86-interrupt" fn device_not_available(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: device not available\n{:#?}", stack_frame);
+unsafe fn handler_with_error(stub: unsafe extern "C" fn()) -> HandlerFuncWithErrCode {
+    mem::transmute(stub)
 }
 
-pub extern "x86-interrupt" fn double_fault(stack_frame: &mut ExceptionStackFrame, code: u64) {
-    panic!("cpuex: double fault {}\n{:#?}", code, stack_frame);
+unsafe fn page_fault_handler(stub: unsafe extern "C" fn()) -> PageFaultHandlerFunc {
+    mem::transmute(stub)
 }
 
-pub extern "x86-interrupt" fn invalid_tss(stack_frame: &mut ExceptionStackFrame, code: u64) {
-    panic!("cpuex: invalid tss {}\n{:#?}", code, stack_frame);
-}
+/// Called by `exceptions.asm` for every exception, so not built for the host, where there is no
+/// `exceptions.asm`
+#[cfg(target_os = "flower")]
+#[no_mangle]
+pub extern "C" fn exception_dispatch(context: &mut ExceptionContext) {
+    match context.vector {
+        vectors::DEBUG => return debug::debug_exception(context),
+        vectors::BREAKPOINT => return debug::breakpoint(context),
+        _ => (),
+    }
 
-pub extern "x86-interrupt" fn segment_not_present(stack_frame: &mut ExceptionStackFrame, code: u64) {
-    panic!("cpuex: segment not present {}\n{:#?}", code, stack_frame);
-}
+    // Every other exception is fatal, and the state of the faulting code is the useful one
+    panic_screen::set_exception_context(context);
 
-pub extern "x86-interrupt" fn stack_segment_fault(stack_frame: &mut ExceptionStackFrame, code: u64) {
-    panic!("cpuex: stack segment fault {}\n{:#?}", code, stack_frame);
+    match context.vector {
+        vectors::DOUBLE_FAULT => double_fault(context),
+        vectors::PAGE_FAULT => page_fault(context),
+        vectors::INVALID_TSS
+            | vectors::SEGMENT_NOT_PRESENT
+            | vectors::STACK_SEGMENT_FAULT
+            | vectors::GENERAL_PROTECTION_FAULT => {
+            panic!("cpuex: {} ({})", name(context.vector), SelectorError(context.error_code))
+        }
+        vectors::ALIGNMENT_CHECK | vectors::SECURITY_EXCEPTION => {
+            panic!("cpuex: {} {:#x}", name(context.vector), context.error_code)
+        }
+        vector => panic!("cpuex: {}", name(vector)),
+    }
 }
 
-pub extern "x86-interrupt" fn general_protection_fault(stack_frame: &mut ExceptionStackFrame, code: u64) {
-    panic!("cpuex: general protection fault {}\n{:#?}", code, stack_frame);
-}
+fn double_fault(context: &ExceptionContext) -> ! {
+    // An overflow page faults on the guard page, which then can't push its stack frame
+    let address = fault_address();
+    if stack::is_guard_page(address) {
+        panic!("cpuex: kernel stack overflow at {:#x}", address);
+    }
 
-pub extern "x86-interrupt" fn page_fault(stack_frame: &mut ExceptionStackFrame, code: PageFaultErrorCode) {
-    panic!("cpuex: page fault fault {:?}\n{:#?}", code, stack_frame);
+    panic!("cpuex: double fault");
 }
 
-pub extern "x86-interrupt" fn x87_floating_point(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: x87 floating point\n{:#?}", stack_frame);
-}
+fn page_fault(context: &ExceptionContext) -> ! {
+    let address = fault_address();
+    if stack::is_guard_page(address) {
+        panic!("cpuex: kernel stack overflow at {:#x}", address);
+    }
 
-pub extern "x86-interrupt" fn alignment_check(stack_frame: &mut ExceptionStackFrame, code: u64) {
-    panic!("cpuex: alignment check {}\n{:#?}", code, stack_frame);
+    let code = PageFaultCode::from_bits_truncate(context.error_code);
+    panic!("cpuex: page fault at {:#x}: {}", address, code);
 }
 
-pub extern "x86-interrupt" fn machine_check(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: machine check\n{:#?}", stack_frame);
+/// The name of the exception with the given vector
+fn name(vector: u64) -> &'static str {
+    match vector {
+        vectors::DIVIDE_BY_ZERO => "divide by zero",
+        vectors::DEBUG => "debug",
+        vectors::BREAKPOINT => "breakpoint",
+        vectors::OVERFLOW => "overflow",
+        vectors::BOUND_RANGE_EXCEEDED => "out of bounds",
+        vectors::INVALID_OPCODE => "invalid opcode",
+        vectors::DEVICE_NOT_AVAILABLE => "device not available",
+        vectors::DOUBLE_FAULT => "double fault",
+        vectors::INVALID_TSS => "invalid tss",
+        vectors::SEGMENT_NOT_PRESENT => "segment not present",
+        vectors::STACK_SEGMENT_FAULT => "stack segment fault",
+        vectors::GENERAL_PROTECTION_FAULT => "general protection fault",
+        vectors::PAGE_FAULT => "page fault",
+        vectors::X87_FLOATING_POINT => "x87 floating point",
+        vectors::ALIGNMENT_CHECK => "alignment check",
+        vectors::MACHINE_CHECK => "machine check",
+        vectors::SIMD_FLOATING_POINT => "simd floating point",
+        vectors::VIRTUALIZATION => "virtualization",
+        vectors::SECURITY_EXCEPTION => "security exception",
+        _ => "unknown exception",
+    }
 }
 
-pub extern "x86-interrupt" fn simd_floating_point(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: simd floating point\n{:#?}", stack_frame);
+/// Reads the address whose access caused the last page fault from CR2
+fn fault_address() -> usize {
+    let address: usize;
+    unsafe { asm!("mov %cr2, $0" : "=r"(address)); }
+
+    address
 }
 
-pub extern "x86-interrupt" fn virtualization(stack_frame: &mut ExceptionStackFrame) {
-    panic!("cpuex: simd floating point\n{:#?}", stack_frame);
+impl fmt::Display for PageFaultCode {
+    /// Describes the fault in words, such as "write to non-present page in kernel mode"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = if self.contains(PageFaultCode::INSTRUCTION_FETCH) {
+            "instruction fetch from"
+        } else if self.contains(PageFaultCode::WRITE) {
+            "write to"
+        } else {
+            "read from"
+        };
+
+        let page = if self.contains(PageFaultCode::PROTECTION_VIOLATION) {
+            "protected"
+        } else {
+            "non-present"
+        };
+
+        let mode = if self.contains(PageFaultCode::USER_MODE) { "user" } else { "kernel" };
+
+        write!(f, "{} {} page in {} mode", access, page, mode)?;
+
+        if self.contains(PageFaultCode::RESERVED_BIT) {
+            write!(f, ", reserved bit set in page table")?;
+        }
+
+        if self.contains(PageFaultCode::PROTECTION_KEY) {
+            write!(f, ", protection key violation")?;
+        }
+
+        Ok(())
+    }
 }
 
-pub extern "x86-interrupt" fn security_exception(stack_frame: &mut ExceptionStackFrame, code: u64) {
-    panic!("cpuex: security exception {}\n{:#?}", code, stack_frame);
+/// The error code of segment related exceptions, which describes the selector at fault
+struct SelectorError(u64);
+
+impl fmt::Display for SelectorError {
+    /// Describes the selector, such as "GDT selector 0x10, index 2"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "not caused by a selector");
+        }
+
+        let table = match (self.0 >> 1) & 0b11 {
+            0b00 => "GDT",
+            0b10 => "LDT",
+            _ => "IDT",
+        };
+
+        write!(f, "{} selector {:#x}, index {}", table, self.0 & 0xFFF8, (self.0 >> 3) & 0x1FFF)?;
+
+        if self.0 & 1 != 0 {
+            write!(f, ", external event")?;
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,230 @@
+//! # IRQ Registry
+//!
+//! Allows drivers to claim one of the 16 legacy IRQ lines. A trampoline for every line is installed
+//! into the IDT up front, which dispatches to the registered handler and then acknowledges the
+//! interrupt. Lines stay masked until a handler is registered for them.
+//!
+//! IRQs are delivered either by the legacy PICs or by the I/O APIC, if it is enabled. Drivers
+//! don't need to care which.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! fn handle_keyboard() {
+//!     let data = ps2::io::DATA_PORT.read();
+//!     // ...
+//! }
+//!
+//! irq::register(irq::lines::KEYBOARD, handle_keyboard)?;
+//! ```
+
+use spin::RwLock;
+use super::apic;
+use super::legacy_pic::{self, CHAINED_PICS};
+use x86_64::structures::idt::{ExceptionStackFrame, HandlerFunc, Idt};
+
+/// The amount of IRQ lines available through the chained PICs
+pub const IRQ_COUNT: u8 = 16;
+
+/// A function handling an IRQ. It is called with interrupts disabled, and must not take any lock
+/// which may be held by code running with interrupts enabled.
+pub type IrqHandler = fn();
+
+#[allow(dead_code)] // Dead constants for completeness
+pub mod lines {
+    //! Well known ISA IRQ lines
+
+    pub const TIMER: u8 = 0;
+    pub const KEYBOARD: u8 = 1;
+    /// Used internally by the master PIC to chain the slave. It cannot be registered.
+    pub const CASCADE: u8 = 2;
+    pub const COM2: u8 = 3;
+    pub const COM1: u8 = 4;
+    pub const LPT2: u8 = 5;
+    pub const FLOPPY: u8 = 6;
+    pub const LPT1: u8 = 7;
+    pub const RTC: u8 = 8;
+    pub const MOUSE: u8 = 12;
+    pub const FPU: u8 = 13;
+    pub const PRIMARY_ATA: u8 = 14;
+    pub const SECONDARY_ATA: u8 = 15;
+}
+
+/// An error which occurred while (un)registering an IRQ handler
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum IrqError {
+    /// The line is out of range or reserved
+    InvalidLine(u8),
+    /// A handler is already registered for the line
+    AlreadyRegistered(u8),
+    /// No handler is registered for the line
+    NotRegistered(u8),
+}
+
+/// The registered handlers. This is read by the trampolines, so must only be written to with
+/// interrupts disabled.
+static HANDLERS: RwLock<[Option<IrqHandler>; IRQ_COUNT as usize]> = RwLock::new([None; IRQ_COUNT as usize]);
+
+/// Registers a handler for the given IRQ line and unmasks it
+pub fn register(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    check_line(irq)?;
+
+    super::without_interrupts(|| {
+        let mut handlers = HANDLERS.write();
+        if handlers[irq as usize].is_some() {
+            return Err(IrqError::AlreadyRegistered(irq));
+        }
+
+        handlers[irq as usize] = Some(handler);
+        set_masked(irq, false);
+
+        Ok(())
+    })
+}
+
+/// Masks the given IRQ line and unregisters its handler
+pub fn unregister(irq: u8) -> Result<(), IrqError> {
+    check_line(irq)?;
+
+    super::without_interrupts(|| {
+        let mut handlers = HANDLERS.write();
+        if handlers[irq as usize].is_none() {
+            return Err(IrqError::NotRegistered(irq));
+        }
+
+        set_masked(irq, true);
+        handlers[irq as usize] = None;
+
+        Ok(())
+    })
+}
+
+/// Installs the trampolines of every IRQ line, for both the legacy PICs and the I/O APIC, into the
+/// given IDT
+pub fn install(idt: &mut Idt) {
+    for (irq, trampoline) in PIC_TRAMPOLINES.iter().enumerate() {
+        idt[interrupt_id(irq as u8) as usize].set_handler_fn(*trampoline);
+    }
+
+    for (irq, trampoline) in APIC_TRAMPOLINES.iter().enumerate() {
+        idt[apic::ISA_OFFSET as usize + irq].set_handler_fn(*trampoline);
+    }
+}
+
+/// The interrupt controller which delivered an IRQ
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Source {
+    LegacyPic,
+    Apic,
+}
+
+/// Masks or unmasks the given line on the interrupt controller in use. Must be called with
+/// interrupts disabled.
+fn set_masked(irq: u8, masked: bool) {
+    if apic::is_enabled() {
+        apic::set_masked(irq, masked);
+    } else {
+        let pics = CHAINED_PICS.lock();
+        pics.set_masked(irq, masked);
+
+        // Slave IRQs are only delivered if the cascade line is unmasked too
+        if irq >= 8 && !masked {
+            pics.set_masked(lines::CASCADE, false);
+        }
+    }
+}
+
+fn check_line(irq: u8) -> Result<(), IrqError> {
+    if irq >= IRQ_COUNT || irq == lines::CASCADE {
+        Err(IrqError::InvalidLine(irq))
+    } else {
+        Ok(())
+    }
+}
+
+/// Dispatches an IRQ to its registered handler and acknowledges it
+fn dispatch(source: Source, irq: u8) {
+    if source == Source::LegacyPic {
+        let pics = CHAINED_PICS.lock();
+
+        // Once the I/O APIC is in use, the PICs are fully masked and only raise spurious IRQs
+        if apic::is_enabled() || pics.is_spurious(irq) {
+            if irq == 15 {
+                pics.end_of_spurious_interrupt();
+            }
+
+            return;
+        }
+    }
+
+    let handler = HANDLERS.read()[irq as usize];
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    match source {
+        Source::LegacyPic => CHAINED_PICS.lock().end_of_interrupt(interrupt_id(irq)),
+        Source::Apic => apic::end_of_interrupt(),
+    }
+}
+
+/// Gets the IDT index which the given IRQ line is remapped to on the legacy PICs
+fn interrupt_id(irq: u8) -> u8 {
+    if irq < 8 {
+        legacy_pic::MASTER_OFFSET + irq
+    } else {
+        legacy_pic::SLAVE_OFFSET + (irq - 8)
+    }
+}
+
+macro_rules! trampolines {
+    ($table:ident, $source:expr; $($name:ident = $irq:expr),+ $(,)*) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: &mut ExceptionStackFrame) {
+                dispatch($source, $irq);
+            }
+        )+
+
+        const $table: [HandlerFunc; IRQ_COUNT as usize] = [$($name),+];
+    };
+}
+
+trampolines! {
+    PIC_TRAMPOLINES, Source::LegacyPic;
+    pic_irq_0 = 0,
+    pic_irq_1 = 1,
+    pic_irq_2 = 2,
+    pic_irq_3 = 3,
+    pic_irq_4 = 4,
+    pic_irq_5 = 5,
+    pic_irq_6 = 6,
+    pic_irq_7 = 7,
+    pic_irq_8 = 8,
+    pic_irq_9 = 9,
+    pic_irq_10 = 10,
+    pic_irq_11 = 11,
+    pic_irq_12 = 12,
+    pic_irq_13 = 13,
+    pic_irq_14 = 14,
+    pic_irq_15 = 15,
+}
+
+trampolines! {
+    APIC_TRAMPOLINES, Source::Apic;
+    apic_irq_0 = 0,
+    apic_irq_1 = 1,
+    apic_irq_2 = 2,
+    apic_irq_3 = 3,
+    apic_irq_4 = 4,
+    apic_irq_5 = 5,
+    apic_irq_6 = 6,
+    apic_irq_7 = 7,
+    apic_irq_8 = 8,
+    apic_irq_9 = 9,
+    apic_irq_10 = 10,
+    apic_irq_11 = 11,
+    apic_irq_12 = 12,
+    apic_irq_13 = 13,
+    apic_irq_14 = 14,
+    apic_irq_15 = 15,
+}
@@ -3,15 +3,23 @@
 use io::SynchronizedPort;
 use spin::Mutex;
 
-pub static CHAINED_PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics::new((0x20, 0x28)));
+/// The offset of the master PIC's interrupts in the IDT
+pub const MASTER_OFFSET: u8 = 0x20;
+/// The offset of the slave PIC's interrupts in the IDT
+pub const SLAVE_OFFSET: u8 = 0x28;
+
+pub static CHAINED_PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics::new((MASTER_OFFSET, SLAVE_OFFSET)));
 
 /// Used to pause execution temporarily
 pub static IO_WAIT_PORT: SynchronizedPort<u8> = unsafe { SynchronizedPort::new(0x80) };
 
 #[repr(u8)]
 enum Commands {
-    Init = 0x10,
+    /// Initialise, expecting the ICW4 (mode) word
+    Init = 0x11,
     EndOfInterrupt = 0x20,
+    /// Makes the next read of the command port return the in-service register
+    ReadIsr = 0x0B,
 }
 
 /// Represents an 8295/8295A PIC (superseded by APIC)
@@ -34,6 +42,15 @@ impl Pic {
         self.command_port.write(Commands::EndOfInterrupt as u8);
     }
 
+    /// Reads the in-service register, which has a bit set for each IRQ line currently being
+    /// serviced
+    fn read_isr(&self) -> u8 {
+        self.command_port.with_lock(|mut port| {
+            port.write(Commands::ReadIsr as u8);
+            port.read()
+        })
+    }
+
     pub fn initialise(&self) {
         // Tell the PIC to initialise
         self.command_port.write(Commands::Init as u8);
@@ -96,4 +113,95 @@ impl ChainedPics {
         self.inner[0].data_port.write(0xFF);
         self.inner[1].data_port.write(0xFF);
     }
+
+    /// Masks or unmasks the given IRQ line (0-15). Unmasking a slave line doesn't unmask the
+    /// cascade line (2) on the master.
+    pub fn set_masked(&self, irq: u8, masked: bool) {
+        let pic = &self.inner[(irq / 8) as usize];
+        let line = irq % 8;
+
+        let mask = pic.data_port.read();
+        let mask = if masked { mask | (1 << line) } else { mask & !(1 << line) };
+        pic.data_port.write(mask);
+    }
+
+    /// Notifies the PICs that the given interrupt has been handled. If the interrupt came from the
+    /// slave, both PICs are notified, as the master raised it through the cascade line.
+    pub fn end_of_interrupt(&self, interrupt_id: u8) {
+        if self.inner[1].handles_interrupt(interrupt_id) {
+            self.inner[1].end_of_interrupt();
+        }
+
+        if self.inner.iter().any(|pic| pic.handles_interrupt(interrupt_id)) {
+            self.inner[0].end_of_interrupt();
+        }
+    }
+
+    /// Checks whether the given IRQ was spurious, that is, raised by the PIC without the line
+    /// actually being in service. This can only happen on the lowest priority lines, 7 and 15.
+    ///
+    /// # Note
+    ///
+    /// A spurious IRQ must not be acknowledged by the PIC that raised it. For a spurious IRQ 15,
+    /// the master still needs to be acknowledged, as it cannot know the slave's IRQ was spurious.
+    pub fn is_spurious(&self, irq: u8) -> bool {
+        match irq {
+            7 => self.inner[0].read_isr() & (1 << 7) == 0,
+            15 => self.inner[1].read_isr() & (1 << 7) == 0,
+            _ => false,
+        }
+    }
+
+    /// Acknowledges a spurious IRQ 15 on the master PIC, which raised it through the cascade line
+    pub fn end_of_spurious_interrupt(&self) {
+        self.inner[0].end_of_interrupt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use io::mock::{self, MockIo};
+    use super::*;
+
+    /// Both PICs, with nothing masked or in service
+    fn fake_pics() -> MockIo {
+        MockIo::new().ports(&[0x20, 0x21, 0xA0, 0xA1, 0x80], 0)
+    }
+
+    kernel_test! {
+        fn remap_sends_initialization_words() {
+            let pics = ChainedPics::new((MASTER_OFFSET, SLAVE_OFFSET));
+            let (mock, _) = mock::run(fake_pics(), || pics.remap_and_disable());
+
+            assert_eq!(mock.writes_to(0x20), [0x11]);
+            assert_eq!(mock.writes_to(0xA0), [0x11]);
+            // Offset, cascade identity, 8086 mode and then the mask
+            assert_eq!(mock.writes_to(0x21), [0x20, 4, 0x01, 0xFF]);
+            assert_eq!(mock.writes_to(0xA1), [0x28, 2, 0x01, 0xFF]);
+        }
+
+        fn set_masked_changes_only_its_line() {
+            let pics = ChainedPics::new((MASTER_OFFSET, SLAVE_OFFSET));
+            let mock = fake_pics().port(0xA1, 0xFF);
+            let (mock, _) = mock::run(mock, || pics.set_masked(12, false));
+
+            assert_eq!(mock.writes(), [(0xA1, 0xEF)]);
+        }
+
+        fn slave_end_of_interrupt_notifies_both() {
+            let pics = ChainedPics::new((MASTER_OFFSET, SLAVE_OFFSET));
+            let (mock, _) = mock::run(fake_pics(), || pics.end_of_interrupt(SLAVE_OFFSET + 4));
+
+            assert_eq!(mock.writes(), [(0xA0, 0x20), (0x20, 0x20)]);
+        }
+
+        fn irq_7_without_isr_bit_is_spurious() {
+            let pics = ChainedPics::new((MASTER_OFFSET, SLAVE_OFFSET));
+            let mock = fake_pics().queue(0x20, &[0x00, 0x80]);
+            let (mock, spurious) = mock::run(mock, || (pics.is_spurious(7), pics.is_spurious(7)));
+
+            assert_eq!(spurious, (true, false));
+            assert_eq!(mock.writes_to(0x20), [0x0B, 0x0B]);
+        }
+    }
 }
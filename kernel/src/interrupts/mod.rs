@@ -1,38 +1,91 @@
 //! Module for interrupt handling/IDT
+//!
+//! Host tests run in user mode, where changing the interrupt flag faults, so there [enable],
+//! [disable] and [enable_and_halt] do nothing and interrupts count as disabled.
 
 use x86_64::structures::idt::Idt;
 
+pub mod irq;
+pub mod apic;
+pub mod debug;
 mod legacy_pic;
 mod exceptions;
+pub mod context;
 
 lazy_static! {
     static ref IDT: Idt = {
         let mut idt = Idt::new();
-        idt.divide_by_zero.set_handler_fn(exceptions::divide_by_zero);
-        idt.breakpoint.set_handler_fn(exceptions::breakpoint);
-        idt.overflow.set_handler_fn(exceptions::overflow);
-        idt.bound_range_exceeded.set_handler_fn(exceptions::out_of_bounds);
-        idt.invalid_opcode.set_handler_fn(exceptions::invalid_opcode);
-        idt.device_not_available.set_handler_fn(exceptions::device_not_available);
-        idt.double_fault.set_handler_fn(exceptions::double_fault);
-        idt.invalid_tss.set_handler_fn(exceptions::invalid_tss);
-        idt.segment_not_present.set_handler_fn(exceptions::segment_not_present);
-        idt.stack_segment_fault.set_handler_fn(exceptions::stack_segment_fault);
-        idt.general_protection_fault.set_handler_fn(exceptions::general_protection_fault);
-        idt.page_fault.set_handler_fn(exceptions::page_fault);
-        idt.x87_floating_point.set_handler_fn(exceptions::x87_floating_point);
-        idt.alignment_check.set_handler_fn(exceptions::alignment_check);
-        idt.machine_check.set_handler_fn(exceptions::machine_check);
-        idt.simd_floating_point.set_handler_fn(exceptions::simd_floating_point);
-        idt.virtualization.set_handler_fn(exceptions::virtualization);
-        idt.security_exception.set_handler_fn(exceptions::security_exception);
+        exceptions::install(&mut idt);
+        irq::install(&mut idt);
+        apic::install(&mut idt);
         idt
     };
 }
 
-/// Implicitly invoke the lazy initializer of the IDT & load it, as well as disable PICs and set up
-/// APICs
+/// Implicitly invoke the lazy initializer of the IDT & load it, as well as remap and mask the PICs
+/// and set up APICs, falling back to the PICs if there are none. The ACPI tables should be found
+/// and the GDT loaded before this is called. Interrupts are enabled once this returns; IRQ lines
+/// are unmasked as handlers are registered through [irq::register].
 pub fn init() {
     IDT.load();
+
+    // Even when using the APICs, the PICs must be remapped so that spurious IRQs from them don't
+    // look like CPU exceptions
     legacy_pic::CHAINED_PICS.lock().remap_and_disable();
+
+    match apic::init() {
+        Ok(_) => info!("apic: routing irqs through the I/O APIC"),
+        Err(error) => warn!("apic: {:?}, falling back to legacy PIC", error),
+    }
+
+    enable();
+}
+
+/// Returns `true` if maskable interrupts are enabled on this CPU
+pub fn enabled() -> bool {
+    if cfg!(not(target_os = "flower")) {
+        return false;
+    }
+
+    let flags: u64;
+    unsafe { asm!("pushfq; pop $0" : "=r"(flags) ::: "volatile"); }
+
+    flags & (1 << 9) != 0
+}
+
+/// Enables maskable interrupts on this CPU
+pub fn enable() {
+    if cfg!(target_os = "flower") {
+        unsafe { asm!("sti" :::: "volatile"); }
+    }
+}
+
+/// Disables maskable interrupts on this CPU
+pub fn disable() {
+    if cfg!(target_os = "flower") {
+        unsafe { asm!("cli" :::: "volatile"); }
+    }
+}
+
+/// Runs the given closure with interrupts disabled, restoring the previous state afterwards. This
+/// should be used when taking a lock which is also taken by an interrupt handler.
+pub fn without_interrupts<R, F: FnOnce() -> R>(f: F) -> R {
+    let was_enabled = enabled();
+    disable();
+
+    let result = f();
+
+    if was_enabled {
+        enable();
+    }
+
+    result
+}
+
+/// Enables interrupts and halts until the next one arrives. Because `sti` only takes effect after
+/// the following instruction, an interrupt cannot slip in between the two and be missed.
+pub fn enable_and_halt() {
+    if cfg!(target_os = "flower") {
+        unsafe { asm!("sti; hlt" :::: "volatile"); }
+    }
 }
@@ -0,0 +1,379 @@
+//! # Mock Port I/O
+//!
+//! A [MockIo] stands in for the devices behind the ports it claims while it is [run]. Every [Port]
+//! access to a claimed port goes to the mock instead of the hardware: reads are answered from a
+//! queue of values kept per port, and every access is recorded in order, so that tests can assert
+//! the exact sequence of commands a driver sends. Accesses to other ports still reach the
+//! hardware in the kernel, while on the host, where there is none, they panic.
+//!
+//! Fakes of whole devices are built on top of a mock, such as the PS/2 controller of
+//! [drivers::ps2::fake].
+//!
+//! A simple fake device can be scripted with a few rules:
+//!  - A claimed port answers reads from its queue, and with its default value once that is empty
+//!  - A ready flag makes a status port report whether a data port has values queued
+//!  - A response queues values on a data port whenever a given value is written to a port
+//!
+//! A captured trace of accesses can also be [replayed](MockIo::replay), with its reads answered
+//! as they were recorded.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! let mock = MockIo::new()
+//!     .port(0x60, 0)
+//!     .ready_flag(0x64, 0x60, 1)
+//!     .respond(0x64, 0xAA, 0x60, &[0x55]);
+//!
+//! let (mock, passed) = mock::run(mock, || controller.test_controller());
+//! assert_eq!(mock.writes_to(0x64), [0xAA]);
+//! ```
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// The mock which claimed ports are currently backed by
+static ACTIVE: Mutex<Option<MockIo>> = Mutex::new(None);
+
+/// Held while a mock is run, so that tests running on several threads take turns
+static EXCLUSIVE: Mutex<()> = Mutex::new(());
+
+/// A single port access, with the value read or written
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Access {
+    Read(u16, u32),
+    Write(u16, u32),
+}
+
+impl Access {
+    /// The port this access was to
+    pub fn port(&self) -> u16 {
+        match *self {
+            Access::Read(port, _) | Access::Write(port, _) => port,
+        }
+    }
+}
+
+/// A port claimed by the mock
+struct MockPort {
+    port: u16,
+    queue: VecDeque<u32>,
+    default: u32,
+}
+
+/// Makes reads of `port` include `flag` while `data_port` has values queued
+struct ReadyFlag {
+    port: u16,
+    data_port: u16,
+    flag: u32,
+}
+
+/// Queues `values` on `data_port` whenever `value` is written to `port`
+struct Response {
+    port: u16,
+    value: u32,
+    data_port: u16,
+    values: Vec<u32>,
+    used: bool,
+}
+
+/// A scripted fake device, which answers reads from queues and records every access
+pub struct MockIo {
+    ports: Vec<MockPort>,
+    flags: Vec<ReadyFlag>,
+    responses: Vec<Response>,
+    log: Vec<Access>,
+}
+
+impl MockIo {
+    /// Creates a mock which claims no ports
+    pub fn new() -> Self {
+        MockIo {
+            ports: Vec::new(),
+            flags: Vec::new(),
+            responses: Vec::new(),
+            log: Vec::new(),
+        }
+    }
+
+    /// Creates a mock which replays the given trace. Every port in the trace is claimed, and
+    /// reads are answered with the values they were recorded with.
+    pub fn replay(trace: &[Access]) -> Self {
+        let mut mock = MockIo::new();
+
+        for access in trace {
+            mock.claim(access.port());
+
+            if let Access::Read(port, value) = *access {
+                mock.claim(port).queue.push_back(value);
+            }
+        }
+
+        mock
+    }
+
+    /// Claims the given port, which reads the default value while nothing is queued on it
+    pub fn port(mut self, port: u16, default: u32) -> Self {
+        self.claim(port).default = default;
+        self
+    }
+
+    /// Claims each of the given ports, which read the default value while nothing is queued on them
+    pub fn ports(self, ports: &[u16], default: u32) -> Self {
+        ports.iter().fold(self, |mock, &port| mock.port(port, default))
+    }
+
+    /// Queues values to be read from the given port, claiming it if needed
+    pub fn queue(mut self, port: u16, values: &[u32]) -> Self {
+        self.claim(port).queue.extend(values.iter().cloned());
+        self
+    }
+
+    /// Makes reads of `port` include `flag` while `data_port` has values queued, as a status
+    /// register's "output full" bit would. Claims `port` if needed.
+    pub fn ready_flag(mut self, port: u16, data_port: u16, flag: u32) -> Self {
+        self.claim(port);
+        self.flags.push(ReadyFlag { port, data_port, flag });
+        self
+    }
+
+    /// Queues `values` on `data_port` every time `value` is written to `port`, as a device
+    /// answering a command would. Claims both ports if needed.
+    ///
+    /// If several responses are set for the same write, they are used in turn, with the last one
+    /// used from then on.
+    pub fn respond(mut self, port: u16, value: u32, data_port: u16, values: &[u32]) -> Self {
+        self.claim(port);
+        self.claim(data_port);
+        self.responses.push(Response {
+            port,
+            value,
+            data_port,
+            values: values.to_vec(),
+            used: false,
+        });
+        self
+    }
+
+    /// Every access made to the claimed ports, in order
+    pub fn log(&self) -> &[Access] {
+        &self.log
+    }
+
+    /// Every write made to the claimed ports, in order, as `(port, value)`
+    pub fn writes(&self) -> Vec<(u16, u32)> {
+        self.log.iter()
+            .filter_map(|access| match *access {
+                Access::Write(port, value) => Some((port, value)),
+                Access::Read(..) => None,
+            })
+            .collect()
+    }
+
+    /// Every value written to the given port, in order
+    pub fn writes_to(&self, port: u16) -> Vec<u32> {
+        self.writes().into_iter()
+            .filter(|&(written_port, _)| written_port == port)
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    fn claim(&mut self, port: u16) -> &mut MockPort {
+        let index = match self.ports.iter().position(|claimed| claimed.port == port) {
+            Some(index) => index,
+            None => {
+                self.ports.push(MockPort { port, queue: VecDeque::new(), default: 0 });
+                self.ports.len() - 1
+            }
+        };
+
+        &mut self.ports[index]
+    }
+
+    fn find(&mut self, port: u16) -> Option<&mut MockPort> {
+        self.ports.iter_mut().find(|claimed| claimed.port == port)
+    }
+
+    fn read(&mut self, port: u16) -> Option<u32> {
+        let flags = self.flags.iter()
+            .filter(|flag| flag.port == port)
+            .filter(|flag| self.ports.iter().any(|claimed| {
+                claimed.port == flag.data_port && !claimed.queue.is_empty()
+            }))
+            .fold(0, |flags, flag| flags | flag.flag);
+
+        let value = {
+            let claimed = self.find(port)?;
+            claimed.queue.pop_front().unwrap_or(claimed.default) | flags
+        };
+
+        self.log.push(Access::Read(port, value));
+        Some(value)
+    }
+
+    fn write(&mut self, port: u16, value: u32) -> bool {
+        if self.find(port).is_none() {
+            return false;
+        }
+
+        self.log.push(Access::Write(port, value));
+
+        let response = {
+            let mut matching = self.responses.iter_mut()
+                .filter(|response| response.port == port && response.value == value)
+                .peekable();
+
+            let mut chosen = None;
+            while let Some(response) = matching.next() {
+                if !response.used || matching.peek().is_none() {
+                    response.used = true;
+                    chosen = Some((response.data_port, response.values.clone()));
+                    break;
+                }
+            }
+
+            chosen
+        };
+
+        if let Some((data_port, values)) = response {
+            self.claim(data_port).queue.extend(values);
+        }
+
+        true
+    }
+}
+
+/// Backs the mock's claimed ports with it while running the given closure, then returns it with
+/// every access recorded, along with the closure's result. On the kernel, interrupts are disabled
+/// meanwhile, so that no interrupt handler touches the claimed ports.
+pub fn run<R, F: FnOnce() -> R>(mock: MockIo, f: F) -> (MockIo, R) {
+    let _exclusive = EXCLUSIVE.lock();
+    *ACTIVE.lock() = Some(mock);
+
+    #[cfg(target_os = "flower")]
+    let result = ::interrupts::without_interrupts(f);
+    #[cfg(not(target_os = "flower"))]
+    let result = f();
+
+    let mock = ACTIVE.lock().take().expect("mock removed while running");
+    (mock, result)
+}
+
+/// Removes the running mock. Used by the test runner after a test panics, as the mock would
+/// otherwise never be removed.
+#[cfg(target_os = "flower")]
+pub unsafe fn reset() {
+    ACTIVE.force_unlock();
+    EXCLUSIVE.force_unlock();
+    ACTIVE.lock().take();
+}
+
+/// Reads the given port from the running mock, or returns `None` if it isn't claimed.
+///
+/// As interrupts are disabled while a mock is run, the mock can only be found locked by an
+/// interrupt handler which interrupted a port access made while no mock was running, so that
+/// access goes to the hardware.
+pub fn read(port: u16) -> Option<u32> {
+    ACTIVE.try_lock().and_then(|mut active| active.as_mut().and_then(|mock| mock.read(port)))
+}
+
+/// Writes to the given port of the running mock, returning `false` if it isn't claimed. See
+/// [read].
+pub fn write(port: u16, value: u32) -> bool {
+    ACTIVE.try_lock().map_or(false, |mut active| {
+        active.as_mut().map_or(false, |mock| mock.write(port, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::Port;
+
+    kernel_test! {
+        fn reads_queued_values_then_default() {
+            let mock = MockIo::new().port(0x60, 0xFF).queue(0x60, &[1, 2]);
+
+            let (_, read) = run(mock, || {
+                let mut port = unsafe { Port::<u8>::new(0x60) };
+                [port.read(), port.read(), port.read()]
+            });
+
+            assert_eq!(read, [1, 2, 0xFF]);
+        }
+
+        fn records_accesses_in_order() {
+            let mock = MockIo::new().port(0x20, 0).port(0x21, 0x0F);
+
+            let (mock, _) = run(mock, || unsafe {
+                Port::<u8>::new(0x20).write(0x11);
+                Port::<u8>::new(0x21).read();
+                Port::<u16>::new(0x21).write(0x1234);
+            });
+
+            assert_eq!(mock.log(), [
+                Access::Write(0x20, 0x11),
+                Access::Read(0x21, 0x0F),
+                Access::Write(0x21, 0x1234),
+            ]);
+            assert_eq!(mock.writes_to(0x21), [0x1234]);
+        }
+
+        fn ready_flag_follows_data_queue() {
+            let mock = MockIo::new()
+                .port(0x60, 0)
+                .ready_flag(0x64, 0x60, 1)
+                .respond(0x64, 0x20, 0x60, &[0x47]);
+
+            let (_, read) = run(mock, || unsafe {
+                let mut status = Port::<u8>::new(0x64);
+                let mut data = Port::<u8>::new(0x60);
+
+                let before = status.read();
+                status.write(0x20);
+                let ready = status.read();
+                let value = data.read();
+
+                [before, ready, value, status.read()]
+            });
+
+            assert_eq!(read, [0, 1, 0x47, 0]);
+        }
+
+        fn responses_are_used_in_turn() {
+            let mock = MockIo::new()
+                .port(0x60, 0)
+                .respond(0x60, 0xF2, 0x60, &[3])
+                .respond(0x60, 0xF2, 0x60, &[4]);
+
+            let (_, read) = run(mock, || unsafe {
+                let mut port = Port::<u8>::new(0x60);
+                let mut identify = || {
+                    port.write(0xF2);
+                    port.read()
+                };
+
+                [identify(), identify(), identify()]
+            });
+
+            assert_eq!(read, [3, 4, 4]);
+        }
+
+        fn replays_trace() {
+            let trace = [
+                Access::Write(0x64, 0xAA),
+                Access::Read(0x64, 0x01),
+                Access::Read(0x60, 0x55),
+            ];
+
+            let (mock, _) = run(MockIo::replay(&trace), || unsafe {
+                Port::<u8>::new(0x64).write(0xAA);
+                Port::<u8>::new(0x64).read();
+                Port::<u8>::new(0x60).read();
+            });
+
+            assert_eq!(mock.log(), trace);
+        }
+    }
+}
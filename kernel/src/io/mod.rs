@@ -1,3 +1,12 @@
+//! # Port I/O
+//!
+//! Devices are accessed through [Port]s, which execute `in` and `out` instructions. In test builds,
+//! ports can instead be backed by a [mock::MockIo], so that drivers can be tested against a
+//! scripted fake device.
+
+#[cfg(test)]
+pub mod mock;
+
 use core::marker::PhantomData;
 use spin::{Mutex, MutexGuard};
 
@@ -43,9 +52,17 @@ use self::x86_io::{inb, inl, inw, outb, outl, outw};
 
 /// Nice little type that allows us to specify the size of the value read without using inb
 /// directly.
-pub trait InOut {
+pub trait InOut: Copy {
     unsafe fn port_in(port: u16) -> Self;
     unsafe fn port_out(port: u16, value: Self);
+
+    /// Widens the value, so that the mock can store values of any size
+    #[cfg(test)]
+    fn into_u32(self) -> u32;
+
+    /// Truncates a value stored by the mock
+    #[cfg(test)]
+    fn from_u32(value: u32) -> Self;
 }
 
 impl InOut for u8 {
@@ -55,6 +72,14 @@ impl InOut for u8 {
     unsafe fn port_out(port: u16, value: u8) {
         outb(value, port);
     }
+    #[cfg(test)]
+    fn into_u32(self) -> u32 {
+        self as u32
+    }
+    #[cfg(test)]
+    fn from_u32(value: u32) -> u8 {
+        value as u8
+    }
 }
 
 impl InOut for u16 {
@@ -64,6 +89,14 @@ impl InOut for u16 {
     unsafe fn port_out(port: u16, value: u16) {
         outw(value, port);
     }
+    #[cfg(test)]
+    fn into_u32(self) -> u32 {
+        self as u32
+    }
+    #[cfg(test)]
+    fn from_u32(value: u32) -> u16 {
+        value as u16
+    }
 }
 
 impl InOut for u32 {
@@ -73,6 +106,14 @@ impl InOut for u32 {
     unsafe fn port_out(port: u16, value: u32) {
         outl(value, port);
     }
+    #[cfg(test)]
+    fn into_u32(self) -> u32 {
+        self as u32
+    }
+    #[cfg(test)]
+    fn from_u32(value: u32) -> u32 {
+        value as u32
+    }
 }
 
 /// An `InOut`sized port. This could be any of the type implementors for `InOut`.
@@ -97,11 +138,33 @@ impl<T: InOut> Port<T> {
 
     /// Read a value from `self.port`.
     pub fn read(&mut self) -> T {
+        #[cfg(test)]
+        {
+            if let Some(value) = mock::read(self.port) {
+                return T::from_u32(value);
+            }
+
+            if cfg!(not(target_os = "flower")) {
+                panic!("port {:#x} read on the host without a mock claiming it", self.port);
+            }
+        }
+
         unsafe { T::port_in(self.port) }
     }
 
     /// Write a value to `self.port`.
     pub fn write(&mut self, value: T) {
+        #[cfg(test)]
+        {
+            if mock::write(self.port, value.into_u32()) {
+                return;
+            }
+
+            if cfg!(not(target_os = "flower")) {
+                panic!("port {:#x} written on the host without a mock claiming it", self.port);
+            }
+        }
+
         unsafe { T::port_out(self.port, value); }
     }
 }
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(target_os = "flower", no_std)]
 
 #![feature(asm)]
 #![feature(lang_items)]
@@ -11,8 +11,20 @@
 #![feature(type_ascription)]
 #![feature(ptr_internals)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc, alloc_error_handler)]
+#![feature(panic_handler, panic_info_message)]
+#![feature(custom_test_frameworks)]
 
+#![cfg_attr(all(test, target_os = "flower"), no_main)]
+#![cfg_attr(target_os = "flower", test_runner(testing::runner))]
+#![cfg_attr(target_os = "flower", reexport_test_harness_main = "test_main")]
+
+// Built for the host, only the tests are run, so most of the kernel is unused
+#![cfg_attr(not(target_os = "flower"), allow(dead_code, unused_imports))]
+
+#[cfg(target_os = "flower")]
 extern crate rlibc;
+extern crate alloc;
 extern crate volatile;
 extern crate spin;
 extern crate x86_64;
@@ -25,62 +37,183 @@ extern crate lazy_static;
 
 use drivers::keyboard::{Keyboard, KeyEventType, Ps2Keyboard};
 use drivers::keyboard::keymap;
+use drivers::mouse::{Mouse, Ps2Mouse};
 use drivers::ps2;
+use drivers::serial::SerialConfig;
 use terminal::TerminalOutput;
 
+#[cfg(target_os = "flower")]
 mod lang;
 #[macro_use]
 mod log;
+#[cfg(test)]
+#[macro_use]
+mod testing;
 #[macro_use]
 mod util;
 #[macro_use]
 mod color;
-mod io;
-mod interrupts;
-
 #[macro_use]
 mod terminal;
+mod io;
+mod ring_buffer;
+mod multiboot;
+mod memory;
+mod gdt;
+mod acpi;
+mod backtrace;
+mod panic_screen;
+mod interrupts;
+mod time;
 mod drivers;
 
-/// Kernel main function
+/// Kernel main function, passed the address of the multiboot information by `boot.asm`. Built for
+/// the host, there is no `boot.asm` to call it, and it would be kept only to fail linking against
+/// the symbols the assembly defines.
+#[cfg(target_os = "flower")]
 #[no_mangle]
-pub extern fn kmain() -> ! {
-    interrupts::init();
-
+pub extern fn kmain(multiboot_info_address: usize) -> ! {
     terminal::STDOUT.write().clear().expect("Screen clear failed");
 
     print_flower().expect("Flower print failed");
 
+    // Errors are logged after the boot message
+    let boot_info = unsafe { multiboot::init(multiboot_info_address) };
+    let memory_stats = boot_info.map_err(|_| None)
+        .and_then(|boot_info| memory::init(boot_info).map_err(Some));
+
     terminal::STDOUT.write().set_color(color!(Green on Black))
         .expect("Color should be supported");
 
     // Print boot message
     println!("Flower kernel boot!");
+    if let Ok(stats) = memory_stats {
+        println!("Memory: {}", stats);
+    }
     println!("-------------------\n");
 
     // Reset colors
     terminal::STDOUT.write().set_color(color!(White on Black))
         .expect("Color should be supported");
 
-    let mut controller = ps2::CONTROLLER.lock();
-    match controller.initialize() {
-        Ok(_) => info!("ps2c: init successful"),
-        Err(error) => error!("ps2c: {:?}", error),
+    let boot_info = match boot_info {
+        Ok(boot_info) => {
+            info!("multiboot: booted by {}", boot_info.bootloader_name().unwrap_or("unknown bootloader"));
+            debug!("multiboot: command line \"{}\"", boot_info.command_line().unwrap_or(""));
+            Some(boot_info)
+        }
+        Err(error) => {
+            error!("multiboot: {:?}", error);
+            None
+        }
+    };
+
+    let command_line = boot_info.and_then(|boot_info| boot_info.command_line());
+    if let Err(error) = command_line.map_or(Ok(()), log::filter::init) {
+        warn!("log: {:?}", error);
+    }
+
+    if let Err(Some(error)) = memory_stats {
+        error!("mem: {:?}", error);
+    }
+
+    memory::paging::init();
+
+    if let Err(error) = memory::heap::init() {
+        error!("heap: {:?}", error);
+    }
+
+    if let Err(error) = memory::stack::protect_boot_stack() {
+        warn!("stack: boot stack unprotected: {:?}", error);
+    }
+
+    if let Err(error) = gdt::init() {
+        error!("gdt: {:?}", error);
     }
 
-    let keyboard_device = controller.device(ps2::DevicePort::Keyboard);
-    let mut keyboard = Ps2Keyboard::new(keyboard_device);
+    let rsdp_address = boot_info.and_then(|boot_info| boot_info.rsdp_v2().or(boot_info.rsdp_v1()));
+    if let Err(error) = unsafe { acpi::init(rsdp_address) } {
+        warn!("acpi: {:?}", error);
+    }
+
+    interrupts::init();
+
+    if let Err(error) = time::init() {
+        error!("time: {:?}", error);
+    }
+
+    match drivers::serial::COM1.write().init(SerialConfig::default()) {
+        Ok(_) => {
+            info!("serial: COM1 initialized");
+            if let Err(error) = log::register(&log::sinks::COM1, log::Level::Trace) {
+                warn!("log: {:?}", error);
+            }
+        }
+        Err(error) => warn!("serial: COM1 {:?}", error),
+    }
+
+    if log::sinks::DEBUGCON.present() {
+        if let Err(error) = log::register(&log::sinks::DEBUGCON, log::Level::Trace) {
+            warn!("log: {:?}", error);
+        }
+    }
+
+    #[cfg(all(test, target_os = "flower"))]
+    test_main();
+
+    // The drivers own their devices, so that the controller isn't left locked while they run
+    let (mouse_device, keyboard_device) = {
+        let mut controller = ps2::CONTROLLER.lock();
+        match controller.initialize() {
+            Ok(_) => info!("ps2c: init successful"),
+            Err(error) => error!("ps2c: {:?}", error),
+        }
+
+        let mouse_port = controller.mouse().map(|device| device.port);
+        let keyboard_port = controller.keyboard().map(|device| device.port);
+        (mouse_port.map(|port| controller.take(port)), keyboard_port.map(|port| controller.take(port)))
+    };
+
+    if let Some(mut mouse_device) = mouse_device {
+        let mut mouse = Ps2Mouse::new(&mut mouse_device);
+        match mouse.enable() {
+            Ok(_) => {
+                info!("mouse: enabled with {:?} protocol", mouse.protocol());
+
+                // Nothing reads mouse events yet, so the mouse is disabled again rather than
+                // filling its queue from an IRQ handler nobody drains
+                if let Err(error) = mouse.disable() {
+                    warn!("mouse: {:?}", error);
+                }
+            }
+            Err(error) => warn!("mouse: {:?}", error),
+        }
+    }
+
+    let mut keyboard_device = match keyboard_device {
+        Some(device) => device,
+        None => {
+            error!("kbd: no keyboard found");
+            halt()
+        }
+    };
+
+    let mut keyboard = Ps2Keyboard::new(&mut keyboard_device);
     if let Ok(_) = keyboard.enable() {
         info!("kbd: successfully enabled");
         loop {
-            if let Ok(Some(event)) = keyboard.read_event() {
-                if event.event_type != KeyEventType::Break {
+            match keyboard.wait_event() {
+                Ok(event) => if event.event_type != KeyEventType::Break {
                     if event.keycode == keymap::codes::BACKSPACE {
                         // Ignore error
                         let _ = terminal::STDOUT.write().backspace();
                     } else if let Some(character) = event.char {
                         print!("{}", character)
                     }
+                },
+                Err(error) => {
+                    error!("kbd: {:?}", error);
+                    break;
                 }
             }
         }
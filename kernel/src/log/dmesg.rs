@@ -0,0 +1,219 @@
+//! # Kernel Log Buffer
+//!
+//! Keeps the last [CAPACITY] log records in memory, with the time they were logged at and their
+//! level, so that messages which scrolled off the screen can be read back. Records longer than
+//! [TEXT_LENGTH] bytes are truncated.
+//!
+//! The buffer is never allocated or moved, so it can still be read after a panic.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! // Print the last 10 records
+//! dmesg::dump(&mut *terminal::STDOUT.write(), 10)?;
+//! ```
+
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use time;
+use super::{Level, Record, Sink, SinkError};
+
+/// The amount of records kept
+pub const CAPACITY: usize = 256;
+/// The amount of bytes of each record's message kept
+pub const TEXT_LENGTH: usize = 120;
+
+/// The sink writing every record into the buffer
+pub static DMESG: DmesgSink = DmesgSink;
+
+static LOG: Log = Log {
+    locked: AtomicBool::new(false),
+    entries: UnsafeCell::new(Entries {
+        entries: [Entry::EMPTY; CAPACITY],
+        written: 0,
+    }),
+};
+
+/// An error which occurred while reading the buffer
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DmesgError {
+    /// A record is being written by the interrupted code
+    Busy,
+    /// Writing a record to the given writer failed
+    WriteFailed,
+}
+
+/// A record kept in the buffer
+#[derive(Copy, Clone)]
+pub struct Entry {
+    /// The uptime at which the record was logged, in milliseconds
+    timestamp: u64,
+    level: Level,
+    text: [u8; TEXT_LENGTH],
+    len: usize,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        timestamp: 0,
+        level: Level::Trace,
+        text: [0; TEXT_LENGTH],
+        len: 0,
+    };
+
+    /// The time since boot at which the record was logged
+    pub fn timestamp(&self) -> Duration {
+        Duration::from_millis(self.timestamp)
+    }
+
+    #[allow(dead_code)] // Part of API
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// The record's message, possibly truncated
+    pub fn text(&self) -> &str {
+        // Only ever filled with whole characters
+        unsafe { ::core::str::from_utf8_unchecked(&self.text[..self.len]) }
+    }
+}
+
+impl fmt::Display for Entry {
+    /// Formats the entry as a log line, such as `[    1.024] [info]  kbd: successfully enabled`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let timestamp = self.timestamp();
+
+        write!(
+            f,
+            "[{:>5}.{:03}] {}{}",
+            timestamp.as_secs(),
+            timestamp.subsec_nanos() / 1_000_000,
+            self.level.prefix(),
+            self.text(),
+        )
+    }
+}
+
+impl Write for Entry {
+    /// Appends as many whole characters as fit, dropping the rest
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        for character in s.chars() {
+            let len = character.len_utf8();
+            if self.len + len > TEXT_LENGTH {
+                break;
+            }
+
+            character.encode_utf8(&mut self.text[self.len..self.len + len]);
+            self.len += len;
+        }
+
+        Ok(())
+    }
+}
+
+struct Entries {
+    entries: [Entry; CAPACITY],
+    /// The total amount of records ever written
+    written: usize,
+}
+
+impl Entries {
+    /// The kept entries, from oldest to newest, skipping all but the last `count`
+    fn last(&self, count: usize) -> impl Iterator<Item = &Entry> {
+        let kept = self.written.min(CAPACITY).min(count);
+        let entries = &self.entries;
+
+        ((self.written - kept)..self.written).map(move |index| &entries[index % CAPACITY])
+    }
+}
+
+/// The buffer, behind a lock which is never waited on. Logging from an interrupt handler while the
+/// buffer is locked drops the record rather than deadlocking.
+struct Log {
+    locked: AtomicBool,
+    entries: UnsafeCell<Entries>,
+}
+
+unsafe impl Sync for Log {}
+
+impl Log {
+    fn try_with<R, F: FnOnce(&mut Entries) -> R>(&self, f: F) -> Result<R, DmesgError> {
+        if self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return Err(DmesgError::Busy);
+        }
+
+        let result = f(unsafe { &mut *self.entries.get() });
+        self.locked.store(false, Ordering::Release);
+
+        Ok(result)
+    }
+}
+
+/// Writes every record into the kernel log buffer
+pub struct DmesgSink;
+
+impl Sink for DmesgSink {
+    fn name(&self) -> &'static str {
+        "dmesg"
+    }
+
+    fn write(&self, record: &Record) -> Result<(), SinkError> {
+        let uptime = time::uptime();
+
+        let mut entry = Entry {
+            timestamp: uptime.as_secs() * 1000 + (uptime.subsec_nanos() / 1_000_000) as u64,
+            level: record.level,
+            ..Entry::EMPTY
+        };
+        let _ = write!(entry, "{}", record.args);
+
+        LOG.try_with(|entries| {
+            let index = entries.written % CAPACITY;
+            entries.entries[index] = entry;
+            entries.written += 1;
+        }).map_err(|_| SinkError::Busy)
+    }
+}
+
+/// Calls the given closure with the last `count` records, from oldest to newest. Records logged
+/// from within the closure are dropped.
+pub fn for_each_last<F: FnMut(&Entry)>(count: usize, mut f: F) -> Result<(), DmesgError> {
+    LOG.try_with(|entries| entries.last(count).for_each(|entry| f(entry)))
+}
+
+/// Calls the given closure with every kept record, from oldest to newest
+#[allow(dead_code)] // Part of API
+pub fn for_each<F: FnMut(&Entry)>(f: F) -> Result<(), DmesgError> {
+    for_each_last(CAPACITY, f)
+}
+
+/// Writes the last `count` records to the given writer, one per line
+#[allow(dead_code)] // Part of API
+pub fn dump<W: Write>(writer: &mut W, count: usize) -> Result<(), DmesgError> {
+    LOG.try_with(|entries| {
+        for entry in entries.last(count) {
+            writeln!(writer, "{}", entry).map_err(|_| DmesgError::WriteFailed)?;
+        }
+
+        Ok(())
+    })?
+}
+
+/// The total amount of records ever written, including those no longer kept
+#[allow(dead_code)] // Part of API
+pub fn written() -> usize {
+    LOG.try_with(|entries| entries.written).unwrap_or(0)
+}
+
+/// Unlocks the buffer, in case a panic happened while a record was being written, so that it can be
+/// read by the panic handler. The record being written may be garbled.
+///
+/// # Safety
+///
+/// Nothing else may be accessing the buffer, for instance because interrupts are disabled and the
+/// kernel has panicked.
+pub unsafe fn force_unlock() {
+    LOG.locked.store(false, Ordering::Release);
+}
@@ -0,0 +1,198 @@
+//! Runtime log level filtering
+//!
+//! Records are let through if they are at least as severe as the default level, unless a filter
+//! matching them overrides it. Filters match by prefix against a record's target, which is the tag
+//! its message starts with (such as `ps2c` in `"ps2c: init successful"`), and against its module
+//! path (such as `drivers::ps2`). When several filters match, the longest one wins.
+//!
+//! Filters are parsed from specs like `info,ps2c=trace,kbd=warn`, where a bare level sets the
+//! default level. At boot, the spec is read from the `log=` argument of the kernel command line.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::RwLock;
+use super::{Level, Record};
+
+/// The maximum amount of filters which can be set at once
+pub const MAX_FILTERS: usize = 16;
+
+/// The default level when none is given, chosen by the `debug` and `trace` features
+#[cfg(feature = "trace")]
+const COMPILED_LEVEL: Level = Level::Trace;
+#[cfg(all(feature = "debug", not(feature = "trace")))]
+const COMPILED_LEVEL: Level = Level::Debug;
+#[cfg(not(feature = "debug"))]
+const COMPILED_LEVEL: Level = Level::Info;
+
+/// The name of the kernel command line argument holding the filter spec
+const COMMAND_LINE_ARGUMENT: &str = "log=";
+
+static FILTERS: RwLock<Filters> = RwLock::new(Filters {
+    default: COMPILED_LEVEL,
+    filters: [None; MAX_FILTERS],
+});
+
+/// The least severe level which any filter or the default lets through, so that most records can be
+/// discarded without taking the lock
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(COMPILED_LEVEL as usize);
+
+struct Filters {
+    default: Level,
+    filters: [Option<Filter>; MAX_FILTERS],
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Filter {
+    prefix: &'static str,
+    level: Level,
+}
+
+/// An error which occurred while setting a filter
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FilterError {
+    /// The given level is not the name of a level
+    InvalidLevel(&'static str),
+    /// The maximum amount of filters are already set
+    TooManyFilters,
+}
+
+impl Filters {
+    fn level_for(&self, record: &Record) -> Level {
+        let module_path = strip_crate_name(record.module_path);
+
+        self.filters.iter()
+            .flat_map(|filter| filter.as_ref())
+            .filter(|filter| {
+                (!record.target.is_empty() && record.target.starts_with(filter.prefix))
+                    || module_path.starts_with(filter.prefix)
+            })
+            .max_by_key(|filter| filter.prefix.len())
+            .map_or(self.default, |filter| filter.level)
+    }
+
+    fn max_level(&self) -> Level {
+        self.filters.iter()
+            .flat_map(|filter| filter.as_ref())
+            .map(|filter| filter.level)
+            .fold(self.default, |max, level| max.max(level))
+    }
+}
+
+/// Returns `false` if no record of the given level could be let through. Used by the logging
+/// macros to skip formatting.
+pub fn enabled(level: Level) -> bool {
+    level as usize <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Returns `true` if the given record should be logged
+pub fn allows(record: &Record) -> bool {
+    match FILTERS.try_read() {
+        Some(filters) => record.level <= filters.level_for(record),
+        // The filters are being changed by the interrupted code
+        None => record.level <= COMPILED_LEVEL,
+    }
+}
+
+/// Sets the level which records not matching any filter must be at least as severe as
+pub fn set_default_level(level: Level) {
+    update(|filters| filters.default = level);
+}
+
+/// Sets the level of records whose target or module path starts with the given prefix, replacing
+/// any filter with the same prefix
+pub fn set_filter(prefix: &'static str, level: Level) -> Result<(), FilterError> {
+    update(|filters| {
+        let existing = filters.filters.iter()
+            .position(|filter| filter.map_or(false, |filter| filter.prefix == prefix));
+        let free = filters.filters.iter().position(|filter| filter.is_none());
+
+        let index = existing.or(free).ok_or(FilterError::TooManyFilters)?;
+        filters.filters[index] = Some(Filter { prefix, level });
+
+        Ok(())
+    })
+}
+
+/// Removes every filter, leaving only the default level
+#[allow(dead_code)] // Part of API
+pub fn clear_filters() {
+    update(|filters| filters.filters = [None; MAX_FILTERS]);
+}
+
+/// Applies a spec such as `info,ps2c=trace,kbd=warn`. Entries before an invalid one are still
+/// applied.
+pub fn parse(spec: &'static str) -> Result<(), FilterError> {
+    for entry in spec.split(',').map(|entry| entry.trim()).filter(|entry| !entry.is_empty()) {
+        let mut parts = entry.splitn(2, '=');
+        let first = parts.next().unwrap_or("");
+
+        match parts.next() {
+            Some(level) => set_filter(first, parse_level(level)?)?,
+            None => set_default_level(parse_level(first)?),
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies the spec given by the `log=` argument of the kernel command line, if there is one
+pub fn init(command_line: &'static str) -> Result<(), FilterError> {
+    let argument = command_line.split_whitespace()
+        .find(|argument| argument.starts_with(COMMAND_LINE_ARGUMENT));
+
+    match argument {
+        Some(argument) => parse(&argument[COMMAND_LINE_ARGUMENT.len()..]),
+        None => Ok(()),
+    }
+}
+
+/// The target of a message, which is the tag before its first colon, such as `ps2c` in
+/// `"ps2c: init successful"`. Messages without a tag have an empty target.
+pub fn target(message: &'static str) -> &'static str {
+    match message.find(':') {
+        Some(end) if !message[..end].contains(' ') => &message[..end],
+        _ => "",
+    }
+}
+
+fn parse_level(name: &'static str) -> Result<Level, FilterError> {
+    Level::from_name(name).ok_or(FilterError::InvalidLevel(name))
+}
+
+/// Changes the filters, with interrupts disabled so that logging from an interrupt handler doesn't
+/// see them half changed, and recalculates the maximum level
+fn update<R, F: FnOnce(&mut Filters) -> R>(f: F) -> R {
+    ::interrupts::without_interrupts(|| {
+        let mut filters = FILTERS.write();
+        let result = f(&mut filters);
+        MAX_LEVEL.store(filters.max_level() as usize, Ordering::Relaxed);
+
+        result
+    })
+}
+
+/// Strips the crate name from a module path, such as `flower_kernel::drivers::ps2`
+fn strip_crate_name(module_path: &'static str) -> &'static str {
+    module_path.splitn(2, "::").nth(1).unwrap_or(module_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    kernel_test! {
+        fn target_is_tag_before_colon() {
+            assert_eq!(target("ps2c: init successful"), "ps2c");
+            assert_eq!(target("no tag here: really"), "");
+            assert_eq!(target("no colon"), "");
+        }
+
+        fn strips_crate_name() {
+            assert_eq!(strip_crate_name("flower_kernel::drivers::ps2"), "drivers::ps2");
+        }
+
+        fn parses_levels_ignoring_case() {
+            assert_eq!(parse_level("TRACE"), Ok(Level::Trace));
+            assert_eq!(parse_level("loud"), Err(FilterError::InvalidLevel("loud")));
+        }
+    }
+}
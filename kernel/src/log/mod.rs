@@ -0,0 +1,268 @@
+//! # Logging
+//!
+//! The `error!`, `warn!`, `info!`, `debug!` and `trace!` macros create a [Record], which the
+//! registry fans out to every registered [Sink] whose minimum level lets it through. Before that,
+//! records are checked against the runtime level [filter]s, which can be set from the kernel
+//! command line.
+//!
+//! The VGA terminal and the kernel log buffer ([dmesg]) are registered from the start; other sinks,
+//! such as a serial port or the QEMU debug console, are registered once they are available.
+//!
+//! Logging never panics or blocks: a sink which fails, or which is busy because the logging code
+//! interrupted something holding its lock, simply misses the record. Sinks wait on their device for
+//! a bounded time only, so a serial port whose UART stops draining misses records until it drains
+//! again.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! log::register(&sinks::DEBUGCON, Level::Debug)?;
+//! info!("kbd: successfully enabled");
+//! ```
+
+pub mod sinks;
+pub mod filter;
+pub mod dmesg;
+
+use color::{Color, ColorPair};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::RwLock;
+
+/// Logs a record of the given level, if the filters let it through. The message's format string
+/// must be a literal, optionally starting with a tag such as `ps2c:` which the filters can match.
+macro_rules! log {
+    ($level:expr, $fmt:expr) => (log!($level, $fmt,));
+    ($level:expr, $fmt:expr, $($arg:tt)*) => {
+        // Host tests have no sinks to log to, as the terminal draws straight into VGA memory
+        if cfg!(target_os = "flower") && $crate::log::filter::enabled($level) {
+            $crate::log::log(&$crate::log::Record {
+                level: $level,
+                target: $crate::log::filter::target($fmt),
+                module_path: module_path!(),
+                file: file!(),
+                line: line!(),
+                args: format_args!($fmt, $($arg)*),
+            });
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => (log!($crate::log::Level::Error, $($arg)*));
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => (log!($crate::log::Level::Warn, $($arg)*));
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => (log!($crate::log::Level::Info, $($arg)*));
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => (log!($crate::log::Level::Debug, $($arg)*));
+}
+
+/// Trace records are only compiled in with the `trace` feature, as they are logged often enough
+/// from hot paths, such as interrupt handlers, that even checking the filters would cost
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "trace") {
+            log!($crate::log::Level::Trace, $($arg)*);
+        }
+    };
+}
+
+/// The maximum amount of sinks which can be registered at once
+const MAX_SINKS: usize = 8;
+
+/// The registered sinks. Logging only ever reads this, so nested logging can't deadlock on it.
+static SINKS: RwLock<[Option<SinkEntry>; MAX_SINKS]> = RwLock::new([
+    Some(SinkEntry { sink: &sinks::TERMINAL, level: Level::Trace }),
+    Some(SinkEntry { sink: &dmesg::DMESG, level: Level::Trace }),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+]);
+
+/// The amount of times a sink failed to write a record
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// The severity of a log record, from most to least severe
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// Parses the name of a level, such as `debug`, ignoring case
+    pub fn from_name(name: &str) -> Option<Level> {
+        let levels = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+        levels.iter().cloned().find(|level| level.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// The prefix written before records of this level, padded to the same width for every level
+    pub fn prefix(&self) -> &'static str {
+        match *self {
+            Level::Error => "[error] ",
+            Level::Warn => "[warn]  ",
+            Level::Info => "[info]  ",
+            Level::Debug => "[debug] ",
+            Level::Trace => "[trace] ",
+        }
+    }
+
+    /// The color which the prefix is written in
+    pub fn color(&self) -> ColorPair {
+        let foreground = match *self {
+            Level::Error => Color::Red,
+            Level::Warn => Color::LightRed,
+            Level::Info => Color::LightBlue,
+            Level::Debug => Color::Cyan,
+            Level::Trace => Color::White,
+        };
+
+        ColorPair::new(foreground, Color::Black)
+    }
+}
+
+/// A single message to be logged, along with where it was logged from
+#[derive(Copy, Clone, Debug)]
+pub struct Record<'a> {
+    pub level: Level,
+    /// The tag the message starts with, such as `ps2c`, or an empty string if there is none
+    pub target: &'static str,
+    pub module_path: &'static str,
+    pub file: &'static str,
+    pub line: u32,
+    pub args: fmt::Arguments<'a>,
+}
+
+/// A destination for log records
+pub trait Sink: Send + Sync {
+    /// The name of this sink, which it is identified by in the registry
+    fn name(&self) -> &'static str;
+
+    /// Writes a record to this sink. This may be called from interrupt handlers, so must not wait
+    /// on a lock.
+    fn write(&self, record: &Record) -> Result<(), SinkError>;
+}
+
+/// An error which occurred while writing a record to a sink
+#[allow(dead_code)] // Dead variants for completeness
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SinkError {
+    /// The sink's lock is held, most likely by the code which was interrupted to log, or its device
+    /// isn't ready to take the record
+    Busy,
+    /// The sink's device is not set up
+    Unavailable,
+    /// The sink's device failed to write the record
+    WriteFailed,
+}
+
+/// An error which occurred while (un)registering a sink
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LogError {
+    /// The maximum amount of sinks are already registered
+    RegistryFull,
+    /// A sink with the same name is already registered
+    AlreadyRegistered(&'static str),
+    /// No sink with the given name is registered
+    NotRegistered,
+}
+
+#[derive(Copy, Clone)]
+struct SinkEntry {
+    sink: &'static Sink,
+    /// The least severe level which is written to the sink
+    level: Level,
+}
+
+/// Registers a sink, which is then written every record of the given level or more severe
+pub fn register(sink: &'static Sink, level: Level) -> Result<(), LogError> {
+    let mut sinks = SINKS.write();
+
+    if sinks.iter().flat_map(|entry| entry.as_ref()).any(|entry| entry.sink.name() == sink.name()) {
+        return Err(LogError::AlreadyRegistered(sink.name()));
+    }
+
+    let slot = sinks.iter_mut().find(|entry| entry.is_none()).ok_or(LogError::RegistryFull)?;
+    *slot = Some(SinkEntry { sink, level });
+
+    Ok(())
+}
+
+/// Unregisters the sink with the given name
+#[allow(dead_code)] // Part of API
+pub fn unregister(name: &str) -> Result<(), LogError> {
+    let mut sinks = SINKS.write();
+
+    let slot = sinks.iter_mut()
+        .find(|entry| entry.map_or(false, |entry| entry.sink.name() == name))
+        .ok_or(LogError::NotRegistered)?;
+    *slot = None;
+
+    Ok(())
+}
+
+/// Changes the minimum level of the sink with the given name
+#[allow(dead_code)] // Part of API
+pub fn set_level(name: &str, level: Level) -> Result<(), LogError> {
+    let mut sinks = SINKS.write();
+
+    let entry = sinks.iter_mut()
+        .flat_map(|entry| entry.as_mut())
+        .find(|entry| entry.sink.name() == name)
+        .ok_or(LogError::NotRegistered)?;
+    entry.level = level;
+
+    Ok(())
+}
+
+/// The amount of times a sink failed to write a record, or a record was dropped entirely
+#[allow(dead_code)] // Part of API
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Writes a record to every sink that accepts its level, if the filters let it through. Used by the
+/// logging macros.
+pub fn log(record: &Record) {
+    if !filter::allows(record) {
+        return;
+    }
+
+    // Only fails if a sink is being registered by the interrupted code
+    let sinks = match SINKS.try_read() {
+        Some(sinks) => sinks,
+        None => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+
+    for entry in sinks.iter().flat_map(|entry| entry.as_ref()).filter(|entry| record.level <= entry.level) {
+        if entry.sink.write(record).is_err() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! The built-in log sinks, other than the kernel log buffer in [dmesg](super::dmesg)
+
+use core::fmt::{self, Write};
+use drivers::serial::{self, SerialPort};
+use io::Port;
+use spin::RwLock;
+use terminal::{self, TerminalOutput};
+use super::{Level, Record, Sink, SinkError};
+
+/// Writes to the standard output, normally the VGA terminal
+pub static TERMINAL: TerminalSink = TerminalSink;
+
+/// Writes to COM1, which must be initialized before this is registered
+pub static COM1: SerialSink = SerialSink::new("com1", &serial::COM1);
+
+/// Writes to the QEMU and Bochs debug console
+pub static DEBUGCON: DebugconSink = DebugconSink;
+
+/// The port of the debug console, which reads back its own number if present
+const DEBUGCON_PORT: u16 = 0xE9;
+
+/// Writes records to the standard output
+pub struct TerminalSink;
+
+impl Sink for TerminalSink {
+    fn name(&self) -> &'static str {
+        "terminal"
+    }
+
+    fn write(&self, record: &Record) -> Result<(), SinkError> {
+        let mut stdout = terminal::STDOUT.try_write().ok_or(SinkError::Busy)?;
+
+        stdout.write_string_colored(record.level.prefix(), record.level.color())
+            .map_err(|_| SinkError::WriteFailed)?;
+        writeln!(stdout, "{}", record.args).map_err(|_| SinkError::WriteFailed)
+    }
+}
+
+/// Writes records to a serial port, with the same colors as the terminal
+pub struct SerialSink {
+    name: &'static str,
+    port: &'static RwLock<SerialPort>,
+}
+
+impl SerialSink {
+    pub const fn new(name: &'static str, port: &'static RwLock<SerialPort>) -> Self {
+        SerialSink { name, port }
+    }
+}
+
+impl Sink for SerialSink {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn write(&self, record: &Record) -> Result<(), SinkError> {
+        let mut port = self.port.try_write().ok_or(SinkError::Busy)?;
+
+        if !port.initialized() {
+            return Err(SinkError::Unavailable);
+        }
+
+        let written = port.write_string_colored(record.level.prefix(), record.level.color()).is_ok()
+            && writeln!(port, "{}{}", record.args, Location(record)).is_ok();
+
+        if written {
+            Ok(())
+        } else if port.stalled() {
+            // The UART isn't draining, and the port won't wait for it again until it does
+            Err(SinkError::Busy)
+        } else {
+            Err(SinkError::WriteFailed)
+        }
+    }
+}
+
+/// Writes records to port 0xE9, which QEMU's `-debugcon` and Bochs print to their console
+pub struct DebugconSink;
+
+impl DebugconSink {
+    /// Returns `true` if the debug console is present
+    pub fn present(&self) -> bool {
+        debugcon_port().read() == DEBUGCON_PORT as u8
+    }
+}
+
+impl Sink for DebugconSink {
+    fn name(&self) -> &'static str {
+        "debugcon"
+    }
+
+    fn write(&self, record: &Record) -> Result<(), SinkError> {
+        let mut writer = DebugconWriter(debugcon_port());
+        writeln!(writer, "{}{}{}", record.level.prefix(), record.args, Location(record))
+            .map_err(|_| SinkError::WriteFailed)
+    }
+}
+
+/// Formats where a debug or trace record was logged from, such as ` (src/drivers/ps2/mod.rs:80)`.
+/// The terminal is too narrow to fit this, but it is useful in the logs of headless runs.
+struct Location<'a, 'b: 'a>(&'a Record<'b>);
+
+impl<'a, 'b> fmt::Display for Location<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.level >= Level::Debug {
+            write!(f, " ({}:{})", self.0.file, self.0.line)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+struct DebugconWriter(Port<u8>);
+
+impl Write for DebugconWriter {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        for byte in s.bytes() {
+            self.0.write(byte);
+        }
+
+        Ok(())
+    }
+}
+
+fn debugcon_port() -> Port<u8> {
+    unsafe { Port::new(DEBUGCON_PORT) }
+}
@@ -0,0 +1,292 @@
+//! A bitmap frame allocator. Every frame up to the end of the highest available memory area has a
+//! bit in the bitmap, which is set if the frame is in use or not usable at all. A second bitmap
+//! marks the frames which are not usable, so that they can never be freed. Both bitmaps are placed
+//! in the first available memory large enough to hold them.
+//!
+//! Only memory below [IDENTITY_MAPPED_END] is managed, as memory above it can't be accessed yet.
+
+use core::{cmp, iter, slice};
+use multiboot::{BootInformation, MemoryMapTag};
+use super::{align_up, Frame, FrameRange, MemoryStats, IDENTITY_MAPPED_END, PAGE_SIZE};
+
+/// Memory below this is never allocated, as it holds the BIOS data areas, the EBDA and VGA memory
+const LOW_MEMORY_END: u64 = 0x100000;
+
+/// An error which occurred while setting up the frame allocator
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FrameAllocatorError {
+    /// The bootloader provided no memory map
+    NoMemoryMap,
+    /// The bootloader provided no (allocated) ELF sections, so the kernel image can't be located
+    NoElfSections,
+    /// No available memory area can hold the bitmaps, which are the given size in bytes
+    NoSpaceForBitmap(u64),
+}
+
+/// Allocates physical frames, keeping track of them in a bitmap
+pub struct FrameAllocator {
+    /// One bit per frame, set if the frame is in use
+    bitmap: &'static mut [u64],
+    /// One bit per frame, set if the frame is not usable, such as if it holds the kernel image or
+    /// isn't memory at all
+    reserved: &'static mut [u64],
+    /// The amount of frames tracked by the bitmap
+    frame_count: u64,
+    free_frames: u64,
+    total_frames: u64,
+    reserved_frames: u64,
+    /// The index of the first word in the bitmap which may contain a free frame
+    next_word: usize,
+}
+
+impl FrameAllocator {
+    /// Creates a frame allocator managing the available memory in the memory map of the given boot
+    /// information, excluding the kernel image, the boot information itself and the boot modules
+    ///
+    /// # Safety
+    ///
+    /// Must only be called once, as the bitmap is written into free memory
+    pub unsafe fn new(boot_info: &BootInformation) -> Result<Self, FrameAllocatorError> {
+        let memory_map = boot_info.memory_map().ok_or(FrameAllocatorError::NoMemoryMap)?;
+        let kernel = kernel_range(boot_info)?;
+
+        let end = memory_map.available_areas().map(|area| area.end_address()).max().unwrap_or(0);
+        let frame_count = cmp::min(end, IDENTITY_MAPPED_END) / PAGE_SIZE;
+        let words = ((frame_count + 63) / 64) as usize;
+        let bitmap_size = words as u64 * 8 * 2;
+
+        let bitmap_address = place_bitmap(memory_map, boot_info, kernel, bitmap_size)
+            .ok_or(FrameAllocatorError::NoSpaceForBitmap(bitmap_size))?;
+
+        let bitmaps = slice::from_raw_parts_mut(bitmap_address as *mut u64, words * 2);
+
+        // Every frame starts off reserved, and then only the whole frames in available areas are
+        // freed
+        for word in bitmaps.iter_mut() {
+            *word = !0;
+        }
+
+        let (bitmap, reserved) = bitmaps.split_at_mut(words);
+
+        let mut allocator = FrameAllocator {
+            bitmap,
+            reserved,
+            frame_count,
+            free_frames: 0,
+            total_frames: 0,
+            reserved_frames: 0,
+            next_word: 0,
+        };
+
+        for area in memory_map.available_areas() {
+            let start = align_up(area.start_address(), PAGE_SIZE) / PAGE_SIZE;
+            let end = cmp::min(area.end_address() / PAGE_SIZE, frame_count);
+
+            // Some memory maps have overlapping areas, so don't count frames twice
+            for number in start..end {
+                if allocator.is_used(number) {
+                    allocator.set_used(number, false);
+                    allocator.set_reserved(number, false);
+                    allocator.total_frames += 1;
+                }
+            }
+        }
+
+        let bitmap_range = (bitmap_address, bitmap_address + bitmap_size);
+
+        for (start, end) in reserved_ranges(boot_info, kernel).chain(iter::once(bitmap_range)) {
+            let end = cmp::min(align_up(end, PAGE_SIZE) / PAGE_SIZE, frame_count);
+
+            for number in start / PAGE_SIZE..end {
+                if !allocator.is_used(number) {
+                    allocator.set_used(number, true);
+                    allocator.set_reserved(number, true);
+                    allocator.reserved_frames += 1;
+                }
+            }
+        }
+
+        allocator.free_frames = allocator.total_frames - allocator.reserved_frames;
+
+        Ok(allocator)
+    }
+
+    /// Gets statistics about the memory managed by this allocator
+    pub fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            total: self.total_frames * PAGE_SIZE,
+            free: self.free_frames * PAGE_SIZE,
+            reserved: self.reserved_frames * PAGE_SIZE,
+        }
+    }
+
+    /// Allocates a single frame, returning `None` if out of memory
+    pub fn allocate_frame(&mut self) -> Option<Frame> {
+        let word = (self.next_word..self.bitmap.len()).find(|&index| self.bitmap[index] != !0)?;
+
+        // Bits past the last frame are never cleared, so this is always a valid frame
+        let number = word as u64 * 64 + (!self.bitmap[word]).trailing_zeros() as u64;
+
+        self.set_used(number, true);
+        self.free_frames -= 1;
+        self.next_word = word;
+
+        Some(Frame { number })
+    }
+
+    /// Frees a frame allocated by this allocator
+    ///
+    /// # Panics
+    ///
+    /// Panics if the frame is not allocated, or is reserved
+    pub fn deallocate_frame(&mut self, frame: Frame) {
+        assert!(
+            frame.number < self.frame_count && self.is_used(frame.number),
+            "frame {:#x} is not allocated",
+            frame.start_address(),
+        );
+        assert!(!self.is_reserved(frame.number), "frame {:#x} is reserved", frame.start_address());
+
+        self.set_used(frame.number, false);
+        self.free_frames += 1;
+        self.next_word = cmp::min(self.next_word, (frame.number / 64) as usize);
+    }
+
+    /// Allocates `count` physically contiguous frames, starting at a frame number which is a
+    /// multiple of `alignment` and ending below the physical address `limit`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the alignment is not a power of two
+    pub fn allocate_range(&mut self, count: u64, alignment: u64, limit: u64) -> Option<FrameRange> {
+        assert!(alignment.is_power_of_two(), "alignment must be a power of two");
+
+        if count == 0 {
+            return None;
+        }
+
+        let end = cmp::min(self.frame_count, limit / PAGE_SIZE);
+        let mut start = 0;
+
+        while start + count <= end {
+            match (start..start + count).rev().find(|&number| self.is_used(number)) {
+                // The range can't start at or before the last used frame in it
+                Some(used) => start = align_up(used + 1, alignment),
+                None => {
+                    for number in start..start + count {
+                        self.set_used(number, true);
+                    }
+
+                    self.free_frames -= count;
+
+                    return Some(FrameRange { start: Frame { number: start }, count });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Frees a range allocated by this allocator
+    ///
+    /// # Panics
+    ///
+    /// Panics if any frame in the range is not allocated, or is reserved
+    pub fn deallocate_range(&mut self, range: FrameRange) {
+        for frame in range.frames() {
+            self.deallocate_frame(frame);
+        }
+    }
+
+    fn is_used(&self, number: u64) -> bool {
+        bit(self.bitmap, number)
+    }
+
+    fn set_used(&mut self, number: u64, used: bool) {
+        set_bit(self.bitmap, number, used);
+    }
+
+    fn is_reserved(&self, number: u64) -> bool {
+        bit(self.reserved, number)
+    }
+
+    fn set_reserved(&mut self, number: u64, reserved: bool) {
+        set_bit(self.reserved, number, reserved);
+    }
+}
+
+fn bit(bitmap: &[u64], number: u64) -> bool {
+    bitmap[(number / 64) as usize] & (1 << (number % 64)) != 0
+}
+
+fn set_bit(bitmap: &mut [u64], number: u64, value: bool) {
+    let word = &mut bitmap[(number / 64) as usize];
+
+    if value {
+        *word |= 1 << (number % 64);
+    } else {
+        *word &= !(1 << (number % 64));
+    }
+}
+
+/// Finds the physical memory taken up by the kernel image, from its allocated ELF sections
+fn kernel_range(boot_info: &BootInformation) -> Result<(u64, u64), FrameAllocatorError> {
+    let elf_sections = boot_info.elf_sections().ok_or(FrameAllocatorError::NoElfSections)?;
+    let allocated = || elf_sections.sections().filter(|section| section.is_allocated());
+
+    let start = allocated().map(|section| section.start_address()).min();
+    let end = allocated().map(|section| section.end_address()).max();
+
+    match (start, end) {
+        (Some(start), Some(end)) => Ok((start, end)),
+        _ => Err(FrameAllocatorError::NoElfSections),
+    }
+}
+
+/// Gets the physical memory ranges which must never be allocated, as `(start, end)` pairs. The
+/// symbol and string tables loaded alongside the kernel are kept for symbolizing backtraces.
+fn reserved_ranges(boot_info: &BootInformation, kernel: (u64, u64)) -> impl Iterator<Item = (u64, u64)> {
+    let boot_info_range = (boot_info.start_address() as u64, boot_info.end_address() as u64);
+    let modules = boot_info.modules()
+        .map(|module| (module.start_address() as u64, module.end_address() as u64));
+    let tables = boot_info.elf_sections().into_iter()
+        .flat_map(|elf_sections| elf_sections.sections())
+        .filter(|section| section.is_loaded_table())
+        .map(|section| (section.start_address(), section.end_address()));
+
+    iter::once((0, LOW_MEMORY_END))
+        .chain(iter::once(kernel))
+        .chain(iter::once(boot_info_range))
+        .chain(modules)
+        .chain(tables)
+}
+
+/// Finds the first page aligned, identity mapped, available memory of the given size which doesn't
+/// overlap any reserved range
+fn place_bitmap(
+    memory_map: &MemoryMapTag,
+    boot_info: &BootInformation,
+    kernel: (u64, u64),
+    size: u64,
+) -> Option<u64> {
+    memory_map.available_areas()
+        .filter_map(|area| {
+            let mut start = align_up(area.start_address(), PAGE_SIZE);
+
+            loop {
+                let end = start + size;
+                if end > area.end_address() || end > IDENTITY_MAPPED_END {
+                    return None;
+                }
+
+                let overlap = reserved_ranges(boot_info, kernel)
+                    .find(|&(reserved_start, reserved_end)| reserved_start < end && start < reserved_end);
+
+                match overlap {
+                    Some((_, reserved_end)) => start = align_up(reserved_end, PAGE_SIZE),
+                    None => return Some(start),
+                }
+            }
+        })
+        .next()
+}
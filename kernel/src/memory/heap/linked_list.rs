@@ -0,0 +1,137 @@
+//! A linked list allocator. The free blocks of the heap are kept in a list sorted by address, so
+//! that neighbouring blocks can be merged when memory is freed.
+
+use core::{cmp, mem, ptr};
+use core::alloc::Layout;
+use memory::align_up;
+
+/// The header written at the start of every free block
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// The smallest block which can be tracked, as it must hold its header once freed
+const MIN_BLOCK_SIZE: usize = mem::size_of::<FreeBlock>();
+
+/// Allocates blocks from a list of free memory regions
+pub struct LinkedListAllocator {
+    head: *mut FreeBlock,
+}
+
+// The free blocks are only accessed through the allocator
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    /// Creates an allocator with no free memory
+    pub const fn new() -> Self {
+        LinkedListAllocator { head: ptr::null_mut() }
+    }
+
+    /// Adds the given region of memory to the free list
+    ///
+    /// # Safety
+    ///
+    /// The region must be unused, writable and aligned to 8 bytes, with a size which is a multiple
+    /// of 8 bytes and at least 16 bytes
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) {
+        self.free(start, size);
+    }
+
+    /// Allocates a block fitting the given layout, returning `None` if no free block is large enough
+    pub fn allocate(&mut self, layout: Layout) -> Option<*mut u8> {
+        let size = block_size(&layout);
+        let align = cmp::max(layout.align(), mem::align_of::<FreeBlock>());
+
+        let mut previous: *mut FreeBlock = ptr::null_mut();
+        let mut current = self.head;
+
+        unsafe {
+            while !current.is_null() {
+                let block_start = current as usize;
+                let block_end = block_start + (*current).size;
+
+                // Padding before the allocation is returned to the free list, so must fit a header
+                let mut start = align_to(block_start, align);
+                if start != block_start && start - block_start < MIN_BLOCK_SIZE {
+                    start = align_to(block_start + MIN_BLOCK_SIZE, align);
+                }
+
+                let end = start + size;
+                let remainder = block_end.saturating_sub(end);
+
+                if end <= block_end && (remainder == 0 || remainder >= MIN_BLOCK_SIZE) {
+                    let next = (*current).next;
+
+                    if previous.is_null() {
+                        self.head = next;
+                    } else {
+                        (*previous).next = next;
+                    }
+
+                    if start != block_start {
+                        self.free(block_start, start - block_start);
+                    }
+
+                    if remainder != 0 {
+                        self.free(end, remainder);
+                    }
+
+                    return Some(start as *mut u8);
+                }
+
+                previous = current;
+                current = (*current).next;
+            }
+        }
+
+        None
+    }
+
+    /// Frees a block allocated with the given layout
+    ///
+    /// # Safety
+    ///
+    /// The block must have been allocated by this allocator with the same layout
+    pub unsafe fn deallocate(&mut self, block: *mut u8, layout: Layout) {
+        self.free(block as usize, block_size(&layout));
+    }
+
+    /// Inserts the given region into the free list, merging it with its neighbours
+    unsafe fn free(&mut self, start: usize, size: usize) {
+        let mut previous: *mut FreeBlock = ptr::null_mut();
+        let mut current = self.head;
+
+        while !current.is_null() && (current as usize) < start {
+            previous = current;
+            current = (*current).next;
+        }
+
+        let block = start as *mut FreeBlock;
+        ptr::write(block, FreeBlock { size, next: current });
+
+        if !current.is_null() && start + size == current as usize {
+            (*block).size += (*current).size;
+            (*block).next = (*current).next;
+        }
+
+        if previous.is_null() {
+            self.head = block;
+        } else if previous as usize + (*previous).size == start {
+            (*previous).size += (*block).size;
+            (*previous).next = (*block).next;
+        } else {
+            (*previous).next = block;
+        }
+    }
+}
+
+/// The size of the block serving an allocation of the given layout
+fn block_size(layout: &Layout) -> usize {
+    let size = cmp::max(layout.size(), MIN_BLOCK_SIZE);
+    align_to(size, mem::align_of::<FreeBlock>())
+}
+
+fn align_to(address: usize, alignment: usize) -> usize {
+    align_up(address as u64, alignment as u64) as usize
+}
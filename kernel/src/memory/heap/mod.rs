@@ -0,0 +1,157 @@
+//! # Heap
+//!
+//! The kernel heap, which backs the `alloc` crate's `Box`, `Vec`, `String` and `BTreeMap`. The heap
+//! lives at [HEAP_START] in virtual memory, and more pages are mapped on demand as it fills up, up to
+//! [HEAP_MAX_SIZE]. Small allocations are served by the [slab] caches, and everything else (including
+//! the slabs themselves) from a [linked_list::LinkedListAllocator].
+//!
+//! The heap must be set up through `init` before anything is allocated. Allocating may lock the
+//! page tables and frame allocator, so interrupt handlers must never allocate. The heap itself is
+//! only locked with interrupts disabled, so an interrupt handler can't run while it is held.
+
+pub mod linked_list;
+pub mod slab;
+
+use core::{cmp, ptr};
+use core::alloc::{GlobalAlloc, Layout};
+use interrupts;
+use spin::Mutex;
+use super::{align_up, PAGE_SIZE};
+use super::paging::{self, EntryFlags, PagingError};
+use self::linked_list::LinkedListAllocator;
+
+/// The virtual address at which the heap starts, at the start of the higher half
+pub const HEAP_START: usize = 0xFFFF_8000_0000_0000;
+/// The size the heap can grow to
+pub const HEAP_MAX_SIZE: usize = 256 * 1024 * 1024;
+
+/// The size of the heap when it is set up
+const HEAP_INITIAL_SIZE: usize = 1024 * 1024;
+/// The heap grows by at least this much at a time, so that pages aren't mapped one by one
+const HEAP_MIN_GROWTH: usize = 64 * 1024;
+
+// Tests on the host use the host's allocator
+#[cfg(target_os = "flower")]
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator;
+
+static HEAP: Mutex<Option<Heap>> = Mutex::new(None);
+
+/// An error which occurred while growing the heap
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum HeapError {
+    /// The heap would grow past [HEAP_MAX_SIZE]
+    Exhausted,
+    /// The heap's pages could not be mapped
+    Paging(PagingError),
+}
+
+struct Heap {
+    free_list: LinkedListAllocator,
+    /// The end of the mapped part of the heap
+    end: usize,
+}
+
+impl Heap {
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        loop {
+            if let Some(block) = self.free_list.allocate(layout) {
+                return block;
+            }
+
+            // The new memory may need padding to be aligned
+            if self.grow(layout.size() + layout.align()).is_err() {
+                return ptr::null_mut();
+            }
+        }
+    }
+
+    /// Maps at least `size` bytes more at the end of the heap, and adds them to the free list
+    fn grow(&mut self, size: usize) -> Result<(), HeapError> {
+        let size = align_up(cmp::max(size, HEAP_MIN_GROWTH) as u64, PAGE_SIZE) as usize;
+
+        if self.end - HEAP_START + size > HEAP_MAX_SIZE {
+            return Err(HeapError::Exhausted);
+        }
+
+        paging::ACTIVE_TABLE.lock()
+            .map_range(self.end, size, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE)
+            .map_err(HeapError::Paging)?;
+
+        unsafe { self.free_list.add_region(self.end, size) };
+        self.end += size;
+
+        Ok(())
+    }
+}
+
+/// The global allocator, serving allocations from the heap
+struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| {
+            if let Some(cache) = slab::size_class(&layout) {
+                return cache.allocate().unwrap_or(ptr::null_mut());
+            }
+
+            match *HEAP.lock() {
+                Some(ref mut heap) => heap.allocate(layout),
+                None => ptr::null_mut(),
+            }
+        })
+    }
+
+    unsafe fn dealloc(&self, block: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| {
+            if let Some(cache) = slab::size_class(&layout) {
+                return cache.deallocate(block);
+            }
+
+            if let Some(ref mut heap) = *HEAP.lock() {
+                heap.free_list.deallocate(block, layout);
+            }
+        })
+    }
+}
+
+/// Maps the first pages of the heap. The frame allocator and paging must be set up.
+pub fn init() -> Result<(), HeapError> {
+    let mut heap = Heap { free_list: LinkedListAllocator::new(), end: HEAP_START };
+    heap.grow(HEAP_INITIAL_SIZE)?;
+
+    *HEAP.lock() = Some(heap);
+
+    Ok(())
+}
+
+/// Gets the amount of bytes of virtual memory which are mapped for the heap
+#[allow(dead_code)] // Part of API
+pub fn mapped_size() -> usize {
+    HEAP.lock().as_ref().map(|heap| heap.end - HEAP_START).unwrap_or(0)
+}
+
+/// Allocates a page aligned page from the heap for a slab
+fn allocate_page() -> Option<*mut u8> {
+    let layout = page_layout();
+
+    match *HEAP.lock() {
+        Some(ref mut heap) => Some(heap.allocate(layout)).filter(|page| !page.is_null()),
+        None => None,
+    }
+}
+
+/// Gives a page allocated by `allocate_page` back to the heap
+///
+/// # Safety
+///
+/// The page must have been allocated by `allocate_page`, and not freed since
+unsafe fn deallocate_page(page: *mut u8) {
+    if let Some(ref mut heap) = *HEAP.lock() {
+        heap.free_list.deallocate(page, page_layout());
+    }
+}
+
+fn page_layout() -> Layout {
+    Layout::from_size_align(PAGE_SIZE as usize, PAGE_SIZE as usize).expect("Page layout is valid")
+}
@@ -0,0 +1,281 @@
+//! A slab allocator for small, fixed size objects. Each [SlabCache] hands out objects of one size
+//! from slabs, which are single pages taken from the heap. A slab starts with a header holding the
+//! list of its free objects, so the slab of an object is found by rounding its address down to the
+//! page.
+//!
+//! Small allocations through the global allocator are served by the `kmalloc-*` size class caches.
+//! Subsystems with many objects of one type can declare their own named caches:
+//!
+//! ```rust,no_run
+//! static KEY_EVENTS: SlabCache = SlabCache::new("key_event", mem::size_of::<KeyEvent>(), 8);
+//!
+//! let event = KEY_EVENTS.allocate().expect("Out of memory") as *mut KeyEvent;
+//! unsafe { KEY_EVENTS.deallocate(event as *mut u8) };
+//! ```
+
+use alloc::vec::Vec;
+use core::{cmp, mem, ptr};
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicBool, Ordering};
+use memory::{align_up, PAGE_SIZE};
+use spin::{Mutex, RwLock};
+
+/// The maximum amount of caches which can be registered for statistics
+const MAX_CACHES: usize = 32;
+
+/// The caches serving small allocations through the global allocator. Each object is aligned to its
+/// size, so any layout whose size and alignment fit in a class can use it.
+static SIZE_CLASSES: [SlabCache; 7] = [
+    SlabCache::new("kmalloc-16", 16, 16),
+    SlabCache::new("kmalloc-32", 32, 32),
+    SlabCache::new("kmalloc-64", 64, 64),
+    SlabCache::new("kmalloc-128", 128, 128),
+    SlabCache::new("kmalloc-256", 256, 256),
+    SlabCache::new("kmalloc-512", 512, 512),
+    SlabCache::new("kmalloc-1024", 1024, 1024),
+];
+
+/// Every cache which has held a slab, for statistics
+static CACHES: RwLock<[Option<&'static SlabCache>; MAX_CACHES]> = RwLock::new([None; MAX_CACHES]);
+
+/// The header at the start of every slab
+struct Slab {
+    next: *mut Slab,
+    free: *mut FreeObject,
+    in_use: usize,
+}
+
+/// The header written into every free object
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+struct SlabList {
+    head: *mut Slab,
+    objects_in_use: usize,
+    pages_held: usize,
+}
+
+// The slabs are only accessed through their cache
+unsafe impl Send for SlabList {}
+
+/// Statistics about a slab cache
+#[derive(Copy, Clone, Debug)]
+pub struct SlabStats {
+    pub name: &'static str,
+    /// The size of the objects, including padding
+    pub object_size: usize,
+    pub objects_in_use: usize,
+    /// The amount of pages taken from the heap for slabs
+    pub pages_held: usize,
+}
+
+/// A named cache of objects of one size
+pub struct SlabCache {
+    name: &'static str,
+    size: usize,
+    align: usize,
+    registered: AtomicBool,
+    slabs: Mutex<SlabList>,
+}
+
+impl SlabCache {
+    /// Creates a cache of objects with the given size and alignment. Objects must fit in a page
+    /// along with the slab header.
+    pub const fn new(name: &'static str, size: usize, align: usize) -> Self {
+        SlabCache {
+            name,
+            size,
+            align,
+            registered: AtomicBool::new(false),
+            slabs: Mutex::new(SlabList { head: ptr::null_mut(), objects_in_use: 0, pages_held: 0 }),
+        }
+    }
+
+    /// Allocates an object, returning `None` if out of memory
+    ///
+    /// # Panics
+    ///
+    /// Panics if an object doesn't fit in a slab
+    pub fn allocate(&'static self) -> Option<*mut u8> {
+        self.register();
+
+        let mut slabs = self.slabs.lock();
+
+        unsafe {
+            let mut slab = slabs.head;
+            while !slab.is_null() && (*slab).free.is_null() {
+                slab = (*slab).next;
+            }
+
+            if slab.is_null() {
+                slab = self.create_slab()?;
+                (*slab).next = slabs.head;
+                slabs.head = slab;
+                slabs.pages_held += 1;
+            }
+
+            let object = (*slab).free;
+            (*slab).free = (*object).next;
+            (*slab).in_use += 1;
+            slabs.objects_in_use += 1;
+
+            Some(object as *mut u8)
+        }
+    }
+
+    /// Frees an object allocated from this cache
+    ///
+    /// # Safety
+    ///
+    /// The object must have been allocated from this cache, and not freed since
+    pub unsafe fn deallocate(&self, object: *mut u8) {
+        let mut slabs = self.slabs.lock();
+
+        let slab = (object as usize & !(PAGE_SIZE as usize - 1)) as *mut Slab;
+        let object = object as *mut FreeObject;
+
+        (*object).next = (*slab).free;
+        (*slab).free = object;
+        (*slab).in_use -= 1;
+        slabs.objects_in_use -= 1;
+
+        // Keep the last slab around, so that alternating allocations and frees don't keep taking
+        // and giving back a page
+        if (*slab).in_use == 0 && slabs.pages_held > 1 {
+            if slabs.head == slab {
+                slabs.head = (*slab).next;
+            } else {
+                let mut previous = slabs.head;
+                while (*previous).next != slab {
+                    previous = (*previous).next;
+                }
+
+                (*previous).next = (*slab).next;
+            }
+
+            super::deallocate_page(slab as *mut u8);
+            slabs.pages_held -= 1;
+        }
+    }
+
+    /// Gets statistics about this cache
+    pub fn stats(&self) -> SlabStats {
+        let slabs = self.slabs.lock();
+
+        SlabStats {
+            name: self.name,
+            object_size: self.stride(),
+            objects_in_use: slabs.objects_in_use,
+            pages_held: slabs.pages_held,
+        }
+    }
+
+    /// The alignment of objects, which is at least enough to hold a free object header
+    fn object_align(&self) -> usize {
+        cmp::max(self.align, mem::align_of::<FreeObject>())
+    }
+
+    /// The distance between the starts of neighbouring objects
+    fn stride(&self) -> usize {
+        let size = cmp::max(self.size, mem::size_of::<FreeObject>());
+        align_up(size as u64, self.object_align() as u64) as usize
+    }
+
+    /// Takes a page from the heap and builds a slab of free objects in it
+    unsafe fn create_slab(&self) -> Option<*mut Slab> {
+        let first = align_up(mem::size_of::<Slab>() as u64, self.object_align() as u64) as usize;
+        let count = (PAGE_SIZE as usize).saturating_sub(first) / self.stride();
+        assert!(count > 0, "objects of cache {} don't fit in a slab", self.name);
+
+        let slab = super::allocate_page()? as *mut Slab;
+        let mut free: *mut FreeObject = ptr::null_mut();
+
+        // Build the free list backwards, so that objects are handed out in address order
+        for index in (0..count).rev() {
+            let object = (slab as usize + first + index * self.stride()) as *mut FreeObject;
+            (*object).next = free;
+            free = object;
+        }
+
+        ptr::write(slab, Slab { next: ptr::null_mut(), free, in_use: 0 });
+
+        Some(slab)
+    }
+
+    fn register(&'static self) {
+        if self.registered.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        // If there are too many caches, only the statistics are lost. This is called from within
+        // the allocator, so it can't log, as a sink may allocate.
+        let mut caches = CACHES.write();
+        if let Some(slot) = caches.iter_mut().find(|cache| cache.is_none()) {
+            *slot = Some(self);
+        }
+    }
+}
+
+/// Gets the size class cache which can serve the given layout, if it is small enough
+pub fn size_class(layout: &Layout) -> Option<&'static SlabCache> {
+    let size = cmp::max(layout.size(), layout.align());
+    SIZE_CLASSES.iter().find(|cache| cache.size >= size)
+}
+
+/// Gets statistics about every cache which has held a slab
+#[allow(dead_code)] // Part of API
+pub fn stats() -> Vec<SlabStats> {
+    // Allocate first, as the allocation may itself register a cache
+    let mut stats = Vec::with_capacity(MAX_CACHES);
+
+    for cache in CACHES.read().iter().filter_map(|cache| *cache) {
+        stats.push(cache.stats());
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The name of the size class serving the given layout, or `None` if it is served by the heap
+    fn class(size: usize, align: usize) -> Option<&'static str> {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        size_class(&layout).map(|cache| cache.name)
+    }
+
+    kernel_test! {
+        fn picks_smallest_fitting_class() {
+            assert_eq!(class(1, 1), Some("kmalloc-16"));
+            assert_eq!(class(16, 8), Some("kmalloc-16"));
+            assert_eq!(class(17, 1), Some("kmalloc-32"));
+            assert_eq!(class(100, 4), Some("kmalloc-128"));
+            assert_eq!(class(1024, 8), Some("kmalloc-1024"));
+        }
+
+        fn alignment_selects_larger_class() {
+            assert_eq!(class(8, 64), Some("kmalloc-64"));
+            assert_eq!(class(24, 512), Some("kmalloc-512"));
+        }
+
+        fn large_allocations_fall_back_to_heap() {
+            assert_eq!(class(1025, 1), None);
+            assert_eq!(class(4096, 4096), None);
+            assert_eq!(class(16, 2048), None);
+        }
+
+        fn objects_hold_free_header() {
+            let cache = SlabCache::new("test-1", 1, 1);
+
+            assert_eq!(cache.object_align(), mem::align_of::<FreeObject>());
+            assert_eq!(cache.stride(), mem::size_of::<FreeObject>());
+        }
+
+        fn stride_is_aligned() {
+            assert_eq!(SlabCache::new("test-24", 24, 16).stride(), 32);
+            assert_eq!(SlabCache::new("test-64", 64, 64).stride(), 64);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! # Memory
+//!
+//! Manages physical memory. Usable memory is found through the multiboot memory map, and handed
+//! out in frames by the [frame_allocator::FrameAllocator]. The kernel image, the multiboot
+//! information and the boot modules are never handed out. Virtual memory is managed through
+//! [paging], and the kernel [heap] and guard paged kernel [stack]s are built on top of both.
+//!
+//! The frame allocator must be set up through `init` before frames can be allocated.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! let frame = memory::allocate_frame().expect("Out of memory");
+//! println!("Allocated frame at {:#x}", frame.start_address());
+//! memory::deallocate_frame(frame);
+//! ```
+
+pub mod frame_allocator;
+pub mod paging;
+pub mod heap;
+pub mod stack;
+
+use core::fmt;
+use multiboot::BootInformation;
+use spin::Mutex;
+use self::frame_allocator::{FrameAllocator, FrameAllocatorError};
+
+/// The size of a page or frame in bytes
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The end of the memory identity mapped by `boot.asm`. Memory above this cannot be accessed yet.
+pub const IDENTITY_MAPPED_END: u64 = 4 * 1024 * 1024 * 1024;
+
+static FRAME_ALLOCATOR: Mutex<Option<FrameAllocator>> = Mutex::new(None);
+
+/// A physical frame of memory
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct Frame {
+    pub number: u64,
+}
+
+impl Frame {
+    /// Gets the frame containing the given physical address
+    pub fn containing_address(address: u64) -> Self {
+        Frame { number: address / PAGE_SIZE }
+    }
+
+    /// The physical address at which this frame starts
+    pub fn start_address(&self) -> u64 {
+        self.number * PAGE_SIZE
+    }
+}
+
+/// A range of physically contiguous frames
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct FrameRange {
+    pub start: Frame,
+    /// The amount of frames in the range
+    pub count: u64,
+}
+
+impl FrameRange {
+    /// The physical address at which this range starts
+    #[allow(dead_code)] // Part of API
+    pub fn start_address(&self) -> u64 {
+        self.start.start_address()
+    }
+
+    /// The physical address at which this range ends (exclusive)
+    #[allow(dead_code)] // Part of API
+    pub fn end_address(&self) -> u64 {
+        self.start_address() + self.count * PAGE_SIZE
+    }
+
+    /// Gets an iterator over the frames in this range
+    pub fn frames(&self) -> impl Iterator<Item = Frame> {
+        let start = self.start.number;
+        (start..start + self.count).map(|number| Frame { number })
+    }
+}
+
+/// Statistics about physical memory, in bytes
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct MemoryStats {
+    /// All memory below [IDENTITY_MAPPED_END] which the memory map reports as available
+    pub total: u64,
+    /// Memory which can currently be allocated
+    pub free: u64,
+    /// Available memory which can never be allocated, as it holds the kernel, the boot information
+    /// and modules, or the frame allocator itself
+    pub reserved: u64,
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} MiB total, {} MiB free, {} KiB reserved",
+            self.total / (1024 * 1024),
+            self.free / (1024 * 1024),
+            self.reserved / 1024,
+        )
+    }
+}
+
+/// Rounds the given address up to a multiple of `alignment`, which must be a power of two
+pub fn align_up(address: u64, alignment: u64) -> u64 {
+    (address + alignment - 1) & !(alignment - 1)
+}
+
+/// Sets up the frame allocator from the memory map in the given boot information
+pub fn init(boot_info: &BootInformation) -> Result<MemoryStats, FrameAllocatorError> {
+    let allocator = unsafe { FrameAllocator::new(boot_info)? };
+    let stats = allocator.stats();
+
+    *FRAME_ALLOCATOR.lock() = Some(allocator);
+
+    Ok(stats)
+}
+
+/// Gets statistics about physical memory, if the frame allocator has been set up
+#[allow(dead_code)] // Part of API
+pub fn stats() -> Option<MemoryStats> {
+    FRAME_ALLOCATOR.lock().as_ref().map(|allocator| allocator.stats())
+}
+
+/// Allocates a single frame, returning `None` if out of memory or if the frame allocator has not
+/// been set up
+#[allow(dead_code)] // Part of API
+pub fn allocate_frame() -> Option<Frame> {
+    FRAME_ALLOCATOR.lock().as_mut().and_then(|allocator| allocator.allocate_frame())
+}
+
+/// Frees a frame allocated by `allocate_frame`
+///
+/// # Panics
+///
+/// Panics if the frame is not allocated
+#[allow(dead_code)] // Part of API
+pub fn deallocate_frame(frame: Frame) {
+    if let Some(ref mut allocator) = *FRAME_ALLOCATOR.lock() {
+        allocator.deallocate_frame(frame);
+    }
+}
+
+/// Allocates `count` physically contiguous frames, starting at a frame number which is a multiple
+/// of `alignment` frames and ending below the physical address `limit`. This is meant for DMA
+/// buffers, which devices access by physical address.
+#[allow(dead_code)] // Part of API
+pub fn allocate_range(count: u64, alignment: u64, limit: u64) -> Option<FrameRange> {
+    FRAME_ALLOCATOR.lock().as_mut().and_then(|allocator| allocator.allocate_range(count, alignment, limit))
+}
+
+/// Frees a range allocated by `allocate_range`
+///
+/// # Panics
+///
+/// Panics if any frame in the range is not allocated
+#[allow(dead_code)] // Part of API
+pub fn deallocate_range(range: FrameRange) {
+    if let Some(ref mut allocator) = *FRAME_ALLOCATOR.lock() {
+        allocator.deallocate_range(range);
+    }
+}
@@ -0,0 +1,472 @@
+//! # Paging
+//!
+//! Manages the active page tables, which `boot.asm` sets up to identity map the first 4 GiB with
+//! 2 MiB pages. Pages of 4 KiB, 2 MiB and 1 GiB can be mapped, unmapped, remapped and translated.
+//! Unmapping or remapping part of a larger page first splits it into smaller pages with the same
+//! flags.
+//!
+//! Page tables are accessed through the identity map, so new tables are always allocated below
+//! [IDENTITY_MAPPED_END], and the identity map of the tables must never be removed.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! let mut table = paging::ACTIVE_TABLE.lock();
+//!
+//! // Turn the page at 0x200000 into a guard page
+//! table.unmap(0x200000, PageSize::Small)?;
+//! assert_eq!(table.translate(0x200000), None);
+//! ```
+
+pub mod table;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{Mutex, RwLock};
+use x86_64::registers::msr::{rdmsr, wrmsr};
+use super::{Frame, IDENTITY_MAPPED_END, PAGE_SIZE};
+use self::table::{Entry, Table, ENTRY_COUNT};
+
+/// The extended feature enable register
+const IA32_EFER: u32 = 0xC000_0080;
+/// Enables the no-execute bit in page table entries
+const EFER_NO_EXECUTE_ENABLE: u64 = 1 << 11;
+/// Makes read only pages read only for the kernel too
+const CR0_WRITE_PROTECT: u64 = 1 << 16;
+
+/// The flags of entries pointing to tables. Access is restricted by the flags of the pages instead.
+const TABLE_FLAGS: EntryFlags = EntryFlags {
+    bits: EntryFlags::PRESENT.bits | EntryFlags::WRITABLE.bits | EntryFlags::USER.bits,
+};
+
+lazy_static! {
+    /// The page table loaded when the kernel booted
+    pub static ref ACTIVE_TABLE: Mutex<ActivePageTable> = Mutex::new(unsafe { ActivePageTable::new() });
+}
+
+static NO_EXECUTE_SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Called after the local TLB has been flushed, so that other CPUs can be told to flush theirs
+static SHOOTDOWN_HOOK: RwLock<Option<fn(Shootdown)>> = RwLock::new(None);
+
+bitflags! {
+    pub struct EntryFlags: u64 {
+        const PRESENT = 1 << 0;
+        const WRITABLE = 1 << 1;
+        /// If the page is accessible from user mode
+        const USER = 1 << 2;
+        const WRITE_THROUGH = 1 << 3;
+        const NO_CACHE = 1 << 4;
+        const ACCESSED = 1 << 5;
+        const DIRTY = 1 << 6;
+        /// If the entry maps a 2 MiB or 1 GiB page, rather than pointing to a table
+        const HUGE_PAGE = 1 << 7;
+        /// If the page stays in the TLB when CR3 is reloaded
+        const GLOBAL = 1 << 8;
+        const NO_EXECUTE = 1 << 63;
+    }
+}
+
+/// The size of a page
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PageSize {
+    /// A 4 KiB page, mapped by a P1 entry
+    Small,
+    /// A 2 MiB page, mapped by a P2 entry
+    Large,
+    /// A 1 GiB page, mapped by a P3 entry. Not all CPUs support these.
+    Huge,
+}
+
+impl PageSize {
+    /// The size of the page in bytes
+    pub fn bytes(&self) -> u64 {
+        match *self {
+            PageSize::Small => PAGE_SIZE,
+            PageSize::Large => PAGE_SIZE * ENTRY_COUNT as u64,
+            PageSize::Huge => PAGE_SIZE * (ENTRY_COUNT * ENTRY_COUNT) as u64,
+        }
+    }
+
+    /// The level of the tables whose entries map pages of this size
+    fn level(&self) -> usize {
+        match *self {
+            PageSize::Small => 1,
+            PageSize::Large => 2,
+            PageSize::Huge => 3,
+        }
+    }
+
+    fn from_level(level: usize) -> Self {
+        match level {
+            1 => PageSize::Small,
+            2 => PageSize::Large,
+            3 => PageSize::Huge,
+            _ => unreachable!("pages are only mapped by P1 to P3 tables"),
+        }
+    }
+}
+
+/// An error which occurred while changing the page tables
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PagingError {
+    /// The virtual address is not canonical
+    NonCanonical(usize),
+    /// The virtual address is not aligned to the page size
+    UnalignedPage(usize),
+    /// The physical address is not aligned to the page size
+    UnalignedFrame(u64),
+    /// The page, or part of it, is already mapped
+    AlreadyMapped(usize),
+    /// The page is not mapped
+    NotMapped(usize),
+    /// The page is mapped through smaller pages, so can't be changed as a whole
+    SizeMismatch(usize),
+    /// There are no frames left for page tables or for backing the page
+    OutOfMemory,
+    /// The CPU doesn't support pages of this size
+    Unsupported(PageSize),
+}
+
+/// What the TLBs of other CPUs must flush
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Shootdown {
+    /// The page of the given size at the given address
+    Page(usize, PageSize),
+    /// Every non global page
+    All,
+}
+
+/// How a virtual address is mapped
+#[derive(Copy, Clone, Debug)]
+pub struct Mapping {
+    /// The physical address of the start of the page
+    pub physical: u64,
+    pub size: PageSize,
+    pub flags: EntryFlags,
+}
+
+/// How to walk the page tables to an entry
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Walk {
+    /// Create missing tables, failing if a larger page is in the way
+    Create,
+    /// Split larger pages in the way, failing if a table is missing
+    Split,
+}
+
+/// Interface to the page tables loaded in CR3
+pub struct ActivePageTable {
+    /// The physical address of the P4 table
+    p4: u64,
+}
+
+impl ActivePageTable {
+    /// Creates an interface to the currently loaded page tables
+    ///
+    /// # Safety
+    ///
+    /// Only one interface may exist at a time
+    unsafe fn new() -> Self {
+        let cr3: u64;
+        asm!("mov %cr3, $0" : "=r"(cr3));
+
+        ActivePageTable { p4: cr3 & !0xFFF }
+    }
+
+    /// Maps the page of the given size at `address` to the physical memory at `physical`
+    #[allow(dead_code)] // Part of API
+    pub fn map_to(&mut self, address: usize, physical: u64, size: PageSize, flags: EntryFlags)
+        -> Result<(), PagingError>
+    {
+        check_page(address, size)?;
+
+        if physical % size.bytes() != 0 {
+            return Err(PagingError::UnalignedFrame(physical));
+        }
+
+        if size == PageSize::Huge && !cpu_has_huge_pages() {
+            return Err(PagingError::Unsupported(size));
+        }
+
+        let entry = self.entry_mut(address, size, Walk::Create)?;
+        if !entry.is_unused() {
+            return Err(PagingError::AlreadyMapped(address));
+        }
+
+        // Unused entries are never cached, so there is nothing to flush
+        entry.set(physical, page_flags(flags, size));
+
+        Ok(())
+    }
+
+    /// Maps the page of the given size at `address` to newly allocated physical memory, returning
+    /// the physical address of the memory
+    pub fn map(&mut self, address: usize, size: PageSize, flags: EntryFlags) -> Result<u64, PagingError> {
+        let frames = size.bytes() / PAGE_SIZE;
+        let range = super::allocate_range(frames, frames, u64::max_value())
+            .ok_or(PagingError::OutOfMemory)?;
+
+        match self.map_to(address, range.start_address(), size, flags) {
+            Ok(()) => Ok(range.start_address()),
+            Err(error) => {
+                super::deallocate_range(range);
+                Err(error)
+            }
+        }
+    }
+
+    /// Maps `size` bytes of newly allocated memory at `start` with 4 KiB pages. If this fails part
+    /// way, the pages mapped so far are unmapped and freed again.
+    pub fn map_range(&mut self, start: usize, size: usize, flags: EntryFlags) -> Result<(), PagingError> {
+        let pages = (start..start + size).step_by(PAGE_SIZE as usize);
+
+        for (count, address) in pages.clone().enumerate() {
+            if let Err(error) = self.map(address, PageSize::Small, flags) {
+                for mapped in pages.take(count) {
+                    if let Ok(physical) = self.unmap(mapped, PageSize::Small) {
+                        super::deallocate_frame(Frame::containing_address(physical));
+                    }
+                }
+
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unmaps the page of the given size at `address`, returning the physical address it was mapped
+    /// to. The physical memory is not freed.
+    pub fn unmap(&mut self, address: usize, size: PageSize) -> Result<u64, PagingError> {
+        check_page(address, size)?;
+
+        let entry = self.entry_mut(address, size, Walk::Split)?;
+        check_maps_page(entry, address, size)?;
+
+        let physical = page_address(entry, size);
+        entry.set_unused();
+        flush(Shootdown::Page(address, size));
+
+        Ok(physical)
+    }
+
+    /// Changes the flags of the page of the given size at `address`
+    #[allow(dead_code)] // Part of API
+    pub fn remap(&mut self, address: usize, size: PageSize, flags: EntryFlags) -> Result<(), PagingError> {
+        check_page(address, size)?;
+
+        let entry = self.entry_mut(address, size, Walk::Split)?;
+        check_maps_page(entry, address, size)?;
+
+        let physical = page_address(entry, size);
+        entry.set(physical, page_flags(flags, size));
+        flush(Shootdown::Page(address, size));
+
+        Ok(())
+    }
+
+    /// Finds how the given virtual address is mapped, if it is
+    pub fn mapping(&self, address: usize) -> Option<Mapping> {
+        let mut table = unsafe { &*(self.p4 as *const Table) };
+
+        for level in (1..5).rev() {
+            let entry = &table[table_index(address, level)];
+
+            if !entry.flags().contains(EntryFlags::PRESENT) {
+                return None;
+            }
+
+            if level == 1 || entry.flags().contains(EntryFlags::HUGE_PAGE) {
+                let size = PageSize::from_level(level);
+                return Some(Mapping { physical: page_address(entry, size), size, flags: entry.flags() });
+            }
+
+            table = unsafe { &*(entry.address() as *const Table) };
+        }
+
+        unreachable!("P1 entries always map pages")
+    }
+
+    /// Translates the given virtual address to a physical address, if it is mapped
+    pub fn translate(&self, address: usize) -> Option<u64> {
+        self.mapping(address).map(|mapping| mapping.physical + address as u64 % mapping.size.bytes())
+    }
+
+    /// Walks the page tables to the entry mapping the page of the given size at `address`
+    fn entry_mut(&mut self, address: usize, size: PageSize, walk: Walk) -> Result<&mut Entry, PagingError> {
+        let mut table = unsafe { &mut *(self.p4 as *mut Table) };
+        let mut level = 4;
+
+        loop {
+            let entry = &mut table[table_index(address, level)];
+
+            if level == size.level() {
+                return Ok(entry);
+            }
+
+            if entry.is_unused() {
+                match walk {
+                    Walk::Create => entry.set(allocate_table()?, TABLE_FLAGS),
+                    Walk::Split => return Err(PagingError::NotMapped(address)),
+                }
+            } else if entry.flags().contains(EntryFlags::HUGE_PAGE) {
+                match walk {
+                    Walk::Create => return Err(PagingError::AlreadyMapped(address)),
+                    Walk::Split => split(entry, level)?,
+                }
+            } else if !entry.flags().contains(TABLE_FLAGS) {
+                // The tables set up by boot.asm don't allow user access
+                let table_address = entry.address();
+                entry.set(table_address, entry.flags() | TABLE_FLAGS);
+            }
+
+            table = unsafe { &mut *(entry.address() as *mut Table) };
+            level -= 1;
+        }
+    }
+}
+
+/// Enables the no-execute bit (if supported) and write protection for the kernel
+pub fn init() {
+    if cpu_has_no_execute() {
+        unsafe {
+            wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NO_EXECUTE_ENABLE);
+        }
+
+        NO_EXECUTE_SUPPORTED.store(true, Ordering::Release);
+    } else {
+        warn!("paging: no-execute unsupported, pages will be executable");
+    }
+
+    unsafe {
+        let cr0: u64;
+        asm!("mov %cr0, $0" : "=r"(cr0));
+        asm!("mov $0, %cr0" :: "r"(cr0 | CR0_WRITE_PROTECT) : "memory" : "volatile");
+    }
+}
+
+/// Sets the function called whenever the TLB is flushed after changing the page tables. This is
+/// meant for telling other CPUs to flush their TLBs.
+#[allow(dead_code)] // Part of API
+pub fn set_shootdown_hook(hook: Option<fn(Shootdown)>) {
+    *SHOOTDOWN_HOOK.write() = hook;
+}
+
+/// Splits the page mapped by the given entry of a level `level` table into a new table of pages one
+/// size smaller, with the same flags
+fn split(entry: &mut Entry, level: usize) -> Result<(), PagingError> {
+    let size = PageSize::from_level(level);
+    let child_size = PageSize::from_level(level - 1);
+
+    let child_flags = match child_size {
+        PageSize::Small => entry.flags() - EntryFlags::HUGE_PAGE,
+        _ => entry.flags(),
+    };
+
+    let physical = page_address(entry, size);
+    let table_address = allocate_table()?;
+    let table = unsafe { &mut *(table_address as *mut Table) };
+
+    for index in 0..ENTRY_COUNT {
+        table[index].set(physical + index as u64 * child_size.bytes(), child_flags);
+    }
+
+    entry.set(table_address, TABLE_FLAGS);
+    flush(Shootdown::All);
+
+    Ok(())
+}
+
+/// Allocates and clears a new page table, returning its physical address
+fn allocate_table() -> Result<u64, PagingError> {
+    let range = super::allocate_range(1, 1, IDENTITY_MAPPED_END).ok_or(PagingError::OutOfMemory)?;
+
+    let table = unsafe { &mut *(range.start_address() as *mut Table) };
+    table.zero();
+
+    Ok(range.start_address())
+}
+
+/// Flushes the given mappings from this CPU's TLB, and then calls the shootdown hook
+fn flush(shootdown: Shootdown) {
+    unsafe {
+        match shootdown {
+            Shootdown::Page(address, _) => asm!("invlpg ($0)" :: "r"(address) : "memory" : "volatile"),
+            Shootdown::All => asm!("mov %cr3, %rax; mov %rax, %cr3" ::: "rax", "memory" : "volatile"),
+        }
+    }
+
+    let hook = *SHOOTDOWN_HOOK.read();
+    if let Some(hook) = hook {
+        hook(shootdown);
+    }
+}
+
+/// Checks that the given entry maps a whole page of the given size
+fn check_maps_page(entry: &Entry, address: usize, size: PageSize) -> Result<(), PagingError> {
+    if entry.is_unused() {
+        Err(PagingError::NotMapped(address))
+    } else if size != PageSize::Small && !entry.flags().contains(EntryFlags::HUGE_PAGE) {
+        Err(PagingError::SizeMismatch(address))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that the given virtual address is canonical and aligned to the page size
+fn check_page(address: usize, size: PageSize) -> Result<(), PagingError> {
+    // Bits 48 to 63 must be copies of bit 47
+    let high = address >> 47;
+    if high != 0 && high != 0x1FFFF {
+        return Err(PagingError::NonCanonical(address));
+    }
+
+    if address as u64 % size.bytes() != 0 {
+        return Err(PagingError::UnalignedPage(address));
+    }
+
+    Ok(())
+}
+
+/// Gets the physical address of the page mapped by the given entry. Bit 12 is the PAT bit in the
+/// entries of larger pages, rather than part of the address.
+fn page_address(entry: &Entry, size: PageSize) -> u64 {
+    entry.address() & !(size.bytes() - 1)
+}
+
+/// Adds the flags required for a page of the given size, and drops the no-execute bit if it is not
+/// supported (as it is reserved otherwise)
+fn page_flags(flags: EntryFlags, size: PageSize) -> EntryFlags {
+    let mut flags = flags | EntryFlags::PRESENT;
+    flags.set(EntryFlags::HUGE_PAGE, size != PageSize::Small);
+
+    if !NO_EXECUTE_SUPPORTED.load(Ordering::Acquire) {
+        flags.remove(EntryFlags::NO_EXECUTE);
+    }
+
+    flags
+}
+
+/// The index into the level `level` table for the given virtual address
+fn table_index(address: usize, level: usize) -> usize {
+    (address >> (12 + 9 * (level - 1))) & (ENTRY_COUNT - 1)
+}
+
+/// Returns `true` if CPUID reports that the CPU supports the no-execute bit
+fn cpu_has_no_execute() -> bool {
+    extended_features() & (1 << 20) != 0
+}
+
+/// Returns `true` if CPUID reports that the CPU supports 1 GiB pages
+fn cpu_has_huge_pages() -> bool {
+    extended_features() & (1 << 26) != 0
+}
+
+fn extended_features() -> u32 {
+    let edx: u32;
+    unsafe {
+        asm!("cpuid" : "={edx}"(edx) : "{eax}"(0x8000_0001u32) : "eax", "ebx", "ecx" : "volatile");
+    }
+
+    edx
+}
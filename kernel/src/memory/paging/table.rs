@@ -0,0 +1,68 @@
+//! Page tables and their entries
+
+use core::ops::{Index, IndexMut};
+use super::EntryFlags;
+
+/// The amount of entries in a page table
+pub const ENTRY_COUNT: usize = 512;
+
+/// Bits 12 to 51 of an entry hold the physical address it points to
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// An entry in a page table, pointing either to a page or to the next level's table
+#[derive(Copy, Clone)]
+pub struct Entry(u64);
+
+impl Entry {
+    /// Returns `true` if this entry has never been set, or has been cleared
+    pub fn is_unused(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn set_unused(&mut self) {
+        self.0 = 0;
+    }
+
+    pub fn flags(&self) -> EntryFlags {
+        EntryFlags::from_bits_truncate(self.0)
+    }
+
+    /// The physical address this entry points to
+    pub fn address(&self) -> u64 {
+        self.0 & ADDRESS_MASK
+    }
+
+    pub fn set(&mut self, address: u64, flags: EntryFlags) {
+        debug_assert!(address & !ADDRESS_MASK == 0, "address {:#x} is not page aligned", address);
+        self.0 = address | flags.bits();
+    }
+}
+
+/// A page table of any level
+#[repr(C)]
+pub struct Table {
+    entries: [Entry; ENTRY_COUNT],
+}
+
+impl Table {
+    /// Clears every entry in this table
+    pub fn zero(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.set_unused();
+        }
+    }
+}
+
+impl Index<usize> for Table {
+    type Output = Entry;
+
+    fn index(&self, index: usize) -> &Entry {
+        &self.entries[index]
+    }
+}
+
+impl IndexMut<usize> for Table {
+    fn index_mut(&mut self, index: usize) -> &mut Entry {
+        &mut self.entries[index]
+    }
+}
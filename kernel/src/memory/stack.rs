@@ -0,0 +1,108 @@
+//! Kernel stacks. Every stack has an unmapped guard page below it, so that overflowing the stack
+//! page faults rather than silently corrupting whatever lies below it.
+//!
+//! Stacks are allocated in their own region of virtual memory, and are never freed. The boot stack
+//! from `boot.asm` gets its guard page through `protect_boot_stack`.
+
+use spin::Mutex;
+use super::PAGE_SIZE;
+use super::paging::{self, EntryFlags, PageSize, PagingError};
+
+/// The virtual address at which the region holding kernel stacks starts
+pub const STACKS_START: usize = 0xFFFF_FF00_0000_0000;
+/// The size of the region holding kernel stacks
+pub const STACKS_MAX_SIZE: usize = 1024 * 1024 * 1024;
+
+/// The start of the unused part of the stack region
+static NEXT_STACK: Mutex<usize> = Mutex::new(STACKS_START);
+
+extern {
+    /// The page below the boot stack, from `boot.asm`
+    static stack_guard: u8;
+}
+
+/// An error which occurred while allocating a stack
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StackError {
+    /// There is no space left in the stack region
+    Exhausted,
+    /// The stack's pages could not be mapped
+    Paging(PagingError),
+}
+
+/// A kernel stack, growing down from `top` to `bottom`
+#[derive(Copy, Clone, Debug)]
+pub struct Stack {
+    top: usize,
+    bottom: usize,
+}
+
+impl Stack {
+    /// The address just past the top of the stack, which the stack pointer starts at
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    /// The lowest address of the stack. The guard page is just below this.
+    #[allow(dead_code)] // Part of API
+    pub fn bottom(&self) -> usize {
+        self.bottom
+    }
+}
+
+/// Allocates a stack of the given amount of pages, with a guard page below it
+pub fn allocate(pages: usize) -> Result<Stack, StackError> {
+    let mut next = NEXT_STACK.lock();
+
+    // The guard page is simply left unmapped
+    let bottom = *next + PAGE_SIZE as usize;
+    let top = bottom + pages * PAGE_SIZE as usize;
+
+    if top > STACKS_START + STACKS_MAX_SIZE {
+        return Err(StackError::Exhausted);
+    }
+
+    paging::ACTIVE_TABLE.lock()
+        .map_range(bottom, top - bottom, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE)
+        .map_err(StackError::Paging)?;
+
+    *next = top;
+
+    Ok(Stack { top, bottom })
+}
+
+/// Unmaps the guard page below the boot stack. Its frame stays reserved, as it is part of the
+/// kernel image.
+pub fn protect_boot_stack() -> Result<(), PagingError> {
+    paging::ACTIVE_TABLE.lock().unmap(boot_stack_guard(), PageSize::Small).map(|_| ())
+}
+
+/// Returns `true` if the given address is in the guard page of a stack, meaning that a fault at it
+/// is most likely a stack overflow
+pub fn is_guard_page(address: usize) -> bool {
+    let guard = boot_stack_guard();
+    if address >= guard && address < guard + PAGE_SIZE as usize {
+        return true;
+    }
+
+    // Everything mapped in the stack region is a stack, so any other address in the allocated part
+    // of the region is in a guard page. This is called from exception handlers, so must not wait
+    // on a lock which the faulting code may hold.
+    let next = match NEXT_STACK.try_lock() {
+        Some(next) => *next,
+        None => return false,
+    };
+
+    if address < STACKS_START || address >= next {
+        return false;
+    }
+
+    match paging::ACTIVE_TABLE.try_lock() {
+        Some(table) => table.translate(address).is_none(),
+        None => false,
+    }
+}
+
+fn boot_stack_guard() -> usize {
+    unsafe { &stack_guard as *const u8 as usize }
+}
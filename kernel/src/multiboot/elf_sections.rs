@@ -0,0 +1,226 @@
+//! The ELF sections tag, holding the section headers of the kernel image
+
+use core::{mem, slice, str};
+
+/// The ELF sections tag, followed by the section headers
+#[allow(dead_code)] // Dead fields for completeness
+#[repr(C)]
+pub struct ElfSectionsTag {
+    typ: u32,
+    size: u32,
+    count: u32,
+    entry_size: u32,
+    /// The index of the section holding the section names
+    string_table_index: u32,
+}
+
+impl ElfSectionsTag {
+    /// Gets an iterator over all sections of the kernel image, skipping the null section
+    pub fn sections(&self) -> ElfSections {
+        let (start, count) = self.headers();
+
+        ElfSections {
+            address: start,
+            remaining: count,
+            entry_size: self.entry_size as usize,
+            string_table: self.section(self.string_table_index as usize),
+        }
+        .skip_null()
+    }
+
+    /// Gets the section with the given index, as referred to by other sections' links
+    pub fn section(&self, index: usize) -> Option<&'static ElfSection> {
+        let (start, count) = self.headers();
+
+        if index < count {
+            Some(unsafe { &*((start + index * self.entry_size as usize) as *const ElfSection) })
+        } else {
+            None
+        }
+    }
+
+    /// The address of the first section header and the amount of headers
+    fn headers(&self) -> (usize, usize) {
+        let start = self as *const ElfSectionsTag as usize + mem::size_of::<ElfSectionsTag>();
+        let end = self as *const ElfSectionsTag as usize + self.size as usize;
+        let entry_size = self.entry_size as usize;
+
+        // Never read past the end of the tag, even if the count says otherwise
+        let count = if entry_size >= mem::size_of::<ElfSection>() {
+            (self.count as usize).min((end - start) / entry_size)
+        } else {
+            0
+        };
+
+        (start, count)
+    }
+
+    /// Finds the section with the given name
+    #[allow(dead_code)] // Part of API
+    pub fn find(&self, name: &str) -> Option<&'static ElfSection> {
+        let mut sections = self.sections();
+        let string_table = sections.string_table;
+
+        sections.find(|section| section.name(string_table) == Some(name))
+    }
+}
+
+/// A section header of the kernel image (ELF64)
+#[allow(dead_code)] // Dead fields for completeness
+#[derive(Debug)]
+#[repr(C)]
+pub struct ElfSection {
+    name_index: u32,
+    typ: u32,
+    flags: u64,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    alignment: u64,
+    entry_size: u64,
+}
+
+bitflags! {
+    pub struct ElfSectionFlags: u64 {
+        /// If the section is writable
+        const WRITABLE = 1 << 0;
+        /// If the section occupies memory while executing
+        const ALLOCATED = 1 << 1;
+        /// If the section contains executable code
+        const EXECUTABLE = 1 << 2;
+    }
+}
+
+/// The type of an ELF section
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ElfSectionType {
+    Unused,
+    ProgramBits,
+    SymbolTable,
+    StringTable,
+    NoBits,
+    Other(u32),
+}
+
+impl ElfSection {
+    /// The (physical) address at which this section is loaded
+    pub fn start_address(&self) -> u64 {
+        self.address
+    }
+
+    /// The (physical) address at which this section ends (exclusive)
+    pub fn end_address(&self) -> u64 {
+        self.address + self.size
+    }
+
+    /// The size of this section in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The flags of this section
+    pub fn flags(&self) -> ElfSectionFlags {
+        ElfSectionFlags::from_bits_truncate(self.flags)
+    }
+
+    /// The type of this section
+    pub fn section_type(&self) -> ElfSectionType {
+        match self.typ {
+            0 => ElfSectionType::Unused,
+            1 => ElfSectionType::ProgramBits,
+            2 => ElfSectionType::SymbolTable,
+            3 => ElfSectionType::StringTable,
+            8 => ElfSectionType::NoBits,
+            other => ElfSectionType::Other(other),
+        }
+    }
+
+    /// The section linked to this one. For a symbol table, this is its string table
+    pub fn link(&self) -> u32 {
+        self.link
+    }
+
+    /// The size of the entries of this section, if it is a table
+    pub fn entry_size(&self) -> u64 {
+        self.entry_size
+    }
+
+    /// Returns `true` if this section is loaded into memory
+    pub fn is_allocated(&self) -> bool {
+        self.flags().contains(ElfSectionFlags::ALLOCATED)
+    }
+
+    /// Returns `true` if this is a symbol or string table which the bootloader loaded into memory,
+    /// even though it isn't needed for the kernel to run
+    pub fn is_loaded_table(&self) -> bool {
+        let table = match self.section_type() {
+            ElfSectionType::SymbolTable | ElfSectionType::StringTable => true,
+            _ => false,
+        };
+
+        table && !self.is_allocated() && self.address != 0
+    }
+
+    /// The contents of this section, if it is loaded into memory
+    pub fn data(&self) -> Option<&'static [u8]> {
+        if self.address == 0 {
+            return None;
+        }
+
+        Some(unsafe { slice::from_raw_parts(self.address as usize as *const u8, self.size as usize) })
+    }
+
+    /// Gets the name of this section from the given section name string table
+    pub fn name(&self, string_table: Option<&ElfSection>) -> Option<&'static str> {
+        let string_table = string_table?;
+        let strings = unsafe {
+            slice::from_raw_parts(string_table.address as usize as *const u8, string_table.size as usize)
+        };
+
+        let start = self.name_index as usize;
+        if start >= strings.len() {
+            return None;
+        }
+
+        let length = strings[start..].iter().position(|&byte| byte == 0)?;
+        str::from_utf8(&strings[start..start + length]).ok()
+    }
+}
+
+/// Iterates over the sections in an [ElfSectionsTag]
+pub struct ElfSections {
+    address: usize,
+    remaining: usize,
+    entry_size: usize,
+    /// The section holding the section names
+    pub string_table: Option<&'static ElfSection>,
+}
+
+impl ElfSections {
+    fn skip_null(mut self) -> Self {
+        if self.remaining > 0 {
+            self.address += self.entry_size;
+            self.remaining -= 1;
+        }
+
+        self
+    }
+}
+
+impl Iterator for ElfSections {
+    type Item = &'static ElfSection;
+
+    fn next(&mut self) -> Option<&'static ElfSection> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let section = unsafe { &*(self.address as *const ElfSection) };
+        self.address += self.entry_size;
+        self.remaining -= 1;
+
+        Some(section)
+    }
+}
@@ -0,0 +1,87 @@
+//! The framebuffer tag, describing the framebuffer set up by the bootloader
+
+use core::ptr;
+
+/// The framebuffer tag, followed by information about its colors
+#[allow(dead_code)] // Dead fields for completeness
+#[repr(C, packed)]
+pub struct FramebufferTag {
+    typ: u32,
+    size: u32,
+    address: u64,
+    pitch: u32,
+    width: u32,
+    height: u32,
+    bits_per_pixel: u8,
+    framebuffer_type: u8,
+    // GRUB uses a 16 bit reserved field here, although the specification says 8 bits
+    reserved: u16,
+}
+
+/// The position and size of a color channel within a pixel, in bits
+#[derive(Copy, Clone, Debug)]
+pub struct ColorField {
+    pub position: u8,
+    pub size: u8,
+}
+
+/// How the framebuffer represents colors
+#[derive(Copy, Clone, Debug)]
+pub enum FramebufferType {
+    /// Each pixel is an index into a palette of the given amount of colors
+    Indexed { palette_colors: u32 },
+    /// Each pixel holds red, green and blue values directly
+    Rgb { red: ColorField, green: ColorField, blue: ColorField },
+    /// The framebuffer is an EGA text buffer, such as VGA's at `0xb8000`
+    Text,
+    Unknown(u8),
+}
+
+impl FramebufferTag {
+    /// The physical address of the framebuffer
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// The size of one row of the framebuffer in bytes
+    pub fn pitch(&self) -> u32 {
+        self.pitch
+    }
+
+    /// The width of the framebuffer, in pixels or characters
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the framebuffer, in pixels or characters
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The amount of bits each pixel takes up
+    pub fn bits_per_pixel(&self) -> u8 {
+        self.bits_per_pixel
+    }
+
+    /// How the framebuffer represents colors
+    pub fn framebuffer_type(&self) -> FramebufferType {
+        let color_info = self as *const FramebufferTag as usize + 32;
+        let field = |index: usize| unsafe {
+            ColorField {
+                position: ptr::read((color_info + index * 2) as *const u8),
+                size: ptr::read((color_info + index * 2 + 1) as *const u8),
+            }
+        };
+
+        let color_info_size = self.size as usize - 32;
+
+        match self.framebuffer_type {
+            0 if color_info_size >= 4 => FramebufferType::Indexed {
+                palette_colors: unsafe { ptr::read_unaligned(color_info as *const u32) },
+            },
+            1 if color_info_size >= 6 => FramebufferType::Rgb { red: field(0), green: field(1), blue: field(2) },
+            2 => FramebufferType::Text,
+            unknown => FramebufferType::Unknown(unknown),
+        }
+    }
+}
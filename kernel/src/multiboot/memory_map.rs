@@ -0,0 +1,109 @@
+//! The memory map tag, describing which areas of physical memory are usable
+
+use core::mem;
+
+/// The memory map tag, followed by its entries
+#[allow(dead_code)] // Dead fields for completeness
+#[repr(C)]
+pub struct MemoryMapTag {
+    typ: u32,
+    size: u32,
+    entry_size: u32,
+    entry_version: u32,
+}
+
+impl MemoryMapTag {
+    /// Gets an iterator over all memory areas in the map
+    pub fn areas(&self) -> MemoryAreas {
+        let start = self as *const MemoryMapTag as usize + mem::size_of::<MemoryMapTag>();
+        let end = self as *const MemoryMapTag as usize + self.size as usize;
+
+        MemoryAreas {
+            address: start,
+            end,
+            entry_size: self.entry_size as usize,
+        }
+    }
+
+    /// Gets an iterator over the memory areas which are available for general use
+    pub fn available_areas(&self) -> impl Iterator<Item = &'static MemoryArea> {
+        self.areas().filter(|area| area.area_type() == MemoryAreaType::Available)
+    }
+}
+
+/// An area of physical memory
+#[allow(dead_code)] // Dead fields for completeness
+#[derive(Debug)]
+#[repr(C)]
+pub struct MemoryArea {
+    base_address: u64,
+    length: u64,
+    typ: u32,
+    reserved: u32,
+}
+
+impl MemoryArea {
+    /// The physical address at which this area starts
+    pub fn start_address(&self) -> u64 {
+        self.base_address
+    }
+
+    /// The physical address at which this area ends (exclusive)
+    pub fn end_address(&self) -> u64 {
+        self.base_address + self.length
+    }
+
+    /// The size of this area in bytes
+    pub fn size(&self) -> u64 {
+        self.length
+    }
+
+    /// What this area is used for
+    pub fn area_type(&self) -> MemoryAreaType {
+        match self.typ {
+            1 => MemoryAreaType::Available,
+            3 => MemoryAreaType::AcpiReclaimable,
+            4 => MemoryAreaType::AcpiNvs,
+            5 => MemoryAreaType::Defective,
+            _ => MemoryAreaType::Reserved,
+        }
+    }
+}
+
+/// What an area of physical memory is used for
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MemoryAreaType {
+    /// Usable RAM
+    Available,
+    /// Reserved for the system or used by devices
+    Reserved,
+    /// Holds ACPI tables, and is usable once they are no longer needed
+    AcpiReclaimable,
+    /// Must be preserved across hibernation
+    AcpiNvs,
+    /// Defective RAM
+    Defective,
+}
+
+/// Iterates over the areas in a [MemoryMapTag]
+pub struct MemoryAreas {
+    address: usize,
+    end: usize,
+    entry_size: usize,
+}
+
+impl Iterator for MemoryAreas {
+    type Item = &'static MemoryArea;
+
+    fn next(&mut self) -> Option<&'static MemoryArea> {
+        // Entries may grow in future versions, but never shrink
+        if self.entry_size < mem::size_of::<MemoryArea>() || self.address + self.entry_size > self.end {
+            return None;
+        }
+
+        let area = unsafe { &*(self.address as *const MemoryArea) };
+        self.address += self.entry_size;
+
+        Some(area)
+    }
+}
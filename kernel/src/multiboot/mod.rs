@@ -0,0 +1,260 @@
+//! # Multiboot 2
+//!
+//! Parses the boot information structure which a multiboot 2 bootloader passes to the kernel.
+//! The structure is a list of tags, each describing something about the machine or how the kernel
+//! was loaded. `boot.asm` passes its (physical, identity mapped) address to `kmain`.
+//!
+//! The information must be loaded through `init` before it can be accessed through `info`.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! let boot_info = unsafe { multiboot::init(multiboot_info_address)? };
+//!
+//! if let Some(memory_map) = boot_info.memory_map() {
+//!     for area in memory_map.available_areas() {
+//!         println!("{:#x} - {:#x}", area.start_address(), area.end_address());
+//!     }
+//! }
+//! ```
+
+pub mod memory_map;
+pub mod elf_sections;
+pub mod module;
+pub mod framebuffer;
+
+use core::{mem, slice, str};
+use spin::Once;
+
+pub use self::elf_sections::{ElfSection, ElfSectionsTag};
+pub use self::framebuffer::FramebufferTag;
+pub use self::memory_map::{MemoryArea, MemoryAreaType, MemoryMapTag};
+pub use self::module::ModuleTag;
+
+static BOOT_INFO: Once<BootInformation> = Once::new();
+
+#[allow(dead_code)] // Dead constants for completeness
+mod tag_types {
+    pub const END: u32 = 0;
+    pub const COMMAND_LINE: u32 = 1;
+    pub const BOOTLOADER_NAME: u32 = 2;
+    pub const MODULE: u32 = 3;
+    pub const BASIC_MEMORY_INFO: u32 = 4;
+    pub const BOOT_DEVICE: u32 = 5;
+    pub const MEMORY_MAP: u32 = 6;
+    pub const FRAMEBUFFER: u32 = 8;
+    pub const ELF_SECTIONS: u32 = 9;
+    pub const APM: u32 = 10;
+    pub const ACPI_OLD_RSDP: u32 = 14;
+    pub const ACPI_NEW_RSDP: u32 = 15;
+}
+
+/// An error which occurred while loading the boot information
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MultibootError {
+    /// The boot information address was null or misaligned
+    InvalidAddress(usize),
+    /// The total size of the boot information is too small to hold the end tag
+    InvalidSize(u32),
+    /// The tag list was not terminated properly by an end tag
+    MissingEndTag,
+}
+
+/// The header common to all tags
+#[derive(Debug)]
+#[repr(C)]
+pub struct Tag {
+    pub typ: u32,
+    pub size: u32,
+}
+
+impl Tag {
+    /// Gets the bytes of this tag following its header
+    fn body(&self) -> &'static [u8] {
+        let start = self as *const Tag as usize + mem::size_of::<Tag>();
+        unsafe { slice::from_raw_parts(start as *const u8, self.size as usize - mem::size_of::<Tag>()) }
+    }
+
+    /// Interprets the body of this tag as a null terminated string
+    fn string(&self) -> Option<&'static str> {
+        let body = self.body();
+        let length = body.iter().position(|&byte| byte == 0).unwrap_or(body.len());
+
+        str::from_utf8(&body[..length]).ok()
+    }
+
+    /// Reinterprets this tag as a specific tag type
+    ///
+    /// # Safety
+    ///
+    /// The tag must actually be a `T`, and at least as large as it
+    unsafe fn cast<T>(&self) -> &'static T {
+        &*(self as *const Tag as *const T)
+    }
+}
+
+/// The boot information structure passed by the bootloader
+pub struct BootInformation {
+    address: usize,
+    total_size: u32,
+}
+
+impl BootInformation {
+    /// The physical address of the boot information structure
+    pub fn start_address(&self) -> usize {
+        self.address
+    }
+
+    /// The physical address of the end of the boot information structure (exclusive)
+    pub fn end_address(&self) -> usize {
+        self.address + self.total_size as usize
+    }
+
+    /// Gets an iterator over all tags in the boot information
+    pub fn tags(&self) -> Tags {
+        Tags {
+            address: self.address + 8,
+            end: self.end_address(),
+        }
+    }
+
+    /// Finds the first tag of the given type
+    fn find(&self, typ: u32) -> Option<&'static Tag> {
+        self.tags().find(|tag| tag.typ == typ)
+    }
+
+    /// Finds the first tag of the given type, as long as it is large enough to be a `T`
+    ///
+    /// # Safety
+    ///
+    /// Tags of the given type must be `T`s
+    unsafe fn find_as<T>(&self, typ: u32) -> Option<&'static T> {
+        match self.find(typ) {
+            Some(tag) if tag.size as usize >= mem::size_of::<T>() => Some(tag.cast()),
+            _ => None,
+        }
+    }
+
+    /// Gets the memory map provided by the bootloader
+    pub fn memory_map(&self) -> Option<&'static MemoryMapTag> {
+        unsafe { self.find_as(tag_types::MEMORY_MAP) }
+    }
+
+    /// Gets the command line the kernel was booted with
+    pub fn command_line(&self) -> Option<&'static str> {
+        self.find(tag_types::COMMAND_LINE).and_then(|tag| tag.string())
+    }
+
+    /// Gets the name of the bootloader which booted the kernel
+    pub fn bootloader_name(&self) -> Option<&'static str> {
+        self.find(tag_types::BOOTLOADER_NAME).and_then(|tag| tag.string())
+    }
+
+    /// Gets an iterator over the modules loaded alongside the kernel
+    pub fn modules(&self) -> Modules {
+        Modules { tags: self.tags() }
+    }
+
+    /// Gets the ELF section headers of the kernel image
+    pub fn elf_sections(&self) -> Option<&'static ElfSectionsTag> {
+        unsafe { self.find_as(tag_types::ELF_SECTIONS) }
+    }
+
+    /// Gets information about the framebuffer set up by the bootloader
+    pub fn framebuffer(&self) -> Option<&'static FramebufferTag> {
+        unsafe { self.find_as(tag_types::FRAMEBUFFER) }
+    }
+
+    /// Gets the address of the bootloader's copy of the ACPI 1.0 RSDP
+    pub fn rsdp_v1(&self) -> Option<usize> {
+        self.find(tag_types::ACPI_OLD_RSDP).map(|tag| tag.body().as_ptr() as usize)
+    }
+
+    /// Gets the address of the bootloader's copy of the ACPI 2.0+ RSDP
+    pub fn rsdp_v2(&self) -> Option<usize> {
+        self.find(tag_types::ACPI_NEW_RSDP).map(|tag| tag.body().as_ptr() as usize)
+    }
+}
+
+/// Iterates over the tags in the boot information, stopping at the end tag or at the first tag
+/// which doesn't fit inside the boot information
+pub struct Tags {
+    address: usize,
+    end: usize,
+}
+
+impl Iterator for Tags {
+    type Item = &'static Tag;
+
+    fn next(&mut self) -> Option<&'static Tag> {
+        if self.address + mem::size_of::<Tag>() > self.end {
+            return None;
+        }
+
+        let tag = unsafe { &*(self.address as *const Tag) };
+
+        if tag.typ == tag_types::END
+            || (tag.size as usize) < mem::size_of::<Tag>()
+            || self.address + tag.size as usize > self.end
+        {
+            return None;
+        }
+
+        // Tags are padded to be 8 byte aligned
+        self.address += ((tag.size + 7) & !7) as usize;
+
+        Some(tag)
+    }
+}
+
+/// Iterates over the module tags in the boot information
+pub struct Modules {
+    tags: Tags,
+}
+
+impl Iterator for Modules {
+    type Item = &'static ModuleTag;
+
+    fn next(&mut self) -> Option<&'static ModuleTag> {
+        self.tags
+            .find(|tag| tag.typ == tag_types::MODULE && tag.size as usize >= mem::size_of::<ModuleTag>())
+            .map(|tag| unsafe { tag.cast() })
+    }
+}
+
+/// Loads the boot information at the given address
+///
+/// # Safety
+///
+/// The address must be the (identity mapped) address passed by a multiboot 2 bootloader
+pub unsafe fn init(address: usize) -> Result<&'static BootInformation, MultibootError> {
+    if address == 0 || address % 8 != 0 {
+        return Err(MultibootError::InvalidAddress(address));
+    }
+
+    let total_size = *(address as *const u32);
+
+    // The fixed part and the end tag take up 16 bytes
+    if total_size < 16 {
+        return Err(MultibootError::InvalidSize(total_size));
+    }
+
+    let boot_info = BootInformation { address, total_size };
+
+    // Check that the tags can be walked all the way to the end tag
+    let last = boot_info.tags().last()
+        .map(|tag| tag as *const Tag as usize + ((tag.size + 7) & !7) as usize)
+        .unwrap_or(address + 8);
+
+    if last + mem::size_of::<Tag>() > boot_info.end_address() || (*(last as *const Tag)).typ != tag_types::END {
+        return Err(MultibootError::MissingEndTag);
+    }
+
+    Ok(BOOT_INFO.call_once(|| boot_info))
+}
+
+/// Gets the boot information, if it has been loaded
+#[allow(dead_code)] // Part of API
+pub fn info() -> Option<&'static BootInformation> {
+    BOOT_INFO.try()
+}
@@ -0,0 +1,35 @@
+//! The module tag, describing a boot module loaded alongside the kernel
+
+use core::{mem, slice, str};
+
+/// A module tag, followed by the module's null terminated command line
+#[allow(dead_code)] // Dead fields for completeness
+#[repr(C)]
+pub struct ModuleTag {
+    typ: u32,
+    size: u32,
+    module_start: u32,
+    module_end: u32,
+}
+
+impl ModuleTag {
+    /// The physical address at which the module starts
+    pub fn start_address(&self) -> u32 {
+        self.module_start
+    }
+
+    /// The physical address at which the module ends (exclusive)
+    pub fn end_address(&self) -> u32 {
+        self.module_end
+    }
+
+    /// The command line the module was loaded with, usually its name
+    pub fn command_line(&self) -> Option<&'static str> {
+        let start = self as *const ModuleTag as usize + mem::size_of::<ModuleTag>();
+        let length = self.size as usize - mem::size_of::<ModuleTag>();
+        let bytes = unsafe { slice::from_raw_parts(start as *const u8, length) };
+
+        let length = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+        str::from_utf8(&bytes[..length]).ok()
+    }
+}
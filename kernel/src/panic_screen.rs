@@ -0,0 +1,157 @@
+//! # Panic Screen
+//!
+//! Shows everything known about a panic on a cleared screen: the message and location, the
+//! registers if an exception caused it, a backtrace and as many of the last log lines as still fit.
+//! The same report is mirrored to COM1 if it was initialized, with the full backtrace and a longer
+//! log tail.
+//!
+//! The panic may have happened anywhere, including while `terminal::STDOUT`, `vga::WRITER` or a
+//! serial port was locked, so nothing here waits on a lock. The screen and the serial port are
+//! written through fresh handles, and the log buffer is forcibly unlocked.
+
+use backtrace::Backtrace;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use drivers::serial::{self, ComPort};
+use drivers::vga::VgaWriter;
+use interrupts;
+use interrupts::context::ExceptionContext;
+use log::dmesg;
+use spin::RwLock;
+use terminal::{Stdout, TerminalOutput};
+
+/// The amount of frames shown on screen, so that some room is left for the log
+const SCREEN_FRAMES: usize = 6;
+/// The amount of log lines mirrored to serial
+const SERIAL_LOG_LINES: usize = 32;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// The address of the context of the exception which is being reported, or 0 if there is none
+static EXCEPTION_CONTEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes the next panic report the given exception's registers, and start its backtrace at the
+/// faulting code. The context must stay on the stack until then, which it does as fatal exceptions
+/// never return.
+pub fn set_exception_context(context: &ExceptionContext) {
+    EXCEPTION_CONTEXT.store(context as *const _ as usize, Ordering::SeqCst);
+}
+
+/// Shows the panic screen and halts
+pub fn show(info: &PanicInfo) -> ! {
+    interrupts::disable();
+
+    // A panic while showing the panic screen would only show it again, forever
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        ::halt();
+    }
+
+    // Interrupts are off and every other path has stopped, so nothing can be holding it legitimately
+    unsafe { dmesg::force_unlock(); }
+
+    let context = match EXCEPTION_CONTEXT.load(Ordering::SeqCst) {
+        0 => None,
+        address => Some(unsafe { &*(address as *const ExceptionContext) }),
+    };
+
+    let backtrace = match context {
+        Some(context) => Backtrace::from_registers(context.frame.rip as usize, context.registers.rbp as usize),
+        None => Backtrace::current(),
+    };
+
+    // Ignore the errors because we can't afford to panic in the panic handler
+
+    let vga_writer = RwLock::new(VgaWriter::new());
+    let mut screen = Stdout(&vga_writer);
+    let _ = screen.clear();
+    let _ = report(&mut screen, info, context, backtrace.max_frames(SCREEN_FRAMES), true);
+
+    if let Some(mut com1) = unsafe { serial::steal(ComPort::Com1) } {
+        let _ = write!(com1, "\n");
+        let _ = report(&mut com1, info, context, backtrace, false);
+        let _ = write!(com1, "\n");
+    }
+
+    ::halt()
+}
+
+/// Writes the report. On a screen, the log tail fills the rows left and each line is cut to fit
+/// on one row, so that nothing scrolls off.
+fn report<T>(out: &mut T, info: &PanicInfo, context: Option<&ExceptionContext>, backtrace: Backtrace, screen: bool)
+    -> fmt::Result
+    where T: TerminalOutput<()> + Write
+{
+    let width = out.resolution().x;
+
+    out.set_color(color!(White on Red)).map_err(|_| fmt::Error)?;
+    write!(out, "{:^width$}", "KERNEL PANIC", width = width - 1)?;
+    out.set_color(color!(LightRed on Black)).map_err(|_| fmt::Error)?;
+
+    write!(out, "\nPanicked at \"")?;
+    if let Some(message) = info.message() {
+        out.write_fmt(*message)?;
+    }
+
+    match info.location() {
+        Some(location) => write!(out, "\", {}:{}\n", location.file(), location.line())?,
+        None => write!(out, "\"\n")?,
+    }
+
+    out.set_color(color!(White on Black)).map_err(|_| fmt::Error)?;
+
+    if let Some(context) = context {
+        write!(out, "\n{}\n", context)?;
+    }
+
+    write!(out, "\n{}\n", backtrace)?;
+
+    out.set_color(color!(LightGray on Black)).map_err(|_| fmt::Error)?;
+
+    // The rows below the cursor are left for the log lines, which each start on a new row
+    let (lines, line_width) = if screen {
+        (out.cursor_pos().y.saturating_sub(1), width - 1)
+    } else {
+        (SERIAL_LOG_LINES, usize::max_value())
+    };
+
+    if lines == 0 {
+        return Ok(());
+    }
+
+    write!(out, "\nlog:")?;
+
+    let mut result = Ok(());
+    let _ = dmesg::for_each_last(lines, |entry| {
+        if result.is_ok() {
+            result = write!(Truncate { out: &mut *out, remaining: line_width }, "\n{}", entry);
+        }
+    });
+
+    result
+}
+
+/// Drops whatever is written past the given amount of characters
+struct Truncate<'a, W: Write + 'a> {
+    out: &'a mut W,
+    remaining: usize,
+}
+
+impl<'a, W: Write> Write for Truncate<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for character in s.chars() {
+            // The newline starting the line doesn't take up a column
+            if character != '\n' {
+                if self.remaining == 0 {
+                    break;
+                }
+
+                self.remaining -= 1;
+            }
+
+            self.out.write_char(character)?;
+        }
+
+        Ok(())
+    }
+}
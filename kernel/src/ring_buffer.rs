@@ -0,0 +1,172 @@
+//! A lock-free, single-producer single-consumer ring buffer
+//!
+//! The [RingBuffer] is designed to be pushed to from an interrupt handler and popped from normal
+//! kernel code without either side needing to take a lock.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A fixed-size array which can be used as backing storage for a [RingBuffer]. This is
+/// implemented for power-of-two sized arrays as a workaround until const-generics arrives
+pub unsafe trait Array {
+    type Item;
+
+    fn as_slice(&self) -> &[Self::Item];
+
+    fn as_mut_slice(&mut self) -> &mut [Self::Item];
+}
+
+macro_rules! impl_array {
+    ($($size:expr),+) => {
+        $(
+            unsafe impl<T> Array for [T; $size] {
+                type Item = T;
+
+                fn as_slice(&self) -> &[T] {
+                    self
+                }
+
+                fn as_mut_slice(&mut self) -> &mut [T] {
+                    self
+                }
+            }
+        )+
+    };
+}
+
+impl_array!(8, 16, 32, 64, 128, 256, 512, 1024);
+
+/// A lock-free ring buffer holding up to `A`'s length of items.
+///
+/// # Note
+///
+/// Only one context may push and only one context may pop at a time, otherwise items may be lost
+/// or duplicated.
+pub struct RingBuffer<T: Copy, A: Array<Item = Option<T>>> {
+    buffer: UnsafeCell<A>,
+    /// The total amount of items ever pushed (wrapping)
+    head: AtomicUsize,
+    /// The total amount of items ever popped (wrapping)
+    tail: AtomicUsize,
+    phantom: PhantomData<T>,
+}
+
+unsafe impl<T: Copy + Send, A: Array<Item = Option<T>>> Sync for RingBuffer<T, A> {}
+
+impl<T: Copy, A: Array<Item = Option<T>>> RingBuffer<T, A> {
+    /// Creates a new ring buffer with the given (empty) backing array
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// static QUEUE: RingBuffer<u8, [Option<u8>; 16]> = RingBuffer::new([None; 16]);
+    /// ```
+    pub const fn new(buffer: A) -> Self {
+        RingBuffer {
+            buffer: UnsafeCell::new(buffer),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            phantom: PhantomData,
+        }
+    }
+
+    /// The maximum amount of items this buffer can hold
+    pub fn capacity(&self) -> usize {
+        unsafe { (*self.buffer.get()).as_slice().len() }
+    }
+
+    /// The amount of items currently queued
+    pub fn len(&self) -> usize {
+        self.head.load(Ordering::Acquire).wrapping_sub(self.tail.load(Ordering::Acquire))
+    }
+
+    /// Returns `true` if there are no items queued
+    #[allow(dead_code)] // Part of API
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pushes an item onto the back of this buffer, or returns it back if the buffer is full
+    pub fn push(&self, item: T) -> Result<(), T> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if head.wrapping_sub(tail) >= self.capacity() {
+            return Err(item);
+        }
+
+        unsafe {
+            let slots = (*self.buffer.get()).as_mut_slice();
+            let index = head % slots.len();
+            slots[index] = Some(item);
+        }
+
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Pops an item from the front of this buffer, or returns `None` if it is empty
+    pub fn pop(&self) -> Option<T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        if head == tail {
+            return None;
+        }
+
+        let item = unsafe {
+            let slots = (*self.buffer.get()).as_mut_slice();
+            let index = tail % slots.len();
+            slots[index].take()
+        };
+
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+
+    kernel_test! {
+        fn pops_in_push_order() {
+            let buffer: RingBuffer<u8, [Option<u8>; 8]> = RingBuffer::new([None; 8]);
+
+            for item in 0..5 {
+                assert_eq!(buffer.push(item), Ok(()));
+            }
+
+            for item in 0..5 {
+                assert_eq!(buffer.pop(), Some(item));
+            }
+
+            assert_eq!(buffer.pop(), None);
+        }
+
+        fn rejects_push_when_full() {
+            let buffer: RingBuffer<u8, [Option<u8>; 8]> = RingBuffer::new([None; 8]);
+
+            for item in 0..8 {
+                assert_eq!(buffer.push(item), Ok(()));
+            }
+
+            assert_eq!(buffer.push(8), Err(8));
+            assert_eq!(buffer.len(), 8);
+        }
+
+        fn wraps_around() {
+            let buffer: RingBuffer<u8, [Option<u8>; 8]> = RingBuffer::new([None; 8]);
+
+            for item in 0..20 {
+                assert_eq!(buffer.push(item), Ok(()));
+                assert_eq!(buffer.pop(), Some(item));
+            }
+
+            assert!(buffer.is_empty());
+        }
+    }
+}
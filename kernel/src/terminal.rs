@@ -10,7 +10,8 @@
 //!
 //! The terminal driver also has an `STDOUT`, which is the standard output for terminals,
 //! generally writing to VGA. This can be invoked through the `print!` and `println!` macros,
-//! or directly referencing it through `drivers::terminal::STDOUT`
+//! or directly referencing it through `drivers::terminal::STDOUT`. Its output can be swapped out,
+//! for instance for a serial port on headless machines.
 
 use color::{Color, ColorPair};
 use core::fmt::{self, Debug, Write};
@@ -40,10 +41,10 @@ pub fn stdout_print(args: fmt::Arguments) {
 /// A standard output terminal
 pub static STDOUT: RwLock<Stdout> = RwLock::new(Stdout(&vga::WRITER));
 
-/// The standard output. You should not assume that the `Other` variant will
-/// always carry a `()`.
-// Crate public writer for `panic_fmt` to construct
-pub struct Stdout<'a>(pub(crate) &'a RwLock<vga::VgaWriter>);
+/// The standard output, writing to any [TerminalOutput] such as VGA or a serial port. You should
+/// not assume that the `Other` variant will always carry a `()`.
+// Crate public writer for the panic handler to construct
+pub struct Stdout<'a>(pub(crate) &'a RwLock<TerminalOutput<()> + Send + Sync>);
 
 impl<'a> TerminalOutput<()> for Stdout<'a> {
     fn color_supported(&self, color: Color) -> bool {
@@ -98,7 +99,7 @@ impl<'a> Write for Stdout<'a> {
 }
 
 /// A general [TerminalOutput] error
-#[derive(Debug)]
+#[derive(Eq, PartialEq, Debug)]
 #[allow(dead_code)] // Dead variants for completeness
 pub enum TerminalOutputError<E: Debug> {
     /// Backspacing is not supported by the terminal
@@ -114,7 +115,7 @@ pub enum TerminalOutputError<E: Debug> {
     Other(E),
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 #[allow(dead_code)] // Dead variants for completeness
 pub enum BackspaceUnavailableCause {
     Disabled,
@@ -268,16 +269,19 @@ pub trait TerminalOutput<E: Debug> {
 
     /// Backspaces one character
     fn backspace(&mut self) -> Result<(), TerminalOutputError<E>> {
-        if self.cursor_pos() == Point::new(0, 0) {
+        let top = self.resolution().y - 1;
+
+        if self.cursor_pos() == Point::new(0, top) {
             return Err(TerminalOutputError::BackspaceUnavailable(
                 BackspaceUnavailableCause::TopOfTerminal)
             );
         }
 
+        // The origin is at the bottom left, so the previous line is the one above
         if self.cursor_pos().x == 0 {
             self.set_cursor_pos(Point {
                 x: self.resolution().x - 1,
-                y: self.cursor_pos().y - 1,
+                y: self.cursor_pos().y + 1,
             })?;
         } else {
             self.set_cursor_pos(Point {
@@ -0,0 +1,88 @@
+//! # Kernel Tests
+//!
+//! Tests are declared with [kernel_test], and run in two ways:
+//!  - On the host with `cargo test`, where they are plain `#[test]`s. Only tests of pure logic,
+//!    such as those drawing into a [MemoryBuffer] or driving a device faked by a [MockIo], can run
+//!    there.
+//!  - In QEMU with `make test`, which collects them into a test kernel that runs them from `kmain`
+//!    once the kernel is initialized. Results are reported over COM1, and QEMU is exited through
+//!    its `isa-debug-exit` device with a code saying whether every test passed.
+//!
+//! In the test kernel a panic can't be unwound, so when a test panics the panic handler reports it,
+//! resets the stack and interrupt state to the runner's, and carries on with the next test. A test
+//! which panics while holding a lock leaves it locked for the tests after it.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! #[cfg(test)]
+//! mod tests {
+//!     kernel_test! {
+//!         fn addition() {
+//!             assert_eq!(1 + 1, 2);
+//!         }
+//!
+//!         #[should_panic]
+//!         fn overflow() {
+//!             let _ = [0u8; 4][4];
+//!         }
+//!     }
+//! }
+//! ```
+
+#[cfg(target_os = "flower")]
+mod runner;
+
+#[cfg(target_os = "flower")]
+pub use self::runner::{panicked, runner, Test};
+
+/// Declares kernel tests, which are functions taking no arguments. Tests marked `#[should_panic]`
+/// pass only if they panic.
+macro_rules! kernel_test {
+    () => ();
+    (#[should_panic] fn $name:ident() $body:block $($rest:tt)*) => {
+        kernel_test!(@test $name, true, $body);
+        kernel_test!($($rest)*);
+    };
+    (fn $name:ident() $body:block $($rest:tt)*) => {
+        kernel_test!(@test $name, false, $body);
+        kernel_test!($($rest)*);
+    };
+    (@test $name:ident, true, $body:block) => {
+        #[cfg(not(target_os = "flower"))]
+        #[test]
+        #[should_panic]
+        fn $name() $body
+
+        kernel_test!(@test_case $name, true, $body);
+    };
+    (@test $name:ident, false, $body:block) => {
+        #[cfg(not(target_os = "flower"))]
+        #[test]
+        fn $name() $body
+
+        kernel_test!(@test_case $name, false, $body);
+    };
+    (@test_case $name:ident, $should_panic:expr, $body:block) => {
+        #[cfg(target_os = "flower")]
+        #[test_case]
+        #[allow(non_upper_case_globals)]
+        const $name: $crate::testing::Test = $crate::testing::Test {
+            name: concat!(module_path!(), "::", stringify!($name)),
+            run: { fn $name() $body $name },
+            should_panic: $should_panic,
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    kernel_test! {
+        fn passing_test_passes() {}
+
+        #[should_panic]
+        fn panicking_test_is_caught() {
+            panic!("expected panic");
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! The runner of the test kernel, which reports over COM1 and exits QEMU
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use drivers::serial::{self, ComPort, RawWriter};
+use interrupts;
+use io::{mock, Port};
+
+/// The port of QEMU's `isa-debug-exit` device, as set in the Makefile
+const EXIT_PORT: u16 = 0xf4;
+
+/// Set as the current test while none is running
+const NO_TEST: usize = usize::max_value();
+
+/// A test, as collected by the test harness
+pub struct Test {
+    pub name: &'static str,
+    pub run: fn(),
+    pub should_panic: bool,
+}
+
+/// The code QEMU is exited with, which it turns into the exit status `(code << 1) | 1` so that
+/// it can't be mistaken for QEMU's own statuses
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u32)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// The tests being run, kept so that the panic handler can carry on with them
+static TESTS: AtomicUsize = AtomicUsize::new(0);
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+static CURRENT: AtomicUsize = AtomicUsize::new(NO_TEST);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+
+/// The stack pointer and interrupt state of the runner, which are restored before carrying on with
+/// the tests after one panicked. The panic may have happened on another stack, such as the double
+/// fault stack, or in an interrupt handler with interrupts disabled.
+static RUNNER_STACK: AtomicUsize = AtomicUsize::new(0);
+static RUNNER_INTERRUPTS: AtomicBool = AtomicBool::new(false);
+
+/// Runs every test and exits QEMU. Called by the `test_main` the test harness generates.
+pub fn runner(tests: &[&Test]) -> ! {
+    // The slice stays alive until QEMU is exited, as `test_main` never returns
+    TESTS.store(tests.as_ptr() as usize, Ordering::SeqCst);
+    COUNT.store(tests.len(), Ordering::SeqCst);
+
+    let rsp: usize;
+    unsafe { asm!("mov %rsp, $0" : "=r"(rsp)); }
+    RUNNER_STACK.store(rsp, Ordering::SeqCst);
+    RUNNER_INTERRUPTS.store(interrupts::enabled(), Ordering::SeqCst);
+
+    report(format_args!("\nrunning {} tests\n", tests.len()));
+    run_from(0)
+}
+
+/// Reports a panic. A panicking test has finished, so the tests after it are run from here.
+pub fn panicked(info: &PanicInfo) -> ! {
+    // A test which panicked while running a mock would leave its ports claimed, COM1's included
+    unsafe { mock::reset(); }
+
+    let current = CURRENT.load(Ordering::SeqCst);
+
+    if current == NO_TEST {
+        report(format_args!("\npanicked outside of a test: {}\n", Panic(info)));
+        exit(ExitCode::Failed);
+    }
+
+    let test = tests()[current];
+    if !test.should_panic {
+        report(format_args!("FAILED\n    {}\n", Panic(info)));
+    }
+
+    finish(test.should_panic);
+    unsafe { resume_from(current + 1) }
+}
+
+/// Exits QEMU with the given code. Halts if the kernel isn't running in QEMU.
+pub fn exit(code: ExitCode) -> ! {
+    unsafe { Port::<u32>::new(EXIT_PORT).write(code as u32); }
+    ::halt()
+}
+
+fn tests() -> &'static [&'static Test] {
+    unsafe {
+        slice::from_raw_parts(
+            TESTS.load(Ordering::SeqCst) as *const &Test,
+            COUNT.load(Ordering::SeqCst),
+        )
+    }
+}
+
+/// Carries on with the tests from the given one, back on the runner's stack and with its interrupt
+/// state. The panicked test's frames, and whichever handler it panicked in, are abandoned.
+///
+/// # Safety
+///
+/// Must only be called once the runner's stack pointer was recorded, from below it on that stack or
+/// from another stack
+unsafe fn resume_from(first: usize) -> ! {
+    interrupts::disable();
+
+    // The stack is aligned for the call, and the frame pointer cleared to end backtraces there
+    let stack = RUNNER_STACK.load(Ordering::SeqCst) & !0xF;
+    asm!("mov $0, %rsp
+          xor %rbp, %rbp
+          call *$1"
+         :: "r"(stack), "r"(resume as extern "C" fn(usize) -> !), "{rdi}"(first)
+         :: "volatile");
+
+    unreachable!()
+}
+
+extern "C" fn resume(first: usize) -> ! {
+    if RUNNER_INTERRUPTS.load(Ordering::SeqCst) {
+        interrupts::enable();
+    }
+
+    run_from(first)
+}
+
+fn run_from(first: usize) -> ! {
+    let tests = tests();
+
+    for (index, test) in tests.iter().enumerate().skip(first) {
+        CURRENT.store(index, Ordering::SeqCst);
+        report(format_args!("test {} ... ", test.name));
+
+        (test.run)();
+
+        if test.should_panic {
+            report(format_args!("FAILED\n    did not panic\n"));
+        }
+
+        finish(!test.should_panic);
+    }
+
+    CURRENT.store(NO_TEST, Ordering::SeqCst);
+
+    let failed = FAILED.load(Ordering::SeqCst);
+    let result = if failed == 0 { "ok" } else { "FAILED" };
+    report(format_args!(
+        "\ntest result: {}. {} passed; {} failed\n",
+        result,
+        tests.len() - failed,
+        failed,
+    ));
+
+    exit(if failed == 0 { ExitCode::Success } else { ExitCode::Failed })
+}
+
+fn finish(passed: bool) {
+    if passed {
+        report(format_args!("ok\n"));
+    } else {
+        FAILED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Writes to COM1 as is, without the escapes of its terminal output. A test which panicked may have
+/// left COM1 locked, in which case it is written to through a second handle.
+fn report(args: fmt::Arguments) {
+    let _ = match serial::COM1.try_write() {
+        Some(mut com1) => RawWriter(&mut com1).write_fmt(args),
+        None => match unsafe { serial::steal(ComPort::Com1) } {
+            Some(mut com1) => RawWriter(&mut com1).write_fmt(args),
+            None => Ok(()),
+        },
+    };
+}
+
+/// Formats a panic as `panicked at 'message', file:line`
+struct Panic<'a>(&'a PanicInfo<'a>);
+
+impl<'a> fmt::Display for Panic<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "panicked at '")?;
+
+        if let Some(message) = self.0.message() {
+            f.write_fmt(*message)?;
+        }
+
+        match self.0.location() {
+            Some(location) => write!(f, "', {}:{}", location.file(), location.line()),
+            None => write!(f, "'"),
+        }
+    }
+}
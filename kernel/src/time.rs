@@ -0,0 +1,89 @@
+//! # Time
+//!
+//! Keeps the time since boot by counting ticks of the PIT (programmable interval timer), which is
+//! set to fire [TICK_RATE] times a second. Until `init` is called, time stands still at zero.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use interrupts::irq::{self, IrqError};
+use io::Port;
+
+/// The amount of ticks per second
+pub const TICK_RATE: u32 = 1000;
+
+/// The amount of device status polls taking about a millisecond, as an ISA port read takes about a
+/// microsecond
+const POLLS_PER_MS: u64 = 1000;
+
+/// The frequency of the PIT's oscillator, which is divided down to the tick rate
+const PIT_FREQUENCY: u32 = 1193182;
+
+/// Channel 0, which is connected to IRQ 0
+const CHANNEL_0_PORT: u16 = 0x40;
+const COMMAND_PORT: u16 = 0x43;
+
+/// Selects channel 0, sets the reload value low byte first, and uses the rate generator mode
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b00_11_010_0;
+
+/// The amount of ticks since `init`
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Programs the PIT to the tick rate and starts counting ticks
+pub fn init() -> Result<(), IrqError> {
+    let divisor = (PIT_FREQUENCY / TICK_RATE) as u16;
+
+    unsafe {
+        Port::<u8>::new(COMMAND_PORT).write(CHANNEL_0_RATE_GENERATOR);
+
+        let mut channel_0 = Port::<u8>::new(CHANNEL_0_PORT);
+        channel_0.write(divisor as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+
+    irq::register(irq::lines::TIMER, tick)
+}
+
+/// The amount of ticks since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed) as u64
+}
+
+/// The time since boot, with the precision of one tick
+pub fn uptime() -> Duration {
+    let ticks = ticks();
+    let rate = TICK_RATE as u64;
+
+    Duration::new(ticks / rate, ((ticks % rate) * 1_000_000_000 / rate) as u32)
+}
+
+/// A point in time after which waiting on a device is given up. It is measured in ticks, but as
+/// those stand still before the PIT is set up and while interrupts are disabled, the amount of
+/// polls is also bounded to about the same duration.
+pub struct Deadline {
+    end: u64,
+    polls_left: u64,
+}
+
+impl Deadline {
+    /// Creates a deadline the given amount of milliseconds from now
+    pub fn after(ms: u64) -> Self {
+        Deadline {
+            end: ticks() + ms * TICK_RATE as u64 / 1000,
+            polls_left: ms * POLLS_PER_MS,
+        }
+    }
+
+    /// Returns `true` once this deadline has passed. Must be called once per poll.
+    pub fn passed(&mut self) -> bool {
+        if self.polls_left == 0 || ticks() > self.end {
+            return true;
+        }
+
+        self.polls_left -= 1;
+        false
+    }
+}
+
+fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}